@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use up2p::core::{bincodec::BinCodec, BaseUp2pProtocol};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(package) = BaseUp2pProtocol::decode_from(data) {
+        // a frame has exactly one encoding
+        assert_eq!(package.encode_to_vec().unwrap(), data);
+    }
+});
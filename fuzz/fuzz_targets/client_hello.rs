@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use up2p::core::{bincodec::BinCodec, uprotocol_pkg::ClientHelloPkg};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(package) = ClientHelloPkg::decode_from(data) {
+        // whatever decodes has to survive its own encoding
+        ClientHelloPkg::decode_from(&package.encode_to_vec().unwrap()).unwrap();
+    }
+});
@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use up2p::core::{bincodec::BinCodec, uprotocol_pkg::DeniedPkg};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(package) = DeniedPkg::decode_from(data) {
+        // whatever decodes has to survive its own encoding
+        DeniedPkg::decode_from(&package.encode_to_vec().unwrap()).unwrap();
+    }
+});
@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use up2p::core::{bincodec::BinCodec, wire, Up2pMessage};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(message) = Up2pMessage::decode_from(data) {
+        // whatever decodes has to survive its own encoding, in the codec it came in
+        let codec = wire::peek_codec(data).unwrap();
+        Up2pMessage::decode_from(&message.encode_with(codec).unwrap()).unwrap();
+    }
+});
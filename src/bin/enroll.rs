@@ -0,0 +1,47 @@
+use std::{io::Read, time::Duration};
+
+use up2p::server::enrollment::EnrollmentIssuer;
+
+const USAGE: &str = "\
+usage:
+  enroll keygen
+      print a new enrollment key and the verifying_key for the server's [enrollment]
+  enroll issue <key_file> <client_class> <client_instance> <valid_for_days>
+      print a token the device uses as its identity, reading the key from <key_file> or from stdin when it is -
+";
+
+fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["keygen"] => {
+            let issuer = EnrollmentIssuer::generate();
+            println!("key: {}", issuer.to_hex());
+            println!("verifying_key: {}", issuer.verifying_key_hex());
+        }
+        ["issue", key_file, client_class, client_instance, valid_for_days] => {
+            let valid_for_days: u64 = valid_for_days.parse()
+                .map_err(|_| anyhow::anyhow!("valid_for_days must be a number of days"))?;
+            let valid_for_secs = valid_for_days.checked_mul(24 * 60 * 60)
+                .ok_or_else(|| anyhow::anyhow!("valid_for_days is too large"))?;
+            let issuer = EnrollmentIssuer::from_hex(read_key(key_file)?.trim())?;
+            println!("{}", issuer.issue(client_class, client_instance, Duration::from_secs(valid_for_secs))?);
+        }
+        _ => {
+            eprint!("{}", USAGE);
+            std::process::exit(2);
+        }
+    }
+    Ok(())
+}
+
+// the key stays out of argv, where other users could read it from the process list
+fn read_key(key_file: &str) -> anyhow::Result<String> {
+    let mut key = String::new();
+    if key_file == "-" {
+        std::io::stdin().read_to_string(&mut key)?;
+    } else {
+        key = std::fs::read_to_string(key_file)
+            .map_err(|e| anyhow::anyhow!("Failed to read the key from {}, {}", key_file, e))?;
+    }
+    Ok(key)
+}
@@ -0,0 +1,329 @@
+use std::{net::SocketAddr, path::PathBuf, str::FromStr};
+
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
+use tracing::Level;
+
+use up2p::{core::{codec::Codec, server_key::ServerKey}, server::{acl::AclConfig, enrollment::EnrollmentConfig, listing::ListingConfig, rate_limit::RateLimitConfig, tenant::TenantConfig}};
+
+const DEFAULT_CONFIG_PATH: &str = "up2pd.toml";
+const ENV_PREFIX: &str = "UP2PD_";
+
+pub const USAGE: &str = "\
+usage: server [options]
+
+options:
+  -c, --config <path>            config file, default up2pd.toml (env UP2PD_CONFIG)
+      --address <address>        bind address (env UP2PD_ADDRESS)
+      --port <port>              bind port (env UP2PD_PORT)
+      --log-level <level>        trace, debug, info, warn or error (env UP2PD_LOG_LEVEL)
+      --identity <identity>      shared client identity (env UP2PD_IDENTITY)
+      --tcp-port <port>          also accept tcp clients on port (env UP2PD_TCP_PORT)
+      --websocket-port <port>    also accept websocket clients on port (env UP2PD_WEBSOCKET_PORT)
+      --metrics-address <addr>   serve prometheus metrics on addr (env UP2PD_METRICS_ADDRESS)
+      --workers <n>              number of routing workers (env UP2PD_WORKERS)
+      --reuseport-sockets <n>    number of SO_REUSEPORT sockets (env UP2PD_REUSEPORT_SOCKETS)
+      --codec <codec>            bincode or cbor, offered to clients besides bincode (env UP2PD_CODEC)
+      --print-config             print the effective config and exit
+      --generate-key             print a new server_key and its verifying key and exit
+  -h, --help                     print this help
+";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerConfig {
+    pub address: String,
+    pub port: u16,
+    pub log_level: String,
+    pub identity: String,
+    // fallback listeners on the same address for clients whose udp is blocked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tcp_port: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub websocket_port: Option<u16>,
+    // serve prometheus metrics on this address if set, e.g. "0.0.0.0:9009"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metrics_address: Option<String>,
+    // hex ed25519 secret the server signs every package with, clients pin its verifying key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_key: Option<String>,
+    // number of routing workers, defaults to the number of cpus
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workers: Option<usize>,
+    // number of SO_REUSEPORT sockets bound to the server address
+    #[serde(default = "default_reuseport_sockets")]
+    pub reuseport_sockets: usize,
+    // "cbor" lets clients that negotiate it use CBOR, bincode is always spoken
+    #[serde(default)]
+    pub codec: Codec,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    #[serde(default)]
+    pub listing: ListingConfig,
+    #[serde(default)]
+    pub acl: AclConfig,
+    // namespaces with their own identity and limits, `identity` covers the default one
+    #[serde(default)]
+    pub tenants: Vec<TenantConfig>,
+    // devices present tokens from `enroll issue` instead of `identity`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enrollment: Option<EnrollmentConfig>,
+}
+
+fn default_reuseport_sockets() -> usize {
+    1
+}
+
+// What the command line asked for, besides field overrides
+#[derive(Debug, Clone, Default)]
+pub struct CliArgs {
+    pub config_path: Option<PathBuf>,
+    pub print_config: bool,
+    pub generate_key: bool,
+    pub help: bool,
+    // (config key, raw value)
+    pub overrides: Vec<(String, String)>,
+}
+
+impl CliArgs {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> anyhow::Result<Self> {
+        let mut cli_args = CliArgs::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            // accept both `--flag value` and `--flag=value`
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) if arg.starts_with("--") => (flag.to_string(), Some(value.to_string())),
+                _ => (arg.clone(), None),
+            };
+            let mut value = || inline_value.clone().or_else(|| args.next())
+                .ok_or_else(|| anyhow!("missing value for {}", flag));
+            match flag.as_str() {
+                "-h" | "--help" => cli_args.help = true,
+                "--print-config" => cli_args.print_config = true,
+                "--generate-key" => cli_args.generate_key = true,
+                "-c" | "--config" => cli_args.config_path = Some(PathBuf::from(value()?)),
+                "--address" | "--port" | "--log-level" | "--identity" | "--tcp-port" | "--websocket-port" | "--metrics-address" | "--workers" | "--reuseport-sockets" | "--codec" => {
+                    let key = flag.trim_start_matches("--").replace('-', "_");
+                    cli_args.overrides.push((key, value()?));
+                }
+                _ => return Err(anyhow!("unknown argument: {}\n\n{}", arg, USAGE)),
+            }
+        }
+        Ok(cli_args)
+    }
+}
+
+impl ServerConfig {
+    // (path, whether it was given explicitly)
+    pub fn config_path(cli_args: &CliArgs, env: impl Fn(&str) -> Option<String>) -> (PathBuf, bool) {
+        match (&cli_args.config_path, env("UP2PD_CONFIG")) {
+            (Some(path), _) => (path.clone(), true),
+            (None, Some(path)) => (PathBuf::from(path), true),
+            (None, None) => (PathBuf::from(DEFAULT_CONFIG_PATH), false),
+        }
+    }
+
+    // file < UP2PD_* env vars < command line flags
+    pub fn load(cli_args: &CliArgs, env: impl Fn(&str) -> Option<String>) -> anyhow::Result<Self> {
+        let (config_path, explicit) = Self::config_path(cli_args, &env);
+        // the default file may be absent when everything comes from env or flags
+        let mut table = match std::fs::read_to_string(&config_path) {
+            Ok(content) => toml::from_str::<toml::Table>(&content)
+                .with_context(|| format!("failed to parse {}", config_path.display()))?,
+            Err(e) if !explicit && e.kind() == std::io::ErrorKind::NotFound => toml::Table::new(),
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", config_path.display())),
+        };
+        for key in OVERRIDABLE_KEYS {
+            let env_key = format!("{}{}", ENV_PREFIX, key.to_uppercase());
+            if let Some(raw) = env(&env_key) {
+                table.insert(key.to_string(), override_value(key, &raw).with_context(|| format!("invalid {}", env_key))?);
+            }
+        }
+        for (key, raw) in &cli_args.overrides {
+            table.insert(key.clone(), override_value(key, raw).with_context(|| format!("invalid --{}", key.replace('_', "-")))?);
+        }
+        let config: Self = toml::Table::try_into(table)
+            .with_context(|| format!("invalid config (file: {})", config_path.display()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.address.is_empty() {
+            return Err(anyhow!("invalid config: address must not be empty"));
+        }
+        if self.port == 0 || self.tcp_port == Some(0) || self.websocket_port == Some(0) {
+            return Err(anyhow!("invalid config: port, tcp_port and websocket_port must not be 0"));
+        }
+        if self.tcp_port.is_some() && self.tcp_port == self.websocket_port {
+            return Err(anyhow!("invalid config: tcp_port and websocket_port must differ"));
+        }
+        Level::from_str(&self.log_level)
+            .map_err(|_| anyhow!("invalid config: log_level {:?}, expected trace, debug, info, warn or error", self.log_level))?;
+        if self.identity.is_empty() {
+            return Err(anyhow!("invalid config: identity must not be empty"));
+        }
+        if let Some(metrics_address) = &self.metrics_address {
+            metrics_address.parse::<SocketAddr>()
+                .map_err(|e| anyhow!("invalid config: metrics_address {:?}, {}", metrics_address, e))?;
+        }
+        if let Some(server_key) = &self.server_key {
+            ServerKey::from_hex(server_key).map_err(|e| anyhow!("invalid config: server_key, {}", e))?;
+        }
+        if self.workers == Some(0) {
+            return Err(anyhow!("invalid config: workers must be at least 1"));
+        }
+        if self.reuseport_sockets == 0 {
+            return Err(anyhow!("invalid config: reuseport_sockets must be at least 1"));
+        }
+        for (name, value) in [
+            ("per_ip_rate", self.rate_limit.per_ip_rate),
+            ("per_ip_burst", self.rate_limit.per_ip_burst),
+            ("per_device_rate", self.rate_limit.per_device_rate),
+            ("per_device_burst", self.rate_limit.per_device_burst),
+        ] {
+            if !value.is_finite() || value < 0.0 {
+                return Err(anyhow!("invalid config: rate_limit.{} must be a non negative number", name));
+            }
+        }
+        for rule in &self.listing.rules {
+            if rule.requester_class.is_empty() || rule.client_class.is_empty() {
+                return Err(anyhow!("invalid config: listing rules need a requester_class and a client_class"));
+            }
+        }
+        for rule in &self.acl.rules {
+            if rule.from_class.is_empty() || rule.to_class.is_empty() {
+                return Err(anyhow!("invalid config: acl rules need a from_class and a to_class"));
+            }
+        }
+        if let Some(enrollment) = &self.enrollment {
+            enrollment.validate()
+                .map_err(|e| anyhow!("invalid config: enrollment.verifying_key, {}", e))?;
+        }
+        let mut namespaces = std::collections::HashSet::new();
+        for tenant in &self.tenants {
+            if tenant.namespace.is_empty() || tenant.namespace.contains('/') || tenant.identity.is_empty() {
+                return Err(anyhow!("invalid config: tenants need a namespace without '/' and an identity"));
+            }
+            if !namespaces.insert(&tenant.namespace) {
+                return Err(anyhow!("invalid config: tenant namespace {:?} appears twice", tenant.namespace));
+            }
+            if !tenant.rate.is_finite() || tenant.rate < 0.0 || !tenant.burst.is_finite() || tenant.burst < 0.0 {
+                return Err(anyhow!("invalid config: tenant {:?} rate and burst must be non negative numbers", tenant.namespace));
+            }
+        }
+        Ok(())
+    }
+
+    // effective config as toml, with the identities redacted
+    pub fn dump(&self) -> anyhow::Result<String> {
+        let mut redacted = self.clone();
+        redacted.identity = "<redacted>".to_string();
+        if redacted.server_key.is_some() {
+            redacted.server_key = Some("<redacted>".to_string());
+        }
+        for tenant in &mut redacted.tenants {
+            tenant.identity = "<redacted>".to_string();
+        }
+        Ok(toml::to_string(&redacted)?)
+    }
+}
+
+const OVERRIDABLE_KEYS: [&str; 10] = ["address", "port", "log_level", "identity", "tcp_port", "websocket_port", "metrics_address", "workers", "reuseport_sockets", "codec"];
+
+fn override_value(key: &str, raw: &str) -> anyhow::Result<toml::Value> {
+    match key {
+        "port" | "tcp_port" | "websocket_port" | "workers" | "reuseport_sockets" => {
+            let value = raw.parse::<i64>().map_err(|_| anyhow!("expected an integer, got {:?}", raw))?;
+            Ok(toml::Value::Integer(value))
+        }
+        _ => Ok(toml::Value::String(raw.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use up2p::core::codec::Codec;
+
+    use super::{CliArgs, ServerConfig};
+
+    fn write_config(name: &str, content: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("up2pd-test-{}-{}.toml", std::process::id(), name));
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn test_config_precedence() {
+        let path = write_config("precedence", "address = \"0.0.0.0\"\nport = 9008\nlog_level = \"warn\"\nidentity = \"bbb\"\n");
+        let cli_args = CliArgs::parse(
+            ["--config", path.to_str().unwrap(), "--port=9100", "--log-level", "debug"].map(String::from)
+        ).unwrap();
+        let env = HashMap::from([("UP2PD_PORT", "9200"), ("UP2PD_IDENTITY", "ccc")]);
+        let config = ServerConfig::load(&cli_args, |key| env.get(key).map(|v| v.to_string())).unwrap();
+        assert_eq!(config.address, "0.0.0.0");
+        // flags win over env, env wins over the file
+        assert_eq!(config.port, 9100);
+        assert_eq!(config.identity, "ccc");
+        assert_eq!(config.log_level, "debug");
+        assert!(!config.dump().unwrap().contains("ccc"));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_config_tenants() {
+        let path = write_config("tenants", "address = \"0.0.0.0\"\nport = 9008\nlog_level = \"warn\"\nidentity = \"bbb\"\n\
+            [[tenants]]\nnamespace = \"acme\"\nidentity = \"acme-secret\"\nmax_devices = 100\n\
+            [[tenants]]\nnamespace = \"acme\"\nidentity = \"other\"\n");
+        let cli_args = CliArgs::parse(["-c", path.to_str().unwrap()].map(String::from)).unwrap();
+        let err = ServerConfig::load(&cli_args, |_| None).unwrap_err();
+        assert!(err.to_string().contains("twice"));
+        std::fs::write(&path, "address = \"0.0.0.0\"\nport = 9008\nlog_level = \"warn\"\nidentity = \"bbb\"\n\
+            [[tenants]]\nnamespace = \"acme\"\nidentity = \"acme-secret\"\nmax_devices = 100\n").unwrap();
+        let config = ServerConfig::load(&cli_args, |_| None).unwrap();
+        assert_eq!(config.tenants[0].max_devices, Some(100));
+        assert!(!config.dump().unwrap().contains("acme-secret"));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_config_server_key() {
+        let server_key = up2p::core::server_key::ServerKey::generate().to_hex();
+        let path = write_config("server_key", &format!("address = \"0.0.0.0\"\nport = 9008\nlog_level = \"warn\"\nidentity = \"bbb\"\nserver_key = \"{}\"\n", server_key));
+        let cli_args = CliArgs::parse(["-c", path.to_str().unwrap()].map(String::from)).unwrap();
+        let config = ServerConfig::load(&cli_args, |_| None).unwrap();
+        assert!(!config.dump().unwrap().contains(&server_key));
+        std::fs::write(&path, "address = \"0.0.0.0\"\nport = 9008\nlog_level = \"warn\"\nidentity = \"bbb\"\nserver_key = \"abcd\"\n").unwrap();
+        let err = ServerConfig::load(&cli_args, |_| None).unwrap_err();
+        assert!(err.to_string().contains("server_key"));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_config_codec() {
+        let path = write_config("codec", "address = \"0.0.0.0\"\nport = 9008\nlog_level = \"warn\"\nidentity = \"bbb\"\n");
+        let cli_args = CliArgs::parse(["-c", path.to_str().unwrap()].map(String::from)).unwrap();
+        assert_eq!(ServerConfig::load(&cli_args, |_| None).unwrap().codec, Codec::Bincode);
+        let config = ServerConfig::load(&cli_args, |key| (key == "UP2PD_CODEC").then(|| "cbor".to_string())).unwrap();
+        assert_eq!(config.codec, Codec::Cbor);
+        assert!(config.dump().unwrap().contains("codec = \"cbor\""));
+        assert!(ServerConfig::load(&cli_args, |key| (key == "UP2PD_CODEC").then(|| "protobuf".to_string())).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_config_validation() {
+        let path = write_config("validation", "address = \"0.0.0.0\"\nport = 9008\nlog_level = \"loud\"\nidentity = \"bbb\"\n");
+        let cli_args = CliArgs::parse(["-c", path.to_str().unwrap()].map(String::from)).unwrap();
+        let err = ServerConfig::load(&cli_args, |_| None).unwrap_err();
+        assert!(err.to_string().contains("log_level"));
+        let err = ServerConfig::load(&cli_args, |key| (key == "UP2PD_PORT").then(|| "port".to_string())).unwrap_err();
+        assert!(err.to_string().contains("UP2PD_PORT"));
+        assert!(CliArgs::parse(["--nope".to_string()]).is_err());
+        // an explicit config path must exist
+        let missing = CliArgs::parse(["-c", "/nonexistent/up2pd.toml"].map(String::from)).unwrap();
+        assert!(ServerConfig::load(&missing, |_| None).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
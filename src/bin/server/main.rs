@@ -0,0 +1,100 @@
+mod config;
+mod reload;
+
+use std::{str::FromStr, sync::Arc};
+
+use config::{CliArgs, ServerConfig};
+use tokio::signal;
+use tracing::{info, level_filters::LevelFilter, warn};
+use tracing_subscriber::{layer::SubscriberExt as _, util::SubscriberInitExt as _};
+use up2p::{core::server_key::ServerKey, server::{auth::{AuthProvider, SharedIdentityAuth}, enrollment::EnrollmentAuth, metrics::serve_metrics, Up2pServer}};
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let cli_args = CliArgs::parse(std::env::args().skip(1))?;
+    if cli_args.help {
+        print!("{}", config::USAGE);
+        return Ok(());
+    }
+    if cli_args.generate_key {
+        let server_key = ServerKey::generate();
+        println!("server_key = \"{}\"", server_key.to_hex());
+        println!("# clients pin the verifying key: {}", server_key.verifying_key_hex());
+        return Ok(());
+    }
+    let server_config = ServerConfig::load(&cli_args, |key| std::env::var(key).ok())?;
+    if cli_args.print_config {
+        print!("{}", server_config.dump()?);
+        return Ok(());
+    }
+    // init logger, the level can be changed later by a config reload
+    let (level_layer, log_level_handle) = tracing_subscriber::reload::Layer::new(LevelFilter::from_str(&server_config.log_level)?);
+    tracing_subscriber::registry().with(level_layer).with(tracing_subscriber::fmt::layer()).init();
+    info!("Starting server...");
+    info!("Effective config:\n{}", server_config.dump()?);
+
+    // a (host, port) pair also takes ipv6 literals like "::"
+    let bind_address = tokio::net::lookup_host((server_config.address.as_str(), server_config.port)).await?
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("Failed to resolve bind address {}", server_config.address))?;
+    let auth = Arc::new(SharedIdentityAuth::new(&server_config.identity));
+    let enrollment = match &server_config.enrollment {
+        Some(enrollment) => Some(Arc::new(EnrollmentAuth::new(enrollment.clone(), auth.clone())?)),
+        None => None,
+    };
+    let server_auth: Arc<dyn AuthProvider> = match &enrollment {
+        Some(enrollment) => enrollment.clone(),
+        None => auth.clone(),
+    };
+    let mut builder = Up2pServer::builder()
+        .bind_address(bind_address)
+        .auth(server_auth)
+        .rate_limit(server_config.rate_limit.clone())
+        .listing(server_config.listing.clone())
+        .acl(server_config.acl.clone())
+        .tenants(server_config.tenants.clone())
+        .reuseport_sockets(server_config.reuseport_sockets)
+        .codec(server_config.codec);
+    if let Some(tcp_port) = server_config.tcp_port {
+        builder = builder.tcp_address(std::net::SocketAddr::new(bind_address.ip(), tcp_port));
+    }
+    if let Some(websocket_port) = server_config.websocket_port {
+        builder = builder.websocket_address(std::net::SocketAddr::new(bind_address.ip(), websocket_port));
+    }
+    if let Some(server_key) = &server_config.server_key {
+        let server_key = ServerKey::from_hex(server_key)?;
+        info!("Signing packages, verifying key: {}", server_key.verifying_key_hex());
+        builder = builder.server_key(server_key);
+    }
+    if let Some(workers) = server_config.workers {
+        builder = builder.workers(workers);
+    }
+    let server = Arc::new(builder.build().await?);
+
+    if let Some(metrics_address) = &server_config.metrics_address {
+        let metrics_address = metrics_address.parse()?;
+        let metrics = server.metrics();
+        tokio::spawn(async move {
+            if let Err(e) = serve_metrics(metrics_address, metrics).await {
+                warn!("Metrics endpoint stopped: {}", e);
+            }
+        });
+    }
+
+    tokio::spawn(reload::watch_config(cli_args, server_config, reload::Reloadable {
+        log_level_handle,
+        auth,
+        enrollment,
+        server: server.clone(),
+    }));
+
+    tokio::select! {
+        _ = signal::ctrl_c() => {
+            info!("Shutting down server...");
+        }
+        _ = server.run() => {
+            warn!("Shutting down server Unexpectly...");
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,108 @@
+use std::{path::Path, sync::Arc, time::{Duration, SystemTime}};
+
+use tracing::{info, level_filters::LevelFilter, warn};
+use tracing_subscriber::{reload, Registry};
+use up2p::server::{auth::SharedIdentityAuth, enrollment::{EnrollmentAuth, EnrollmentState}, Up2pServer};
+
+use crate::config::{CliArgs, ServerConfig};
+
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+pub type LogLevelHandle = reload::Handle<LevelFilter, Registry>;
+
+// the parts of a running server a reload can change
+pub struct Reloadable {
+    pub log_level_handle: LogLevelHandle,
+    pub auth: Arc<SharedIdentityAuth>,
+    // set when the server started with [enrollment]
+    pub enrollment: Option<Arc<EnrollmentAuth>>,
+    pub server: Arc<Up2pServer>,
+}
+
+// Reload the config on SIGHUP or when the config file changes on disk.
+// Log level, identity, rate limits, ban lists, listing rules, the acl, tenants and
+// enrollment revocations apply live, the rest needs a restart. Registered devices
+// whose credential no longer verifies are evicted.
+pub async fn watch_config(cli_args: CliArgs, mut current: ServerConfig, reloadable: Reloadable) {
+    let (config_path, _) = ServerConfig::config_path(&cli_args, |key| std::env::var(key).ok());
+    let mut last_modified = modified_time(&config_path);
+    #[cfg(unix)]
+    let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(hangup) => Some(hangup),
+        Err(e) => {
+            warn!("Failed to listen for SIGHUP, {}", e);
+            None
+        }
+    };
+    let mut interval = tokio::time::interval(WATCH_INTERVAL);
+    loop {
+        #[cfg(unix)]
+        let hangup_recv = async {
+            match hangup.as_mut() {
+                Some(hangup) => hangup.recv().await,
+                None => std::future::pending().await,
+            }
+        };
+        #[cfg(not(unix))]
+        let hangup_recv = std::future::pending::<Option<()>>();
+        tokio::select! {
+            _ = hangup_recv => {
+                info!("SIGHUP received, reloading config");
+            }
+            _ = interval.tick() => {
+                let modified = modified_time(&config_path);
+                if modified == last_modified {
+                    continue;
+                }
+                last_modified = modified;
+                info!("{} changed, reloading config", config_path.display());
+            }
+        }
+        match reload_config(&cli_args, &current, &reloadable).await {
+            Ok(new_config) => current = new_config,
+            Err(e) => warn!("Failed to reload config, keeping the current one: {:#}", e),
+        }
+    }
+}
+
+async fn reload_config(cli_args: &CliArgs, old_config: &ServerConfig, reloadable: &Reloadable) -> anyhow::Result<ServerConfig> {
+    let new_config = ServerConfig::load(cli_args, |key| std::env::var(key).ok())?;
+    if new_config.address != old_config.address
+        || new_config.port != old_config.port
+        || new_config.tcp_port != old_config.tcp_port
+        || new_config.websocket_port != old_config.websocket_port
+        || new_config.metrics_address != old_config.metrics_address
+        || new_config.server_key != old_config.server_key
+        || new_config.enrollment.is_some() != old_config.enrollment.is_some()
+        || new_config.workers != old_config.workers
+        || new_config.reuseport_sockets != old_config.reuseport_sockets
+        || new_config.codec != old_config.codec
+    {
+        warn!("Changes to address, ports, metrics_address, server_key, workers, reuseport_sockets, codec or turning enrollment on or off apply after a restart");
+    }
+    // check everything first, so a bad config changes nothing
+    let level: LevelFilter = new_config.log_level.parse()?;
+    let enrollment = match (&reloadable.enrollment, &new_config.enrollment) {
+        (Some(enrollment), Some(config)) => Some((enrollment, EnrollmentState::try_from(config.clone())?)),
+        _ => None,
+    };
+    reloadable.log_level_handle.reload(level)?;
+    reloadable.auth.set_identity(&new_config.identity);
+    if let Some((enrollment, state)) = enrollment {
+        enrollment.set_state(state);
+    }
+    reloadable.server.set_rate_limit(new_config.rate_limit.clone());
+    reloadable.server.set_listing(new_config.listing.clone());
+    reloadable.server.set_acl(new_config.acl.clone());
+    reloadable.server.set_tenants(new_config.tenants.clone());
+    if reloadable.enrollment.is_some() {
+        reloadable.server.evict_revoked().await;
+    }
+    reloadable.server.evict_unverified().await;
+    info!("Config reloaded:\n{}", new_config.dump()?);
+    Ok(new_config)
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
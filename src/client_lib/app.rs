@@ -1,35 +1,150 @@
-use std::{cell::Cell, net::{IpAddr, SocketAddr}, pin::Pin, sync::Arc, time::Duration, u64};
+use std::{cell::Cell, collections::HashMap, net::{IpAddr, SocketAddr}, sync::{atomic::{AtomicBool, Ordering}, Arc}, time::{Duration, Instant}};
 use anyhow::anyhow;
-use tokio::{net::UdpSocket, sync::{mpsc::{Receiver, Sender}, oneshot, Mutex}, task::JoinHandle};
+use tokio::{sync::{mpsc::{Receiver, Sender}, oneshot, Mutex}, task::JoinHandle};
 use tracing::{debug, info, warn};
-use crate::{client_lib::event::PkgExchangeEvent, core::{bincodec::BinCodec, request_info::RequestInfo, uprotocol_pkg::{BasePkg, ClientHelloPkg, ClientRequestAckPkg, ClientRequestPkg, GetBaseInfo, PeerExchangePkg}, BaseUp2pProtocol}};
+use tokio::net::TcpStream;
+use futures_util::Stream;
+use crate::{client_lib::event::{DeniedEvent, DeviceListAckEvent, PathChangeEvent, PkgExchangeEvent, PresenceEvent, StatusAckEvent, TcpPunchEvent, TypedEvent}, transport::{stream::tcp_simultaneous_open, DatagramTransport}, core::{bincodec::BinCodec, codec::Codec, protocol_version::{ProtocolVersion, CAP_PRESENCE, CAP_TCP_PUNCH}, request_info::RequestInfo, server_key::PinnedServerKey, uprotocol_pkg::{BasePkg, ClientHelloPkg, ClientRequestPkg, DeviceListQueryPkg, DeviceStatusPkg, GetBaseInfo, PeerExchangePkg, PresenceSubscribePkg, StatusAckPkg, StatusAdvertisePkg, StatusQueryPkg, Up2pRequest}, Up2pMessage}};
 
-use super::event::{CliEvent, EventType, HelloACKEvent, RequestAckEvent};
+use super::{discovery::{DiscoveryConfig, LanDiscovery}, dispatch::EventDispatcher, event::{CliEvent, HelloACKEvent, RequestAckEvent}, metrics::{CliMetricsHook, PathType}};
+
+// a control request is sent at most this many times, waiting ACK_TIMEOUT for each answer
+const ACK_ATTEMPTS: u32 = 3;
+const ACK_TIMEOUT: Duration = Duration::from_secs(1);
+// a status query asks for no more statuses than fit this, clients read 1500 byte datagrams
+const MAX_STATUS_ACK_LEN: usize = 1200;
+// events a stream from on_message, on_presence or on_path_change holds before newer ones are dropped
+const EVENT_STREAM_BUFFER: usize = 64;
+// how often a live on_presence stream keeps us registered, well under the server's default online_timeout
+const DEFAULT_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
 
 pub struct Up2pCli {
     base_info: BasePkg,
-    udp_socket: Arc<UdpSocket>,
+    transport: Arc<dyn DatagramTransport>,
+    // tcp or websocket connection to the server, used once udp goes unanswered
+    fallback_transport: Option<Arc<dyn DatagramTransport>>,
+    using_fallback: Arc<AtomicBool>,
     server_address: (IpAddr, u16),
+    // more addresses of the same server, e.g. its ipv6 one next to an ipv4 server_address
+    extra_server_addresses: Vec<SocketAddr>,
+    // packages from the server must carry its signature once pinned
+    server_key: Option<PinnedServerKey>,
+    // announced in the hello, and what the server's HELLO_ACK left of it
+    protocol: ProtocolVersion,
+    negotiated_protocol: Arc<std::sync::Mutex<Option<ProtocolVersion>>>,
+    // the token the server's HELLO_ACK gave us, our identity after the hello
+    session: Arc<std::sync::Mutex<Option<String>>>,
+    keepalive_interval: Duration,
     stop_sig: Option<tokio::sync::oneshot::Receiver<()>>,
-    event_list: Arc<Mutex<Vec<(Sender<Option<Box<dyn CliEvent>>>, u8, u128)>>>,
-    event_reciver: Cell<Option<Receiver<Box<dyn CliEvent>>>>,
+    dispatcher: Arc<Mutex<EventDispatcher>>,
+    // the path packages to each peer took last, by global id
+    paths: std::sync::Mutex<HashMap<String, PathType>>,
+    event_sender: Sender<CliEvent>,
+    event_reciver: Cell<Option<Receiver<CliEvent>>>,
     event_loop_handle: Option<JoinHandle<()>>,
-    event_task: Cell<Option<Pin<Box<dyn Future<Output = ()> + Send + 'static>>>>,
+    metrics_hook: Option<Arc<dyn CliMetricsHook>>,
+    lan_discovery: Option<LanDiscovery>,
 }
 
 unsafe impl Sync for Up2pCli {}
 
 impl Up2pCli {
     /// let (up2p_cli, cancer_hdl) = Up2pCli::new(base_info, udp_socket, server_address); 
-    pub fn new(base_info: BasePkg, udp_socket: Arc<UdpSocket>, server_address:(IpAddr, u16) ) -> (Self, oneshot::Sender<()>) {
-        let (event_tx, event_rx) = tokio::sync::mpsc::channel(1024);
+    /// any `DatagramTransport` works in place of the udp socket
+    pub fn new(base_info: BasePkg, transport: Arc<dyn DatagramTransport>, server_address:(IpAddr, u16) ) -> (Self, oneshot::Sender<()>) {
+        let (event_sender, event_rx) = tokio::sync::mpsc::channel(1024);
         let (cancel_tx, cancell_rx) = tokio::sync::oneshot::channel();
-        let _udp_socket = udp_socket.clone();
-        let event_task = Box::pin(async move {
+        (Up2pCli {
+            base_info,
+            transport,
+            fallback_transport: None,
+            using_fallback: Arc::new(AtomicBool::new(false)),
+            server_address,
+            extra_server_addresses: Vec::new(),
+            server_key: None,
+            protocol: ProtocolVersion::CURRENT,
+            negotiated_protocol: Arc::new(std::sync::Mutex::new(None)),
+            session: Arc::new(std::sync::Mutex::new(None)),
+            keepalive_interval: DEFAULT_KEEPALIVE_INTERVAL,
+            dispatcher: Arc::new(Mutex::new(EventDispatcher::default())),
+            paths: std::sync::Mutex::new(HashMap::new()),
+            event_sender,
+            event_reciver: Cell::new(Some(event_rx)),
+            event_loop_handle: None,
+            stop_sig: Some(cancell_rx),
+            metrics_hook: None,
+            lan_discovery: None,
+        }, cancel_tx)
+    }
+    // report rtt, retransmissions and path type to the hook, call before start
+    pub fn set_metrics_hook(&mut self, hook: Arc<dyn CliMetricsHook>) {
+        self.metrics_hook = Some(hook);
+    }
+    // Registration and relayed packages move to `transport` when the server
+    // stops answering over udp, e.g. a StreamClientTransport. Call before start.
+    pub fn set_fallback_transport(&mut self, transport: Arc<dyn DatagramTransport>) {
+        self.fallback_transport = Some(transport);
+    }
+    // client_hello registers through every server address, so the server learns
+    // both the ipv4 and the ipv6 mapping of a dual stack client. Call before start.
+    pub fn add_server_address(&mut self, server_address: SocketAddr) {
+        self.extra_server_addresses.push(server_address);
+    }
+    // Only accept server packages signed with the key of `server_key`, see
+    // core::server_key. Call before start.
+    pub fn pin_server_key(&mut self, server_key: PinnedServerKey) {
+        self.server_key = Some(server_key);
+    }
+    // How often on_presence streams refresh our registration, keep it under the
+    // server's online_timeout. Call before on_presence.
+    pub fn set_keepalive_interval(&mut self, keepalive_interval: Duration) {
+        self.keepalive_interval = keepalive_interval;
+    }
+    // announce an older protocol than this release's, call before client_hello
+    pub fn set_protocol(&mut self, protocol: ProtocolVersion) {
+        self.protocol = protocol;
+    }
+    // what both we and the server speak, known after the first client_hello
+    pub fn negotiated_protocol(&self) -> Option<ProtocolVersion> {
+        *self.negotiated_protocol.lock().unwrap()
+    }
+    // what packages to the server and peers are encoded with, bincode until the hello is answered
+    pub fn codec(&self) -> Codec {
+        self.negotiated_protocol().map_or(Codec::Bincode, ProtocolVersion::codec)
+    }
+    // fails early when the server told us it lacks `capability`
+    fn require(&self, capability: u32, feature: &str) -> anyhow::Result<()> {
+        match self.negotiated_protocol() {
+            Some(protocol) if !protocol.supports(capability) => Err(anyhow!("{} not supported by the server", feature)),
+            _ => Ok(()),
+        }
+    }
+    // Announce this client on the lan and resolve peers there first, client_request
+    // then answers lan peers without asking the server.
+    pub async fn enable_lan_discovery(&mut self, config: DiscoveryConfig) -> anyhow::Result<()> {
+        let endpoint_port = self.transport.local_addr()?.port();
+        self.lan_discovery = Some(LanDiscovery::start(config, self.base_info.clone(), endpoint_port).await?);
+        Ok(())
+    }
+    pub fn lan_discovery(&self) -> Option<&LanDiscovery> {
+        self.lan_discovery.as_ref()
+    }
+    pub fn is_using_fallback(&self) -> bool {
+        self.using_fallback.load(Ordering::Relaxed)
+    }
+    pub async fn start(&self) -> anyhow::Result<()> {
+        let mut event_reciver = self.event_reciver.take().expect("client has been started");
+        let transport = self.transport.clone();
+        let event_tx = self.event_sender.clone();
+        let server_addresses: Vec<SocketAddr> = std::iter::once(SocketAddr::from(self.server_address))
+            .chain(self.extra_server_addresses.iter().copied())
+            .collect();
+        let server_key = self.server_key.clone();
+        tokio::spawn(async move {
             info!("event loop started");
             let mut buf = [0u8; 1500];
             loop {
-                let (len, endpoint_addr) = match _udp_socket.recv_from(&mut buf).await {
+                let (len, endpoint_addr) = match transport.recv_from(&mut buf).await {
                     Ok((len, endpoint_addr)) => (len, endpoint_addr),
                     Err(e) => {
                         warn!("recv_from error: {}", e);
@@ -37,52 +152,15 @@ impl Up2pCli {
                     }
                 };
                 debug!("recv_from: len: {}, endpoint_addr: {}", len, endpoint_addr);
-                // handle udp pkg
-                let base_protocol_pkg = match BaseUp2pProtocol::decode_from(&buf[..len])
-                {
-                    Ok(base_protocol_pkg) => base_protocol_pkg,
-                    Err(e) => {
-                        warn!("decode_from_slice error: {}", e);
-                        continue;
-                    }
-                };
-                let boxed_client_event: Box<dyn CliEvent> = match base_protocol_pkg.get_pkg_type() {
-                    BaseUp2pProtocol::TYPE_HELLO_ACK => {
-                        Box::new(HelloACKEvent) as Box<dyn CliEvent>
-                    }
-                    BaseUp2pProtocol::TYPE_REQUEST_ACK => {
-                        let payload = base_protocol_pkg.get_payload();
-                        let client_request_ack_pkg = match ClientRequestAckPkg::decode_from(payload) {
-                            Ok(client_request_ack_pkg) => client_request_ack_pkg,
-                            Err(e) => {
-                                warn!("decode_from_slice error: {}", e);
-                                continue;
-                            }
-                        };
-                        Box::new(RequestAckEvent::new(client_request_ack_pkg)) as Box<dyn CliEvent>
-                    }
-                    BaseUp2pProtocol::TYPE_PKG_EXCHANGE => {
-                        let payload = base_protocol_pkg.get_payload();
-                        let peer_exchange_pkg = match PeerExchangePkg::decode_from(payload) {
-                            Ok(peer_exchange_pkg) => peer_exchange_pkg,
-                            Err(e) => {
-                                warn!("decode_from_slice error: {}", e);
-                                continue;
-                            }
-                        };
-                        Box::new(PkgExchangeEvent::new(
-                            peer_exchange_pkg.get_payload(),
-                            peer_exchange_pkg.get_baseinfo().clone(),
-                            peer_exchange_pkg.get_target()
-                        )) as Box<dyn CliEvent>
-                    }
-                    _ => {
-                        warn!("unknown pkg type: {}", base_protocol_pkg.get_pkg_type());
-                        continue;
-                    }
+                // a dual stack socket sees ipv4 servers as mapped addresses
+                let from_server = transport.is_server_connection() || server_addresses.iter().any(|server_address| {
+                    server_address.ip().to_canonical() == endpoint_addr.ip().to_canonical() && server_address.port() == endpoint_addr.port()
+                });
+                let Some(client_event) = decode_event(&buf[..len], from_server, server_key.as_ref()) else {
+                    continue;
                 };
                 // send event to event loop
-                match event_tx.send(boxed_client_event).await {
+                match event_tx.send(client_event).await {
                     Ok(_) => {
                         info!("send event to event loop");
                     }
@@ -92,186 +170,466 @@ impl Up2pCli {
                 }
             }
         });
-        (Up2pCli {
-            base_info,
-            udp_socket,
-            server_address,
-            event_list: Arc::new(Mutex::new(Vec::new())),
-            event_reciver: Cell::new(Some(event_rx)),
-            event_loop_handle: None,
-            event_task: Cell::new(Some(event_task)),
-            stop_sig: Some(cancell_rx),
-        }, cancel_tx)
-    }
-    pub async fn start(&self) -> anyhow::Result<()> {
-        let mut event_reciver = self.event_reciver.take().expect("client has been started");
-        let event_task = self.event_task.take().expect("event loop has been started");
-        tokio::spawn(async move { event_task.await });
-        let event_list = self.event_list.clone();
-        tokio::spawn(async move {
-            debug!("start to handle event loop");
-            loop {
-                match event_reciver.recv().await {
-                    Some(event) => {
-                        let recived_event_type = event.get_event_type();
-                        match recived_event_type {
-                            EventType::HELLO_ACK => {
-                                let event_list = event_list.lock().await;
-                                for (event_tx, event_type, _) in event_list.iter() {
-                                    debug!("scanning, finde event type: {}, recived event type: {}", event_type, recived_event_type);
-                                    if *event_type == recived_event_type {
-                                        if let Err(e) = event_tx.send(None).await {
-                                            warn!("send event error: {}", e);
-                                        };
-                                        break;
-                                    }
-                                }
-                                drop(event_list);
-                            }
-                            EventType::REQUEST_ACK => {
-                                let event_list = event_list.lock().await;
-                                for (event_tx, event_type, _) in event_list.iter() {
-                                    debug!("scanning, finde event type: {}, recived event type: {}", event_type, recived_event_type);
-                                    if *event_type == recived_event_type {
-                                        if let Err(e) = event_tx.send(Some(event)).await {
-                                            warn!("send event error: {}", e);
-                                        };
-                                        break;
-                                    }
-                                }
-                                drop(event_list);
-                            }
-                            EventType::P2P_PKG_EXCHANGE => {
-                                let event_list = event_list.lock().await;
-                                for (event_tx, event_type, _) in event_list.iter() {
-                                    debug!("scanning, finde event type: {}, recived event type: {}", event_type, recived_event_type);
-                                    if *event_type == recived_event_type {
-                                        if let Err(e) = event_tx.send(Some(event)).await {
-                                            warn!("send event error: {}", e);
-                                        };
-                                        break;
-                                    }
-                                }
-                                drop(event_list);
-                            }
-                            _ => {
-                                warn!("unknown event type: {}", recived_event_type);
-                            }
-                            
+        if let Some(fallback_transport) = self.fallback_transport.clone() {
+            let event_tx = self.event_sender.clone();
+            let server_key = self.server_key.clone();
+            tokio::spawn(async move {
+                let mut buf = [0u8; 1500];
+                loop {
+                    // unlike udp a failed stream does not recover
+                    let len = match fallback_transport.recv_from(&mut buf).await {
+                        Ok((len, _)) => len,
+                        Err(e) => {
+                            warn!("fallback transport closed: {}", e);
+                            break;
+                        }
+                    };
+                    let from_server = fallback_transport.is_server_connection();
+                    if let Some(event) = decode_event(&buf[..len], from_server, server_key.as_ref()) {
+                        if event_tx.send(event).await.is_err() {
+                            break;
                         }
-                    }
-                    None => {
-                        warn!("event receiver closed");
                     }
                 }
-                debug!("event loop recv event");
+            });
+        }
+        let dispatcher = self.dispatcher.clone();
+        tokio::spawn(async move {
+            debug!("start to handle event loop");
+            while let Some(event) = event_reciver.recv().await {
+                dispatcher.lock().await.dispatch(event);
             }
+            warn!("event receiver closed");
         });
         self.event_reciver.set(None);
         Ok(())
     }
-    // send client hello to server
+    // send client hello to server, always in bincode since the codec is negotiated by it
     pub async fn client_hello(&self) -> anyhow::Result<()> {
-        let hello_pkg = BaseUp2pProtocol::client_hello_with_payload(
-            ClientHelloPkg::new(&self.base_info.client_class, &self.base_info.client_instance, &self.base_info.identity, 0x01)
-        )?;
-        self.udp_socket.send_to(hello_pkg.encode_to_vec()?.as_slice(), self.server_address).await?;
+        let nonce = new_nonce();
+        let hello_pkg = self.hello_pkg(nonce)?;
         // wait for response
-        self.subscribe_ack_event(EventType::HELLO_ACK, Duration::from_secs(3)).await?;
+        let hello_ack: HelloACKEvent = self.send_with_retry(hello_pkg.as_slice(), nonce).await?;
+        let negotiated = self.protocol.negotiate(hello_ack.get_protocol());
+        debug!("negotiated protocol: {:?}", negotiated);
+        *self.negotiated_protocol.lock().unwrap() = Some(negotiated);
+        self.start_session(&hello_ack);
+        // the other address families are optional, the host may lack ipv6
+        if !self.is_using_fallback() {
+            for server_address in &self.extra_server_addresses {
+                match self.send_with_retry_on::<HelloACKEvent>(&self.transport, *server_address, hello_pkg.as_slice(), nonce).await {
+                    Ok(hello_ack) => self.start_session(&hello_ack),
+                    Err(e) => warn!("client hello to {} failed: {}", server_address, e),
+                }
+            }
+        }
         Ok(())
     }
-    // send client request to server
+    // keep the session a HELLO_ACK handed out, servers without SESSION keep taking our credential
+    fn start_session(&self, hello_ack: &HelloACKEvent) {
+        let session = Some(hello_ack.get_session()).filter(|session| !session.is_empty());
+        *self.session.lock().unwrap() = session;
+    }
+    // what names us to the server after the hello, the session token if we have one
+    fn session_identity(&self) -> String {
+        self.session.lock().unwrap().clone().unwrap_or_else(|| self.base_info.identity.clone())
+    }
+    // send client request to server, returns the endpoint to try first
     pub async fn client_request(&self, _req: RequestInfo) -> anyhow::Result<Option<String>> {
-        let req = ClientRequestPkg::create_endpoint_request(
-            &self.base_info.client_class,
-            &self.base_info.client_instance,
-            &self.base_info.identity,
-            &crate::utils::get_global_id(&_req.client_class, &_req.client_instance)
-        );
-        let request_pkg = BaseUp2pProtocol::request_with_payload(
-            req
-        )?;
-        self.udp_socket.send_to(request_pkg.encode_to_vec()?.as_slice(), self.server_address).await?;
+        let (endpoint_address, _) = self.request_endpoints(_req).await?;
+        Ok(Some(endpoint_address))
+    }
+    // every registered endpoint of the device, ipv4 and ipv6
+    pub async fn client_request_all(&self, _req: RequestInfo) -> anyhow::Result<Vec<String>> {
+        let (_, endpoint_addresses) = self.request_endpoints(_req).await?;
+        Ok(endpoint_addresses)
+    }
+    async fn request_endpoints(&self, _req: RequestInfo) -> anyhow::Result<(String, Vec<String>)> {
+        // a peer on the same lan is preferred over whatever the server knows
+        if let Some(lan_discovery) = &self.lan_discovery {
+            if let Some(endpoint) = lan_discovery.resolve(&crate::utils::get_global_id(&_req.client_class, &_req.client_instance)) {
+                debug!("resolved {:?} on the lan: {}", _req, endpoint);
+                return Ok((endpoint.to_string(), vec![endpoint.to_string()]));
+            }
+        }
+        let global_id = crate::utils::get_global_id(&_req.client_class, &_req.client_instance);
+        let (request_pkg, nonce) = self.request_pkg(Up2pRequest::Endpoint(global_id.clone()))?;
         // wait for response
-        let response = self.subscribe_ack_event(EventType::REQUEST_ACK, Duration::from_secs(3)).await?;
-        if let Some(event) = response {
-            if let Some(request_ack) = event.as_any().downcast_ref::<RequestAckEvent>() {
-                debug!("request ack: {:?}", request_ack);
-                return Ok(Some(request_ack.get_result_endpoint_address()));
-            } else {
-                warn!("unknown event type: {}", event.get_event_type());
+        let request_ack: RequestAckEvent = self.send_with_retry(&request_pkg, nonce).await?;
+        debug!("request ack: {:?}", request_ack);
+        if request_ack.get_global_id() != global_id {
+            return Err(anyhow!("request ack for {} while asking for {}", request_ack.get_global_id(), global_id));
+        }
+        Ok((request_ack.get_result_endpoint_address(), request_ack.get_result_endpoint_addresses()))
+    }
+    // tell the server our nat type and capabilities, others see them in query_status
+    pub async fn advertise_status(&self, nat_type: u8, capabilities: u32) -> anyhow::Result<DeviceStatusPkg> {
+        let global_id = crate::utils::get_global_id(&self.base_info.client_class, &self.base_info.client_instance);
+        let request_pkg = self.request_pkg(Up2pRequest::AdvertiseStatus(StatusAdvertisePkg::new(nat_type, capabilities)))?;
+        self.send_status_request(&request_pkg, &[global_id]).await?.pop()
+            .ok_or_else(|| anyhow!("empty status ack"))
+    }
+    // whether `_req` is online, when it was last seen and what it advertised
+    pub async fn query_status(&self, _req: RequestInfo) -> anyhow::Result<DeviceStatusPkg> {
+        self.query_status_batch(vec![_req]).await?.pop()
+            .ok_or_else(|| anyhow!("empty status ack"))
+    }
+    // statuses in the order asked, split over as many requests as the package size needs
+    pub async fn query_status_batch(&self, _reqs: Vec<RequestInfo>) -> anyhow::Result<Vec<DeviceStatusPkg>> {
+        let global_ids: Vec<String> = _reqs.iter()
+            .map(|req| crate::utils::get_global_id(&req.client_class, &req.client_instance))
+            .collect();
+        let mut statuses = Vec::with_capacity(global_ids.len());
+        let mut chunk: Vec<String> = Vec::new();
+        for global_id in global_ids {
+            chunk.push(global_id);
+            if chunk.len() > 1 && self.status_request(chunk.clone()).is_err() {
+                let global_id = chunk.pop().unwrap();
+                let request_pkg = self.status_request(chunk.clone())?;
+                statuses.extend(self.send_status_request(&request_pkg, &chunk).await?);
+                chunk = vec![global_id];
             }
-        } else {
-            warn!("request ack not received");
         }
-        // maybe type error
-        Err(anyhow!("request ack type mismatch"))
-    }
-    // subscribe ack event
-    // Ok(None) if event is received bug no payload
-    // Err(anyhow::anyhow!("event timeout")) if event is not received
-    // Ok(Some(event)) if event is received with payload
-    async fn subscribe_ack_event(&self, event_type: u8, cancel_duration: Duration) -> anyhow::Result<Option<Box<dyn CliEvent>>> {
-        let (event_tx, mut event_rx) = tokio::sync::mpsc::channel(1);
-        let id = rand::random::<u128>();
-        let mut event_list = self.event_list.lock().await;
-        event_list.push((event_tx, event_type, id));
-        drop(event_list);
-        let wait_result: Option<Box<dyn CliEvent>> = tokio::select! {
-            result = event_rx.recv() => {
-                match result {
-                    Some(event) => {
-                        debug!("event received with payload: {:?}", event.is_some());
-                        event
+        if !chunk.is_empty() {
+            let request_pkg = self.status_request(chunk.clone())?;
+            statuses.extend(self.send_status_request(&request_pkg, &chunk).await?);
+        }
+        Ok(statuses)
+    }
+    // fails if the answer to the ids might not fit in one datagram, sized by the
+    // largest status each of them can have in the negotiated codec
+    fn status_request(&self, global_ids: Vec<String>) -> anyhow::Result<(Vec<u8>, u64)> {
+        let largest = global_ids.iter()
+            .map(|global_id| DeviceStatusPkg::new(global_id.clone(), true, Some(u64::MAX), u8::MAX, u32::MAX))
+            .collect();
+        let status_ack = Up2pMessage::StatusAck(StatusAckPkg::new(largest).with_nonce(u64::MAX)).encode_with(self.codec())?;
+        if status_ack.len() > MAX_STATUS_ACK_LEN {
+            return Err(anyhow!("status ack too large"));
+        }
+        self.request_pkg(Up2pRequest::Status(StatusQueryPkg::new(global_ids)))
+    }
+    // the statuses must be those of `global_ids`, in that order
+    async fn send_status_request(&self, (request_pkg, nonce): &(Vec<u8>, u64), global_ids: &[String]) -> anyhow::Result<Vec<DeviceStatusPkg>> {
+        let status_ack: StatusAckEvent = self.send_with_retry(request_pkg, *nonce).await?;
+        let statuses = status_ack.get_statuses();
+        if !statuses.iter().map(DeviceStatusPkg::get_global_id).eq(global_ids.iter().cloned()) {
+            return Err(anyhow!("status ack does not answer {:?}", global_ids));
+        }
+        Ok(statuses)
+    }
+    // One page of the devices registered under `client_class`, with the cursor of the
+    // next page if there is one. The server has to allow our class to list that class.
+    pub async fn list_devices(&self, client_class: &str, online_only: bool, cursor: Option<String>, limit: u16) -> anyhow::Result<(Vec<DeviceStatusPkg>, Option<String>)> {
+        let (request_pkg, nonce) = self.request_pkg(Up2pRequest::ListDevices(DeviceListQueryPkg::new(client_class, online_only, cursor, limit)))?;
+        let device_list_ack: DeviceListAckEvent = self.send_with_retry(&request_pkg, nonce).await?;
+        if device_list_ack.is_denied() {
+            return Err(anyhow!("listing devices of {} denied", client_class));
+        }
+        Ok((device_list_ack.get_devices(), device_list_ack.get_next_cursor()))
+    }
+    // every page of list_devices
+    pub async fn list_all_devices(&self, client_class: &str, online_only: bool) -> anyhow::Result<Vec<DeviceStatusPkg>> {
+        let mut devices = Vec::new();
+        let mut cursor = None;
+        loop {
+            let (page, next_cursor) = self.list_devices(client_class, online_only, cursor, 0).await?;
+            devices.extend(page);
+            match next_cursor {
+                Some(next_cursor) => cursor = Some(next_cursor),
+                None => return Ok(devices),
+            }
+        }
+    }
+    // Get pushed presence events for `devices` and every device of `client_classes`
+    // through on_presence, replacing any earlier subscription. Returns the
+    // current status of `devices`. Watching a class takes the server's listing rule for it.
+    pub async fn subscribe_presence(&self, devices: Vec<RequestInfo>, client_classes: Vec<String>) -> anyhow::Result<Vec<DeviceStatusPkg>> {
+        self.require(CAP_PRESENCE, "presence")?;
+        let global_ids = devices.iter()
+            .map(|req| crate::utils::get_global_id(&req.client_class, &req.client_instance))
+            .collect();
+        let (request_pkg, nonce) = self.request_pkg(Up2pRequest::SubscribePresence(PresenceSubscribePkg::new(global_ids, client_classes.clone())))?;
+        let subscription_ack: DeviceListAckEvent = self.send_with_retry(&request_pkg, nonce).await?;
+        if subscription_ack.is_denied() {
+            return Err(anyhow!("presence subscription to {:?} denied", client_classes));
+        }
+        Ok(subscription_ack.get_devices())
+    }
+    // Packages from peers, each stream gets every package and the first one also those
+    // that came while nothing listened. Packages addressed to another device are skipped.
+    pub async fn on_message(&self) -> impl Stream<Item = PkgExchangeEvent> + Send + Unpin + 'static {
+        let base_info = self.base_info.clone();
+        let messages = self.event_stream::<PkgExchangeEvent>().await;
+        Box::pin(futures_util::StreamExt::filter(messages, move |message| {
+            let for_self = message.get_dst().is_none_or(|dst| dst == base_info);
+            if !for_self {
+                warn!("Received pkg not for self");
+            }
+            std::future::ready(for_self)
+        }))
+    }
+    // Presence events, each stream gets every event. Subscribe with
+    // subscribe_presence, the server only pushes while we stay registered, which
+    // the stream sees to while it lives, see set_keepalive_interval.
+    pub async fn on_presence(&self) -> impl Stream<Item = PresenceEvent> + Send + Unpin + 'static {
+        let (event_tx, event_rx) = tokio::sync::mpsc::channel(EVENT_STREAM_BUFFER);
+        self.spawn_keepalive(event_tx.clone());
+        self.subscribe_stream(event_tx, event_rx).await
+    }
+    // A peer's packages started taking another path, see PathType. Each stream gets every change.
+    pub async fn on_path_change(&self) -> impl Stream<Item = PathChangeEvent> + Send + Unpin + 'static {
+        self.event_stream().await
+    }
+    async fn event_stream<E: TypedEvent + Send + 'static>(&self) -> impl Stream<Item = E> + Send + Unpin + 'static {
+        let (event_tx, event_rx) = tokio::sync::mpsc::channel(EVENT_STREAM_BUFFER);
+        self.subscribe_stream(event_tx, event_rx).await
+    }
+    async fn subscribe_stream<E: TypedEvent + Send + 'static>(&self, event_tx: Sender<CliEvent>, event_rx: Receiver<CliEvent>) -> impl Stream<Item = E> + Send + Unpin + 'static {
+        self.dispatcher.lock().await.subscribe(event_tx, E::EVENT_TYPE);
+        Box::pin(futures_util::stream::unfold(event_rx, |mut event_rx| async move {
+            loop {
+                if let Some(event) = E::from_event(event_rx.recv().await?) {
+                    return Some((event, event_rx));
+                }
+            }
+        }))
+    }
+    // Until the stream behind `stream_tx` is dropped, send the server a status query
+    // for no devices every keepalive_interval. Like any request it refreshes our
+    // last_seen, nothing waits for its answer.
+    fn spawn_keepalive(&self, stream_tx: Sender<CliEvent>) {
+        let base_info = self.base_info.clone();
+        let session = self.session.clone();
+        let negotiated_protocol = self.negotiated_protocol.clone();
+        let transport = self.transport.clone();
+        let fallback_transport = self.fallback_transport.clone();
+        let using_fallback = self.using_fallback.clone();
+        let server_address = SocketAddr::from(self.server_address);
+        let keepalive_interval = self.keepalive_interval;
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + keepalive_interval, keepalive_interval);
+            loop {
+                tokio::select! {
+                    _ = stream_tx.closed() => return,
+                    _ = ticker.tick() => {}
+                }
+                let identity = session.lock().unwrap().clone().unwrap_or_else(|| base_info.identity.clone());
+                let codec = negotiated_protocol.lock().unwrap().map_or(Codec::Bincode, ProtocolVersion::codec);
+                let transport = match &fallback_transport {
+                    Some(fallback_transport) if using_fallback.load(Ordering::Relaxed) => fallback_transport,
+                    _ => &transport,
+                };
+                let keepalive = encode_request(&base_info, &identity, Up2pRequest::Status(StatusQueryPkg::new(Vec::new())), 0, codec);
+                match keepalive {
+                    Ok(keepalive) => {
+                        if let Err(e) = transport.send_to(&keepalive, server_address).await {
+                            warn!("keepalive failed: {}", e);
+                        }
                     }
-                    None => {
-                        warn!("event receiver closed");
-                        None
+                    Err(e) => warn!("keepalive not encoded: {}", e),
+                }
+            }
+        });
+    }
+    // tell on_path_change streams when the path to `global_id` is not the one used last
+    async fn record_path(&self, global_id: String, path_type: PathType) {
+        let previous = self.paths.lock().unwrap().insert(global_id.clone(), path_type);
+        if previous != Some(path_type) {
+            self.dispatcher.lock().await.dispatch(CliEvent::from(PathChangeEvent::new(global_id, path_type)));
+        }
+    }
+    // Tcp hole punching with `_req`, which calls accept_tcp_punch meanwhile. Both
+    // peers must talk to the server over StreamClientTransport::connect_tcp, as
+    // main or fallback transport, since the punch starts from that connection's port.
+    pub async fn tcp_punch(&self, _req: RequestInfo) -> anyhow::Result<TcpStream> {
+        self.require(CAP_TCP_PUNCH, "tcp punch")?;
+        let global_id = crate::utils::get_global_id(&_req.client_class, &_req.client_instance);
+        let (request_pkg, nonce) = self.request_pkg(Up2pRequest::TcpPunch(global_id.clone()))?;
+        let tcp_punch: TcpPunchEvent = self.send_with_retry(&request_pkg, nonce).await?;
+        if tcp_punch.get_peer_global_id() != global_id {
+            return Err(anyhow!("tcp punch with {} while asking for {}", tcp_punch.get_peer_global_id(), global_id));
+        }
+        self.tcp_simultaneous_open(&tcp_punch).await
+    }
+    // wait for a peer's tcp_punch, returns its global id and the connection
+    pub async fn accept_tcp_punch(&self) -> anyhow::Result<(String, TcpStream)> {
+        let tcp_punch: TcpPunchEvent = self.subscribe_ack_event(Duration::from_secs(u64::MAX)).await?;
+        Ok((tcp_punch.get_peer_global_id(), self.tcp_simultaneous_open(&tcp_punch).await?))
+    }
+    async fn tcp_simultaneous_open(&self, tcp_punch: &TcpPunchEvent) -> anyhow::Result<TcpStream> {
+        let local_addr = self.server_transport().local_addr()?;
+        let peer_addr: SocketAddr = tcp_punch.get_endpoint_address().parse()?;
+        info!("tcp punch from {} to {} ({})", local_addr, peer_addr, tcp_punch.get_peer_global_id());
+        let stream = tcp_simultaneous_open(local_addr, peer_addr).await?;
+        if let Some(hook) = &self.metrics_hook {
+            hook.on_path(PathType::Direct, None);
+        }
+        self.record_path(tcp_punch.get_peer_global_id(), PathType::Direct).await;
+        Ok(stream)
+    }
+    // the only package with our credential, the others carry the session it starts
+    fn hello_pkg(&self, nonce: u64) -> anyhow::Result<Vec<u8>> {
+        Up2pMessage::Hello(
+            ClientHelloPkg::new(&self.base_info.client_class, &self.base_info.client_instance, &self.base_info.identity, ClientHelloPkg::MSG_HELLO)
+                .in_namespace(&self.base_info.namespace)
+                .with_protocol(self.protocol)
+                .with_nonce(nonce)
+        ).encode_to_vec()
+    }
+    // `request` from this client, encoded, with the nonce its answer has to echo
+    fn request_pkg(&self, request: Up2pRequest) -> anyhow::Result<(Vec<u8>, u64)> {
+        let nonce = new_nonce();
+        let request_pkg = encode_request(&self.base_info, &self.session_identity(), request, nonce, self.codec())?;
+        Ok((request_pkg, nonce))
+    }
+    // packages for the server go through the fallback transport once it took over
+    fn server_transport(&self) -> &Arc<dyn DatagramTransport> {
+        match &self.fallback_transport {
+            Some(fallback_transport) if self.is_using_fallback() => fallback_transport,
+            _ => &self.transport,
+        }
+    }
+    // send a control pkg to the server and wait for its ack, switching to the
+    // fallback transport if udp gets no answer at all
+    async fn send_with_retry<R: TypedEvent>(&self, data: &[u8], nonce: u64) -> anyhow::Result<R> {
+        let server_address = SocketAddr::from(self.server_address);
+        match self.send_with_retry_on(self.server_transport(), server_address, data, nonce).await {
+            Err(e) if self.fallback_transport.is_some() && !self.is_using_fallback() => {
+                warn!("server not reachable over udp, switching to the fallback transport: {}", e);
+                self.using_fallback.store(true, Ordering::Relaxed);
+                // the server only takes requests from an endpoint we said hello from
+                if R::EVENT_TYPE != HelloACKEvent::EVENT_TYPE {
+                    let hello_nonce = new_nonce();
+                    let hello_ack = self.send_with_retry_on::<HelloACKEvent>(self.server_transport(), server_address, &self.hello_pkg(hello_nonce)?, hello_nonce).await?;
+                    self.start_session(&hello_ack);
+                }
+                self.send_with_retry_on(self.server_transport(), server_address, data, nonce).await
+            }
+            result => result,
+        }
+    }
+    // resend on timeout, only an ack echoing `nonce` answers `data`
+    async fn send_with_retry_on<R: TypedEvent>(&self, transport: &Arc<dyn DatagramTransport>, server_address: SocketAddr, data: &[u8], nonce: u64) -> anyhow::Result<R> {
+        for attempt in 0..ACK_ATTEMPTS {
+            if attempt > 0 {
+                if let Some(hook) = &self.metrics_hook {
+                    hook.on_retransmission(R::EVENT_TYPE, attempt);
+                }
+            }
+            let subscription = self.register_ack_event(R::EVENT_TYPE, Some(nonce)).await;
+            let sent_at = Instant::now();
+            if let Err(e) = transport.send_to(data, server_address).await {
+                self.dispatcher.lock().await.unsubscribe(subscription.1);
+                return Err(e.into());
+            }
+            match self.wait_ack_event(subscription, R::EVENT_TYPE, ACK_TIMEOUT).await {
+                Ok(event) => {
+                    let response = into_response(event)?;
+                    if let Some(hook) = &self.metrics_hook {
+                        hook.on_rtt(R::EVENT_TYPE, sent_at.elapsed());
                     }
+                    return Ok(response);
                 }
+                Err(e) => debug!("attempt {} for event type {} failed: {}", attempt + 1, R::EVENT_TYPE, e),
             }
+        }
+        Err(anyhow!("event timeout after {} attempts", ACK_ATTEMPTS))
+    }
+    // wait for an `R` without sending anything, an error on timeout or denial
+    async fn subscribe_ack_event<R: TypedEvent>(&self, cancel_duration: Duration) -> anyhow::Result<R> {
+        let subscription = self.register_ack_event(R::EVENT_TYPE, None).await;
+        into_response(self.wait_ack_event(subscription, R::EVENT_TYPE, cancel_duration).await?)
+    }
+    // register before sending the request, so an early ack is not dropped
+    async fn register_ack_event(&self, event_type: u8, nonce: Option<u64>) -> (Receiver<CliEvent>, u128) {
+        let (event_tx, event_rx) = tokio::sync::mpsc::channel(1);
+        let mut dispatcher = self.dispatcher.lock().await;
+        let id = match nonce {
+            Some(nonce) => dispatcher.expect(event_tx, event_type, nonce),
+            None => dispatcher.subscribe(event_tx, event_type),
+        };
+        (event_rx, id)
+    }
+    async fn wait_ack_event(&self, subscription: (Receiver<CliEvent>, u128), event_type: u8, cancel_duration: Duration) -> anyhow::Result<CliEvent> {
+        let (mut event_rx, id) = subscription;
+        let wait_result = tokio::select! {
+            result = event_rx.recv() => result.ok_or_else(|| anyhow!("event receiver closed")),
             _ = tokio::time::sleep(cancel_duration) => {
                 warn!("event timeout: {}, event id dropped: {}", event_type, id);
-                return Err(anyhow::anyhow!("event timeout"));
+                Err(anyhow!("event timeout"))
             }
         };
-        let mut event_list = self.event_list.lock().await;
-        event_list.retain(|(_, _, event_id)| *event_id != id);
-        Ok(wait_result)
+        self.dispatcher.lock().await.unsubscribe(id);
+        wait_result
     }
 
 
     // communicate witch other peer
     // you can also send pkg to server, the server will forward it to other peer
     pub async fn pkg_send_to(&self, endpoint_addr: SocketAddr, payload: Vec<u8>, target: Option<BasePkg>) -> anyhow::Result<()> {
-        let pkg = BaseUp2pProtocol::pakge_exchange_with_payload(
+        // peers get neither our credential nor our session, the relaying server the session
+        let relayed = endpoint_addr == SocketAddr::from(self.server_address) || self.extra_server_addresses.contains(&endpoint_addr);
+        let identity = if relayed { self.session_identity() } else { String::new() };
+        let pkg = Up2pMessage::PkgExchange(
             PeerExchangePkg::new(
-                self.base_info.clone(),
+                BasePkg { identity, ..self.base_info.clone() },
                 payload,
-                target
+                target.clone()
             )
-        )?;
-        self.udp_socket.send_to(pkg.encode_to_vec()?.as_slice(), endpoint_addr).await?;
+        );
+        let transport = if relayed { self.server_transport() } else { &self.transport };
+        transport.send_to(pkg.encode_with(self.codec())?.as_slice(), endpoint_addr).await?;
+        let path_type = if relayed {
+            PathType::Relayed
+        } else if self.lan_discovery.as_ref().is_some_and(|lan_discovery| lan_discovery.is_lan_endpoint(endpoint_addr)) {
+            PathType::Lan
+        } else {
+            PathType::Direct
+        };
+        if let Some(hook) = &self.metrics_hook {
+            hook.on_path(path_type, target.as_ref());
+        }
+        if let Some(target) = &target {
+            self.record_path(crate::utils::get_global_id(&target.client_class, &target.client_instance), path_type).await;
+        }
         Ok(())
     }
 
+    // the next package from a peer, see on_message for every package
     pub async fn pkg_recv_from(&self) -> anyhow::Result<(BasePkg, Vec<u8>)> {
-        let ret = self.subscribe_ack_event(
-            EventType::P2P_PKG_EXCHANGE, Duration::from_secs(u64::MAX)
-        ).await?.expect("event is None");
-        let ret  = ret.as_any().downcast_ref::<PkgExchangeEvent>().unwrap();
-        if let Some(dst) = ret.get_dst() {
+        let message: PkgExchangeEvent = self.subscribe_ack_event(Duration::from_secs(u64::MAX)).await?;
+        if let Some(dst) = message.get_dst() {
             if dst != self.base_info {
                 warn!("Received pkg not for self");
                 return Err(anyhow!("pkf not for self"));
             }
         }
-        let data = ret.get_payload();
-        return Ok((ret.get_src(), data));
+        Ok((message.get_src(), message.get_payload()))
     }
+}
 
+// `request` from the device of `base_info`, named by `identity`
+fn encode_request(base_info: &BasePkg, identity: &str, request: Up2pRequest, nonce: u64, codec: Codec) -> anyhow::Result<Vec<u8>> {
+    Up2pMessage::Request(
+        ClientRequestPkg::new(&base_info.client_class, &base_info.client_instance, identity, request)
+            .in_namespace(&base_info.namespace)
+            .with_nonce(nonce)
+    ).encode_with(codec)
 }
 
+// a request's answer echoes its nonce, 0 stands for none
+fn new_nonce() -> u64 {
+    rand::random::<u64>().max(1)
+}
+
+// the answer a request waited for, or why there is none
+fn into_response<R: TypedEvent>(event: CliEvent) -> anyhow::Result<R> {
+    let event_type = event.get_event_type();
+    match event {
+        CliEvent::Denied(denied) => Err(anyhow!("server denied reaching {}: {}", denied.get_target_global_id(), denied.get_reason())),
+        event => R::from_event(event).ok_or_else(|| anyhow!("expected event type {}, got {}", R::EVENT_TYPE, event_type)),
+    }
+}
 
 impl GetBaseInfo for Up2pCli {
     fn get_baseinfo(&self) -> &BasePkg {
@@ -279,6 +637,63 @@ impl GetBaseInfo for Up2pCli {
     }
 }
 
+// One received package as a client event, None if it is not for us. Peer data
+// may come from anywhere, everything else only from the server and, with a
+// pinned key, signed by it.
+fn decode_event(data: &[u8], from_server: bool, server_key: Option<&PinnedServerKey>) -> Option<CliEvent> {
+    let mut message = match Up2pMessage::decode_from(data) {
+        Ok(message) => message,
+        Err(e) => {
+            warn!("decode_from_slice error: {}", e);
+            return None;
+        }
+    };
+    let signed = matches!(message, Up2pMessage::Signed(_));
+    if let Up2pMessage::Signed(signed_pkg) = &message {
+        // without a pinned key the signature goes unchecked
+        let package = match server_key.map(|server_key| server_key.open(signed_pkg)) {
+            Some(Ok(package)) => package,
+            Some(Err(e)) => {
+                warn!("dropped package with a bad server signature: {}", e);
+                return None;
+            }
+            None => signed_pkg.get_package(),
+        };
+        message = match Up2pMessage::decode_from(package) {
+            Ok(message) => message,
+            Err(e) => {
+                warn!("decode_from_slice error: {}", e);
+                return None;
+            }
+        };
+    }
+    let authenticated = from_server && (signed || server_key.is_none());
+    if !matches!(message, Up2pMessage::PkgExchange(_)) && !authenticated {
+        warn!("dropped unauthenticated package of type {}", message.get_pkg_type());
+        return None;
+    }
+    let client_event = match message {
+        Up2pMessage::HelloAck(hello_ack_pkg) => CliEvent::HelloAck(HelloACKEvent::new(hello_ack_pkg)),
+        Up2pMessage::RequestAck(client_request_ack_pkg) => CliEvent::RequestAck(RequestAckEvent::new(client_request_ack_pkg)),
+        Up2pMessage::PkgExchange(peer_exchange_pkg) => CliEvent::PkgExchange(PkgExchangeEvent::new(
+            peer_exchange_pkg.get_payload(),
+            peer_exchange_pkg.get_baseinfo().clone(),
+            peer_exchange_pkg.get_target()
+        )),
+        Up2pMessage::TcpPunch(tcp_punch_pkg) => CliEvent::TcpPunch(TcpPunchEvent::new(tcp_punch_pkg)),
+        Up2pMessage::DeviceListAck(device_list_ack_pkg) => CliEvent::DeviceListAck(DeviceListAckEvent::new(device_list_ack_pkg)),
+        Up2pMessage::Denied(denied_pkg) => CliEvent::Denied(DeniedEvent::new(denied_pkg)),
+        Up2pMessage::Presence(presence_pkg) => CliEvent::Presence(PresenceEvent::new(presence_pkg)),
+        Up2pMessage::StatusAck(status_ack_pkg) => CliEvent::StatusAck(StatusAckEvent::new(status_ack_pkg)),
+        // a signature inside a signature, or packages only servers and lan peers receive
+        message @ (Up2pMessage::Signed(_) | Up2pMessage::Hello(_) | Up2pMessage::Request(_) | Up2pMessage::LanDiscovery(_)) => {
+            warn!("unexpected pkg type: {}", message.get_pkg_type());
+            return None;
+        }
+    };
+    Some(client_event)
+}
+
 async fn handle_udp_pkg() {
     unimplemented!()
 }
\ No newline at end of file
@@ -0,0 +1,177 @@
+// Optional lan discovery. Clients announce themselves on a multicast group and
+// answer queries, so peers on the same lan find each other without the server.
+// Only packages tagged with the discovery key the peers share are trusted, the
+// identity never goes on the lan.
+use std::{collections::HashMap, io, net::{Ipv4Addr, SocketAddr, SocketAddrV4}, sync::{Arc, Mutex}, time::{Duration, Instant}};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tokio::{net::UdpSocket, task::JoinHandle};
+use tracing::{debug, warn};
+
+use crate::core::{bincodec::BinCodec, get_global_id::GetGlobalId, uprotocol_pkg::{BasePkg, LanDiscoveryPkg}, Up2pMessage};
+
+pub const DEFAULT_DISCOVERY_GROUP: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(239, 255, 77, 80), 9010);
+
+#[derive(Debug, Clone)]
+pub struct DiscoveryConfig {
+    pub group: SocketAddrV4,
+    // interface to join the group on, unspecified lets the os pick
+    pub interface: Ipv4Addr,
+    // a peer is forgotten after three intervals without an announcement
+    pub announce_interval: Duration,
+    // Shared by the devices that trust each other on the lan, required. Anyone
+    // with it can announce any device, so it is not the identity.
+    pub key: String,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            group: DEFAULT_DISCOVERY_GROUP,
+            interface: Ipv4Addr::UNSPECIFIED,
+            announce_interval: Duration::from_secs(5),
+            key: String::new(),
+        }
+    }
+}
+
+// global id -> (lan endpoint, last announcement)
+type LanPeers = Arc<Mutex<HashMap<String, (SocketAddr, Instant)>>>;
+
+pub struct LanDiscovery {
+    socket: Arc<UdpSocket>,
+    config: DiscoveryConfig,
+    announce: Vec<u8>,
+    query: Vec<u8>,
+    peers: LanPeers,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl LanDiscovery {
+    // announce `base_info` as reachable on `endpoint_port` and start listening
+    pub async fn start(config: DiscoveryConfig, base_info: BasePkg, endpoint_port: u16) -> io::Result<Self> {
+        if config.key.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "lan discovery needs a key"));
+        }
+        let socket = Arc::new(bind_multicast(&config)?);
+        let encode = |msg| {
+            let discovery_pkg = LanDiscoveryPkg::new(base_info.clone(), msg, endpoint_port);
+            let tag = discovery_tag(&config.key, &discovery_pkg)?.finalize().into_bytes().to_vec();
+            Up2pMessage::LanDiscovery(discovery_pkg.with_tag(tag))
+                .encode_to_vec()
+                .map_err(io::Error::other)
+        };
+        let (announce, query) = (encode(LanDiscoveryPkg::MSG_ANNOUNCE)?, encode(LanDiscoveryPkg::MSG_QUERY)?);
+        let peers = LanPeers::default();
+        let listening = {
+            let (socket, peers, announce, group, key) = (socket.clone(), peers.clone(), announce.clone(), config.group, config.key.clone());
+            tokio::spawn(async move {
+                let mut buf = [0u8; 1500];
+                loop {
+                    let (len, from) = match socket.recv_from(&mut buf).await {
+                        Ok(received) => received,
+                        Err(e) => {
+                            warn!("lan discovery recv error: {}", e);
+                            continue;
+                        }
+                    };
+                    let Some(discovery_pkg) = decode_discovery(&buf[..len]) else {
+                        continue;
+                    };
+                    let peer_info = discovery_pkg.get_peer();
+                    // our own announcements loop back, strangers and other tenants are ignored
+                    let trusted = discovery_tag(&key, &discovery_pkg).is_ok_and(|tag| tag.verify_slice(discovery_pkg.get_tag()).is_ok());
+                    if peer_info == base_info || !trusted || peer_info.namespace != base_info.namespace {
+                        continue;
+                    }
+                    let endpoint = SocketAddr::new(from.ip(), discovery_pkg.get_endpoint_port());
+                    debug!("lan peer {} at {}", peer_info.get_global_id(), endpoint);
+                    // keyed as resolve is asked, without the namespace
+                    let global_id = crate::utils::get_global_id(&peer_info.client_class, &peer_info.client_instance);
+                    peers.lock().unwrap().insert(global_id, (endpoint, Instant::now()));
+                    if discovery_pkg.get_msg() == LanDiscoveryPkg::MSG_QUERY {
+                        if let Err(e) = socket.send_to(&announce, group).await {
+                            warn!("lan discovery announce error: {}", e);
+                        }
+                    }
+                }
+            })
+        };
+        let announcing = {
+            let (socket, announce, group, interval) = (socket.clone(), announce.clone(), config.group, config.announce_interval);
+            tokio::spawn(async move {
+                let mut ticker = tokio::time::interval(interval);
+                loop {
+                    ticker.tick().await;
+                    if let Err(e) = socket.send_to(&announce, group).await {
+                        warn!("lan discovery announce error: {}", e);
+                    }
+                }
+            })
+        };
+        let discovery = Self {
+            socket,
+            config,
+            announce,
+            query,
+            peers,
+            tasks: vec![listening, announcing],
+        };
+        // peers already running announce themselves right away
+        discovery.query().await?;
+        Ok(discovery)
+    }
+    // the lan endpoint of `global_id`, if it announced itself recently
+    pub fn resolve(&self, global_id: &str) -> Option<SocketAddr> {
+        let ttl = self.config.announce_interval * 3;
+        let mut peers = self.peers.lock().unwrap();
+        peers.retain(|_, (_, seen)| seen.elapsed() < ttl);
+        peers.get(global_id).map(|(endpoint, _)| *endpoint)
+    }
+    pub fn is_lan_endpoint(&self, endpoint: SocketAddr) -> bool {
+        self.peers.lock().unwrap().values().any(|(lan_endpoint, _)| *lan_endpoint == endpoint)
+    }
+    pub async fn query(&self) -> io::Result<()> {
+        self.socket.send_to(&self.query, self.config.group).await?;
+        Ok(())
+    }
+    pub async fn announce(&self) -> io::Result<()> {
+        self.socket.send_to(&self.announce, self.config.group).await?;
+        Ok(())
+    }
+}
+
+impl Drop for LanDiscovery {
+    fn drop(&mut self) {
+        self.tasks.iter().for_each(JoinHandle::abort);
+    }
+}
+
+// HMAC-SHA256 over the package without its tag, finalize it for the tag or verify one
+fn discovery_tag(key: &str, discovery_pkg: &LanDiscoveryPkg) -> io::Result<Hmac<Sha256>> {
+    let mut tag = Hmac::<Sha256>::new_from_slice(key.as_bytes()).map_err(io::Error::other)?;
+    tag.update(&discovery_pkg.untagged().encode_to_vec().map_err(io::Error::other)?);
+    Ok(tag)
+}
+
+fn decode_discovery(data: &[u8]) -> Option<LanDiscoveryPkg> {
+    match Up2pMessage::decode_from(data).ok()? {
+        Up2pMessage::LanDiscovery(lan_discovery_pkg) => Some(lan_discovery_pkg),
+        _ => None,
+    }
+}
+
+// every client on the host shares the group port
+fn bind_multicast(config: &DiscoveryConfig) -> io::Result<UdpSocket> {
+    let socket = socket2::Socket::new(socket2::Domain::IPV4, socket2::Type::DGRAM, Some(socket2::Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, config.group.port())).into())?;
+    socket.join_multicast_v4(config.group.ip(), &config.interface)?;
+    socket.set_multicast_if_v4(&config.interface)?;
+    socket.set_multicast_loop_v4(true)?;
+    socket.set_nonblocking(true)?;
+    UdpSocket::from_std(socket.into())
+}
@@ -0,0 +1,234 @@
+use std::{collections::{HashMap, VecDeque}, time::{Duration, Instant}};
+
+use tokio::sync::mpsc::Sender;
+use tracing::{debug, warn};
+
+use crate::transport::stream::PUNCH_TIMEOUT;
+
+use super::event::{CliEvent, EventType};
+
+// broadcast events of one type kept while nobody subscribed to it, the oldest go first
+pub(crate) const EARLY_EVENT_BUFFER: usize = 64;
+
+// How long a broadcast event waits for its type's first subscriber, None if it is
+// only for those listening when it comes, like presence events and path changes.
+fn early_event_ttl(event_type: u8) -> Option<Duration> {
+    match event_type {
+        EventType::P2P_PKG_EXCHANGE => Some(Duration::MAX),
+        // the peer gives up punching after PUNCH_TIMEOUT
+        EventType::TCP_PUNCH => Some(PUNCH_TIMEOUT),
+        _ => None,
+    }
+}
+
+// (event sender, subscribed event type, subscriber id, nonce of the request waiting, None for everyone else)
+pub(crate) type EventSubscriber = (Sender<CliEvent>, u8, u128, Option<u64>);
+
+// Hands the client's events to whoever subscribed. An answer belongs to the one
+// request with its nonce. Everything else is broadcast to every subscriber of its
+// type that waits for no request, or, for the types early_event_ttl keeps, kept for
+// the next one to subscribe when there is none yet.
+#[derive(Default)]
+pub(crate) struct EventDispatcher {
+    subscribers: Vec<EventSubscriber>,
+    // by event type, with when each event came
+    early_events: HashMap<u8, VecDeque<(Instant, CliEvent)>>,
+}
+
+impl EventDispatcher {
+    pub(crate) fn subscribe(&mut self, event_tx: Sender<CliEvent>, event_type: u8) -> u128 {
+        self.subscribe_with(event_tx, event_type, None)
+    }
+    // the answer to the request sent with `nonce`
+    pub(crate) fn expect(&mut self, event_tx: Sender<CliEvent>, event_type: u8, nonce: u64) -> u128 {
+        self.subscribe_with(event_tx, event_type, Some(nonce))
+    }
+    // a new subscriber starts with the events of its type that came early, as many as it has room for,
+    // a request only with its answer
+    fn subscribe_with(&mut self, event_tx: Sender<CliEvent>, event_type: u8, nonce: Option<u64>) -> u128 {
+        let id = rand::random::<u128>();
+        if nonce.is_none() {
+            self.expire_early_events(event_type);
+            if let Some(early_events) = self.early_events.get_mut(&event_type) {
+                while event_tx.capacity() > 0 {
+                    let Some((_, event)) = early_events.pop_front() else {
+                        break;
+                    };
+                    if let Err(e) = event_tx.try_send(event) {
+                        warn!("early event dropped: {}", e);
+                    }
+                }
+            }
+        }
+        self.subscribers.push((event_tx, event_type, id, nonce));
+        id
+    }
+    pub(crate) fn unsubscribe(&mut self, id: u128) {
+        self.subscribers.retain(|(_, _, event_id, _)| *event_id != id);
+    }
+    pub(crate) fn dispatch(&mut self, event: CliEvent) {
+        // a waiter that was cancelled (e.g. by a timeout) leaves a closed sender behind,
+        // drop those so they can't swallow the event
+        self.subscribers.retain(|(event_tx, _, _, _)| !event_tx.is_closed());
+        match event {
+            // nothing waits for a relay, the sender only learns it from the log
+            CliEvent::Denied(denied) if denied.get_denied_type() == EventType::DENIED => {
+                warn!("server denied reaching {}: {}", denied.get_target_global_id(), denied.get_reason());
+            }
+            // a denial answers the request waiting for the type it stands in for
+            CliEvent::HelloAck(_) | CliEvent::RequestAck(_) | CliEvent::StatusAck(_) | CliEvent::DeviceListAck(_) | CliEvent::Denied(_) => self.answer(event),
+            // the requester's copy of a punch answers its tcp_punch, the peer's carries no nonce
+            CliEvent::TcpPunch(_) if event.get_nonce() != 0 => self.answer(event),
+            CliEvent::PkgExchange(_) | CliEvent::TcpPunch(_) | CliEvent::Presence(_) | CliEvent::PathChange(_) => self.broadcast(event),
+        }
+    }
+    fn answer(&mut self, event: CliEvent) {
+        let event_type = event.get_event_type();
+        let nonce = event.get_nonce();
+        match self.subscribers.iter().find(|(_, subscribed_type, _, expected)| *subscribed_type == event_type && *expected == Some(nonce)) {
+            Some((event_tx, _, _, _)) => {
+                if let Err(e) = event_tx.try_send(event) {
+                    warn!("send event error: {}", e);
+                }
+            }
+            // late, the request already gave up, or an answer to a request never sent
+            None => debug!("no request waiting for event type {} with nonce {}", event_type, nonce),
+        }
+    }
+    fn broadcast(&mut self, event: CliEvent) {
+        let event_type = event.get_event_type();
+        let mut subscribers = self.subscribers.iter().filter(|(_, subscribed_type, _, expected)| *subscribed_type == event_type && expected.is_none()).peekable();
+        if subscribers.peek().is_none() {
+            self.keep_early(event, Instant::now());
+            return;
+        }
+        for (event_tx, _, _, _) in subscribers {
+            // a stream nobody polls must not stall the others
+            if let Err(e) = event_tx.try_send(event.clone()) {
+                warn!("event of type {} dropped: {}", event_type, e);
+            }
+        }
+    }
+    fn keep_early(&mut self, event: CliEvent, received_at: Instant) {
+        let event_type = event.get_event_type();
+        if early_event_ttl(event_type).is_none() {
+            debug!("nobody subscribed to event type {}, dropped", event_type);
+            return;
+        }
+        self.expire_early_events(event_type);
+        let early_events = self.early_events.entry(event_type).or_default();
+        if early_events.len() == EARLY_EVENT_BUFFER {
+            warn!("early event buffer of type {} full, dropping the oldest event", event_type);
+            early_events.pop_front();
+        }
+        early_events.push_back((received_at, event));
+    }
+    fn expire_early_events(&mut self, event_type: u8) {
+        let (Some(ttl), Some(early_events)) = (early_event_ttl(event_type), self.early_events.get_mut(&event_type)) else {
+            return;
+        };
+        early_events.retain(|(received_at, _)| received_at.elapsed() < ttl);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Instant;
+
+    use crate::{client_lib::{event::{CliEvent, DeniedEvent, EventType, PathChangeEvent, PkgExchangeEvent, RequestAckEvent, TcpPunchEvent, TypedEvent}, metrics::PathType}, core::uprotocol_pkg::{BasePkg, ClientRequestAckPkg, DeniedPkg, TcpPunchPkg}, transport::stream::PUNCH_TIMEOUT};
+
+    use super::{EventDispatcher, EARLY_EVENT_BUFFER};
+
+    fn message(payload: u8) -> CliEvent {
+        CliEvent::from(PkgExchangeEvent::new(vec![payload], BasePkg { client_class: "cli".to_string(), client_instance: "peer1".to_string(), identity: "test".to_string(), namespace: String::new() }, None))
+    }
+
+    fn payload(event: Option<CliEvent>) -> u8 {
+        PkgExchangeEvent::from_event(event.unwrap()).unwrap().get_payload()[0]
+    }
+
+    #[tokio::test]
+    async fn test_event_dispatcher() {
+        let mut dispatcher = EventDispatcher::default();
+        // early messages wait for the first subscriber, the oldest are dropped beyond the buffer
+        for i in 0..=EARLY_EVENT_BUFFER {
+            dispatcher.dispatch(message(i as u8));
+        }
+        let (early_tx, mut early_rx) = tokio::sync::mpsc::channel(EARLY_EVENT_BUFFER);
+        dispatcher.subscribe(early_tx, EventType::P2P_PKG_EXCHANGE);
+        assert_eq!(payload(early_rx.recv().await), 1);
+        assert_eq!(early_rx.len(), EARLY_EVENT_BUFFER - 1);
+
+        // every subscriber sees every message
+        let (other_tx, mut other_rx) = tokio::sync::mpsc::channel(1);
+        let other = dispatcher.subscribe(other_tx, EventType::P2P_PKG_EXCHANGE);
+        while early_rx.try_recv().is_ok() {}
+        dispatcher.dispatch(message(100));
+        assert_eq!(payload(early_rx.recv().await), 100);
+        assert_eq!(payload(other_rx.recv().await), 100);
+        dispatcher.unsubscribe(other);
+        dispatcher.dispatch(message(101));
+        assert!(other_rx.try_recv().is_err());
+
+        // an answer goes to the request with its nonce only and is not kept
+        let (first_tx, mut first_rx) = tokio::sync::mpsc::channel(1);
+        let (second_tx, mut second_rx) = tokio::sync::mpsc::channel(1);
+        dispatcher.expect(first_tx, EventType::REQUEST_ACK, 1);
+        dispatcher.expect(second_tx, EventType::REQUEST_ACK, 2);
+        let request_ack = |nonce| CliEvent::from(RequestAckEvent::new(ClientRequestAckPkg::new("127.0.0.1:9000".to_string()).answering("cli-peer2".to_string(), nonce)));
+        dispatcher.dispatch(request_ack(3));
+        dispatcher.dispatch(request_ack(2));
+        assert!(second_rx.recv().await.is_some());
+        assert!(first_rx.try_recv().is_err());
+
+        // a denial is the answer, the request can't mistake it for an ack
+        dispatcher.dispatch(CliEvent::from(DeniedEvent::new(DeniedPkg::new(EventType::REQUEST_ACK, "cli-peer2".to_string(), "acl").with_nonce(1))));
+        let denied = first_rx.recv().await.unwrap();
+        assert_eq!(denied.get_event_type(), EventType::REQUEST_ACK);
+        assert!(RequestAckEvent::from_event(denied.clone()).is_none());
+        assert_eq!(DeniedEvent::from_event(denied).unwrap().get_reason(), "acl");
+
+        // a punch is the answer to our tcp_punch when it has its nonce, a peer's punch otherwise
+        let punch = |nonce| CliEvent::from(TcpPunchEvent::new(TcpPunchPkg::new("cli-peer2".to_string(), "127.0.0.1:9000".to_string()).with_nonce(nonce)));
+        dispatcher.dispatch(punch(0));
+        let (requester_tx, mut requester_rx) = tokio::sync::mpsc::channel(1);
+        dispatcher.expect(requester_tx, EventType::TCP_PUNCH, 4);
+        let (acceptor_tx, mut acceptor_rx) = tokio::sync::mpsc::channel(2);
+        dispatcher.subscribe(acceptor_tx, EventType::TCP_PUNCH);
+        assert!(requester_rx.try_recv().is_err());
+        assert_eq!(acceptor_rx.recv().await.unwrap().get_nonce(), 0);
+        dispatcher.dispatch(punch(4));
+        assert_eq!(requester_rx.recv().await.unwrap().get_nonce(), 4);
+        assert!(acceptor_rx.try_recv().is_err());
+        dispatcher.dispatch(CliEvent::from(DeniedEvent::new(DeniedPkg::new(EventType::TCP_PUNCH, "cli-peer2".to_string(), "acl").with_nonce(4))));
+        assert!(DeniedEvent::from_event(requester_rx.recv().await.unwrap()).is_some());
+        assert!(acceptor_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_early_events_by_type() {
+        let mut dispatcher = EventDispatcher::default();
+        // path changes nobody listens to are not kept and push out no waiting message
+        dispatcher.dispatch(message(1));
+        for _ in 0..=EARLY_EVENT_BUFFER {
+            dispatcher.dispatch(CliEvent::from(PathChangeEvent::new("cli-peer2".to_string(), PathType::Relayed)));
+        }
+        dispatcher.dispatch(message(2));
+        let (path_tx, mut path_rx) = tokio::sync::mpsc::channel(1);
+        dispatcher.subscribe(path_tx, EventType::PATH_CHANGE);
+        assert!(path_rx.try_recv().is_err());
+        let (message_tx, mut message_rx) = tokio::sync::mpsc::channel(EARLY_EVENT_BUFFER);
+        dispatcher.subscribe(message_tx, EventType::P2P_PKG_EXCHANGE);
+        assert_eq!(payload(message_rx.recv().await), 1);
+        assert_eq!(payload(message_rx.recv().await), 2);
+
+        // a peer's punch waits only while the peer still punches
+        let punch = |peer: &str| CliEvent::from(TcpPunchEvent::new(TcpPunchPkg::new(peer.to_string(), "127.0.0.1:9000".to_string())));
+        dispatcher.keep_early(punch("cli-stale"), Instant::now().checked_sub(PUNCH_TIMEOUT).unwrap());
+        dispatcher.keep_early(punch("cli-fresh"), Instant::now());
+        let (acceptor_tx, mut acceptor_rx) = tokio::sync::mpsc::channel(2);
+        dispatcher.subscribe(acceptor_tx, EventType::TCP_PUNCH);
+        assert_eq!(TcpPunchEvent::from_event(acceptor_rx.recv().await.unwrap()).unwrap().get_peer_global_id(), "cli-fresh");
+        assert!(acceptor_rx.try_recv().is_err());
+    }
+}
@@ -1,10 +1,86 @@
-use std::any::Any;
+use crate::{client_lib::metrics::PathType, core::{protocol_version::ProtocolVersion, uprotocol_pkg::{BasePkg, ClientRequestAckPkg, DeniedPkg, DeviceListAckPkg, DeviceStatusPkg, HelloAckPkg, PresencePkg, StatusAckPkg, TcpPunchPkg}, BaseUp2pProtocol}};
 
-use crate::core::{uprotocol_pkg::{BasePkg, ClientRequestAckPkg}, BaseUp2pProtocol};
+// Everything the client receives or raises, one variant per event type. A new
+// event type has to be handled wherever events are matched.
+#[derive(Debug, Clone)]
+pub enum CliEvent {
+    HelloAck(HelloACKEvent),
+    RequestAck(RequestAckEvent),
+    PkgExchange(PkgExchangeEvent),
+    TcpPunch(TcpPunchEvent),
+    StatusAck(StatusAckEvent),
+    DeviceListAck(DeviceListAckEvent),
+    Presence(PresenceEvent),
+    Denied(DeniedEvent),
+    PathChange(PathChangeEvent),
+}
+
+impl CliEvent {
+    // a denial has the type of the event it stands in for
+    pub fn get_event_type(&self) -> u8 {
+        match self {
+            CliEvent::HelloAck(_) => HelloACKEvent::EVENT_TYPE,
+            CliEvent::RequestAck(_) => RequestAckEvent::EVENT_TYPE,
+            CliEvent::PkgExchange(_) => PkgExchangeEvent::EVENT_TYPE,
+            CliEvent::TcpPunch(_) => TcpPunchEvent::EVENT_TYPE,
+            CliEvent::StatusAck(_) => StatusAckEvent::EVENT_TYPE,
+            CliEvent::DeviceListAck(_) => DeviceListAckEvent::EVENT_TYPE,
+            CliEvent::Presence(_) => PresenceEvent::EVENT_TYPE,
+            CliEvent::Denied(denied) => denied.get_denied_type(),
+            CliEvent::PathChange(_) => PathChangeEvent::EVENT_TYPE,
+        }
+    }
+    // the nonce of the request an answer is for, 0 for everything else
+    pub fn get_nonce(&self) -> u64 {
+        match self {
+            CliEvent::HelloAck(hello_ack) => hello_ack.nonce,
+            CliEvent::RequestAck(request_ack) => request_ack.nonce,
+            CliEvent::TcpPunch(tcp_punch) => tcp_punch.nonce,
+            CliEvent::StatusAck(status_ack) => status_ack.nonce,
+            CliEvent::DeviceListAck(device_list_ack) => device_list_ack.nonce,
+            CliEvent::Denied(denied) => denied.nonce,
+            CliEvent::PkgExchange(_) | CliEvent::Presence(_) | CliEvent::PathChange(_) => 0,
+        }
+    }
+}
+
+// The event carried by one CliEvent variant, e.g. the answer a request waits for.
+pub trait TypedEvent: Sized {
+    const EVENT_TYPE: u8;
+    // None if `event` is another variant
+    fn from_event(event: CliEvent) -> Option<Self>;
+}
+
+macro_rules! impl_typed_event {
+    ($($event:ty => $variant:ident, $event_type:expr),* $(,)?) => {
+        $(impl TypedEvent for $event {
+            const EVENT_TYPE: u8 = $event_type;
+            fn from_event(event: CliEvent) -> Option<Self> {
+                match event {
+                    CliEvent::$variant(event) => Some(event),
+                    _ => None,
+                }
+            }
+        }
+
+        impl From<$event> for CliEvent {
+            fn from(event: $event) -> Self {
+                CliEvent::$variant(event)
+            }
+        })*
+    };
+}
 
-pub trait CliEvent: Send + Sync + Any + 'static {
-    fn get_event_type(&self) -> u8;
-    fn as_any(&self) -> &dyn Any;
+impl_typed_event! {
+    HelloACKEvent => HelloAck, EventType::HELLO_ACK,
+    RequestAckEvent => RequestAck, EventType::REQUEST_ACK,
+    PkgExchangeEvent => PkgExchange, EventType::P2P_PKG_EXCHANGE,
+    TcpPunchEvent => TcpPunch, EventType::TCP_PUNCH,
+    StatusAckEvent => StatusAck, EventType::STATUS_ACK,
+    DeviceListAckEvent => DeviceListAck, EventType::DEVICE_LIST_ACK,
+    PresenceEvent => Presence, EventType::PRESENCE,
+    DeniedEvent => Denied, EventType::DENIED,
+    PathChangeEvent => PathChange, EventType::PATH_CHANGE,
 }
 
 pub struct EventType;
@@ -12,59 +88,72 @@ impl EventType {
     pub const HELLO_ACK: u8 = BaseUp2pProtocol::TYPE_HELLO_ACK;
     pub const REQUEST_ACK: u8 = BaseUp2pProtocol::TYPE_REQUEST_ACK;
     pub const P2P_PKG_EXCHANGE: u8 = BaseUp2pProtocol::TYPE_PKG_EXCHANGE;
+    pub const TCP_PUNCH: u8 = BaseUp2pProtocol::TYPE_TCP_PUNCH;
+    pub const STATUS_ACK: u8 = BaseUp2pProtocol::TYPE_STATUS_ACK;
+    pub const DEVICE_LIST_ACK: u8 = BaseUp2pProtocol::TYPE_DEVICE_LIST_ACK;
+    pub const PRESENCE: u8 = BaseUp2pProtocol::TYPE_PRESENCE;
+    pub const DENIED: u8 = BaseUp2pProtocol::TYPE_DENIED;
+    // raised by the client itself, outside the range of package types
+    pub const PATH_CHANGE: u8 = 0x80;
 }
 
-#[derive(Debug)]
-pub struct HelloACKEvent;
+// what the server speaks, see ProtocolVersion, and the session it gave us
+#[derive(Debug, Clone)]
+pub struct HelloACKEvent {
+    protocol: ProtocolVersion,
+    nonce: u64,
+    session: String,
+}
 
-impl CliEvent for HelloACKEvent {
-    fn get_event_type(&self) -> u8 {
-        EventType::HELLO_ACK as u8
+impl HelloACKEvent {
+    pub fn new(hello_ack_pkg: HelloAckPkg) -> Self {
+        Self { protocol: hello_ack_pkg.get_protocol(), nonce: hello_ack_pkg.get_nonce(), session: hello_ack_pkg.get_session() }
+    }
+    pub fn get_protocol(&self) -> ProtocolVersion {
+        self.protocol
     }
-    fn as_any(&self) -> &dyn Any {
-        self
+    // empty from servers without SESSION
+    pub fn get_session(&self) -> String {
+        self.session.clone()
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct RequestAckEvent {
     endpoint_address: String,
-}
-
-impl CliEvent for RequestAckEvent {
-    fn get_event_type(&self) -> u8 {
-        EventType::REQUEST_ACK as u8
-    }
-    fn as_any(&self) -> &dyn Any {
-        self
-    }
+    endpoint_addresses: Vec<String>,
+    global_id: String,
+    nonce: u64,
 }
 
 impl RequestAckEvent {
     pub fn new(client_request_ack_pkg: ClientRequestAckPkg) -> Self {
-        Self { endpoint_address: client_request_ack_pkg.get_endpoint_address() }
+        Self {
+            endpoint_address: client_request_ack_pkg.get_endpoint_address(),
+            endpoint_addresses: client_request_ack_pkg.get_endpoint_addresses(),
+            global_id: client_request_ack_pkg.get_global_id(),
+            nonce: client_request_ack_pkg.get_nonce(),
+        }
+    }
+    // the device whose endpoints these are
+    pub fn get_global_id(&self) -> String {
+        self.global_id.clone()
     }
     pub fn get_result_endpoint_address(&self) -> String {
         self.endpoint_address.clone()
     }
+    pub fn get_result_endpoint_addresses(&self) -> Vec<String> {
+        self.endpoint_addresses.clone()
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct PkgExchangeEvent {
     payload: Vec<u8>,
     src: BasePkg,
     dst: Option<BasePkg>
 }
 
-impl CliEvent for PkgExchangeEvent {
-    fn get_event_type(&self) -> u8 {
-        EventType::P2P_PKG_EXCHANGE as u8
-    }
-    fn as_any(&self) -> &dyn Any {
-        self
-    }
-}
-
 impl PkgExchangeEvent {
     pub fn get_payload(&self) -> Vec<u8> {
         self.payload.clone()
@@ -78,4 +167,158 @@ impl PkgExchangeEvent {
     pub fn new(payload: Vec<u8>, src: BasePkg, dst: Option<BasePkg>) -> Self {
         Self { payload, src, dst }
     }
-}
\ No newline at end of file
+}
+#[derive(Debug, Clone)]
+pub struct TcpPunchEvent {
+    peer_global_id: String,
+    endpoint_address: String,
+    nonce: u64,
+}
+
+impl TcpPunchEvent {
+    pub fn new(tcp_punch_pkg: TcpPunchPkg) -> Self {
+        Self {
+            peer_global_id: tcp_punch_pkg.get_peer_global_id(),
+            endpoint_address: tcp_punch_pkg.get_endpoint_address(),
+            nonce: tcp_punch_pkg.get_nonce(),
+        }
+    }
+    pub fn get_peer_global_id(&self) -> String {
+        self.peer_global_id.clone()
+    }
+    pub fn get_endpoint_address(&self) -> String {
+        self.endpoint_address.clone()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StatusAckEvent {
+    statuses: Vec<DeviceStatusPkg>,
+    nonce: u64,
+}
+
+impl StatusAckEvent {
+    pub fn new(status_ack_pkg: StatusAckPkg) -> Self {
+        Self {
+            statuses: status_ack_pkg.get_statuses(),
+            nonce: status_ack_pkg.get_nonce(),
+        }
+    }
+    pub fn get_statuses(&self) -> Vec<DeviceStatusPkg> {
+        self.statuses.clone()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DeviceListAckEvent {
+    result: u8,
+    devices: Vec<DeviceStatusPkg>,
+    next_cursor: Option<String>,
+    nonce: u64,
+}
+
+impl DeviceListAckEvent {
+    pub fn new(device_list_ack_pkg: DeviceListAckPkg) -> Self {
+        Self {
+            result: device_list_ack_pkg.get_result(),
+            devices: device_list_ack_pkg.get_devices(),
+            next_cursor: device_list_ack_pkg.get_next_cursor(),
+            nonce: device_list_ack_pkg.get_nonce(),
+        }
+    }
+    pub fn is_denied(&self) -> bool {
+        self.result == DeviceListAckPkg::RESULT_DENIED
+    }
+    pub fn get_devices(&self) -> Vec<DeviceStatusPkg> {
+        self.devices.clone()
+    }
+    pub fn get_next_cursor(&self) -> Option<String> {
+        self.next_cursor.clone()
+    }
+}
+
+// a device we subscribed to came online, moved or went offline, see PresencePkg
+#[derive(Debug, Clone)]
+pub struct PresenceEvent {
+    event: u8,
+    client_class: String,
+    status: DeviceStatusPkg,
+    endpoint_addresses: Vec<String>,
+}
+
+impl PresenceEvent {
+    pub fn new(presence_pkg: PresencePkg) -> Self {
+        Self {
+            event: presence_pkg.get_event(),
+            client_class: presence_pkg.get_client_class(),
+            status: presence_pkg.get_status(),
+            endpoint_addresses: presence_pkg.get_endpoint_addresses(),
+        }
+    }
+    // one of PresencePkg::EVENT_*
+    pub fn get_event(&self) -> u8 {
+        self.event
+    }
+    pub fn get_global_id(&self) -> String {
+        self.status.get_global_id()
+    }
+    pub fn get_client_class(&self) -> String {
+        self.client_class.clone()
+    }
+    pub fn get_status(&self) -> DeviceStatusPkg {
+        self.status.clone()
+    }
+    pub fn get_endpoint_addresses(&self) -> Vec<String> {
+        self.endpoint_addresses.clone()
+    }
+}
+
+// The server refused a request or relay. Delivered as the event the request
+// waits for, so the wait ends with an error instead of a timeout.
+#[derive(Debug, Clone)]
+pub struct DeniedEvent {
+    denied_type: u8,
+    target_global_id: String,
+    reason: String,
+    nonce: u64,
+}
+
+impl DeniedEvent {
+    pub fn new(denied_pkg: DeniedPkg) -> Self {
+        Self {
+            denied_type: denied_pkg.get_denied_type(),
+            target_global_id: denied_pkg.get_target_global_id(),
+            reason: denied_pkg.get_reason(),
+            nonce: denied_pkg.get_nonce(),
+        }
+    }
+    // the type of the event the denial stands in for
+    pub fn get_denied_type(&self) -> u8 {
+        self.denied_type
+    }
+    pub fn get_target_global_id(&self) -> String {
+        self.target_global_id.clone()
+    }
+    pub fn get_reason(&self) -> String {
+        self.reason.clone()
+    }
+}
+
+// packages to a peer started taking another path, e.g. direct after a tcp punch
+#[derive(Debug, Clone)]
+pub struct PathChangeEvent {
+    global_id: String,
+    path_type: PathType,
+}
+
+impl PathChangeEvent {
+    pub fn new(global_id: String, path_type: PathType) -> Self {
+        Self { global_id, path_type }
+    }
+    pub fn get_global_id(&self) -> String {
+        self.global_id.clone()
+    }
+    pub fn get_path_type(&self) -> PathType {
+        self.path_type
+    }
+}
@@ -0,0 +1,21 @@
+use std::time::Duration;
+
+use crate::core::uprotocol_pkg::BasePkg;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathType {
+    // sent straight to the peer endpoint
+    Direct,
+    // sent to the server, which forwards it to the target
+    Relayed,
+    // sent straight to a peer found by lan discovery
+    Lan,
+}
+
+// Optional hook for exporting client side metrics, every method defaults to a no-op.
+// `event_type` is the `EventType` the client was waiting for.
+pub trait CliMetricsHook: Send + Sync {
+    fn on_rtt(&self, _event_type: u8, _rtt: Duration) {}
+    fn on_retransmission(&self, _event_type: u8, _attempt: u32) {}
+    fn on_path(&self, _path_type: PathType, _target: Option<&BasePkg>) {}
+}
@@ -1,2 +1,5 @@
 pub mod app;
-pub mod event;
\ No newline at end of file
+pub mod discovery;
+mod dispatch;
+pub mod event;
+pub mod metrics;
\ No newline at end of file
@@ -1,38 +1,80 @@
-use bincode::{config::Configuration, Decode, Encode};
+use bincode::{Decode, Encode};
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::codec::Codec;
 
 pub trait BinCodec<T> {
-    fn decode_from(data: &[u8]) -> anyhow::Result<T>;
-    fn encode_to_vec(&self) -> anyhow::Result<Vec<u8>>;
+    fn decode_with(codec: Codec, data: &[u8]) -> anyhow::Result<T>;
+    fn encode_with(&self, codec: Codec) -> anyhow::Result<Vec<u8>>;
+    // bincode, which every deployment speaks
+    fn decode_from(data: &[u8]) -> anyhow::Result<T> {
+        Self::decode_with(Codec::Bincode, data)
+    }
+    fn encode_to_vec(&self) -> anyhow::Result<Vec<u8>> {
+        self.encode_with(Codec::Bincode)
+    }
 }
 
 impl<T> BinCodec<T> for T
 where
-T: Encode + Decode<()>
+T: Encode + Decode<()> + Serialize + DeserializeOwned
 {
-    fn encode_to_vec(&self) -> anyhow::Result<Vec<u8>> {
-        let s = bincode::encode_to_vec(self, crate::get_binencode_config())?;
-        Ok(s)
+    fn encode_with(&self, codec: Codec) -> anyhow::Result<Vec<u8>> {
+        codec.encode(self)
     }
 
-    fn decode_from(data: &[u8]) -> anyhow::Result<T> {
-        let s = bincode::decode_from_slice::<Self, Configuration>(data, crate::get_binencode_config())?;
-        Ok(s.0)
+    // the whole of `data` must be one package, see core::wire for the errors
+    fn decode_with(codec: Codec, data: &[u8]) -> anyhow::Result<T> {
+        codec.decode(data)
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::core::{bincodec::BinCodec as _, uprotocol_pkg::ClientHelloPkg, BaseUp2pProtocol};
+    use crate::core::{bincodec::BinCodec as _, uprotocol_pkg::{ClientHelloPkg, DeviceListAckPkg, PeerExchangePkg, PresencePkg, StatusQueryPkg}, wire::WireError, BaseUp2pProtocol, Up2pMessage};
 
     #[tokio::test]
     async fn test_bin_codec() {
-        let base_protocol_pkg = BaseUp2pProtocol::client_hello_with_payload(
+        let base_protocol_pkg = Up2pMessage::Hello(
             ClientHelloPkg::new("client_class", "client_instance", "identity", 0x00)
-        ).unwrap();
+        );
         let data = base_protocol_pkg.encode_to_vec().unwrap();
         let base_protocol_pkg2 = BaseUp2pProtocol::decode_from(&data).unwrap();
         println!("base_protocol_pkg: {:?} encode -> {:?}", base_protocol_pkg, data);
         assert_eq!(base_protocol_pkg.get_pkg_type(), base_protocol_pkg2.get_pkg_type());
-        assert_eq!(base_protocol_pkg.get_payload(), base_protocol_pkg2.get_payload());
+        assert_eq!(BaseUp2pProtocol::new(base_protocol_pkg2.get_pkg_type(), base_protocol_pkg2.get_payload().to_vec()).unwrap().encode_to_vec().unwrap(), data);
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_malformed_packages() {
+        let wire_error = |result: anyhow::Result<StatusQueryPkg>| result.unwrap_err().downcast::<WireError>().unwrap();
+        let mut query = StatusQueryPkg::new(vec!["cli-peer1".to_string()]).encode_to_vec().unwrap();
+        assert_eq!(wire_error(StatusQueryPkg::decode_from(&query[..4])), WireError::Truncated);
+        query.push(0);
+        assert_eq!(wire_error(StatusQueryPkg::decode_from(&query)), WireError::TrailingBytes(1));
+        // 0xff is a reserved varint marker
+        assert_eq!(wire_error(StatusQueryPkg::decode_from(&[0xff])), WireError::MalformedVarint);
+        // a length of u64::MAX is refused before anything is allocated for it
+        let huge = [&[0xfd][..], &u64::MAX.to_le_bytes()].concat();
+        assert_eq!(wire_error(StatusQueryPkg::decode_from(&huge)), WireError::LimitExceeded);
+    }
+
+    // what the fuzz targets do at length, short enough for every test run
+    #[tokio::test]
+    async fn test_decode_random_bytes() {
+        let hello = ClientHelloPkg::new("cli", "peer1", "test", ClientHelloPkg::MSG_HELLO).encode_to_vec().unwrap();
+        for _ in 0..1000 {
+            let mut data = hello.clone();
+            for _ in 0..rand::random_range(1..4) {
+                let at = rand::random_range(0..data.len());
+                data[at] = rand::random();
+            }
+            data.truncate(rand::random_range(0..=data.len()));
+            let _ = ClientHelloPkg::decode_from(&data);
+            let _ = DeviceListAckPkg::decode_from(&data);
+            let _ = PresencePkg::decode_from(&data);
+            let _ = PeerExchangePkg::decode_from(&data);
+            let _ = BaseUp2pProtocol::decode_from(&data);
+        }
+    }
+}
@@ -0,0 +1,114 @@
+// How package payloads are serialized. Every deployment speaks bincode, a server
+// can also take CBOR so clients without a bincode implementation can talk to it.
+// Frames name their codec, see core::wire, and a session's codec is negotiated in
+// HELLO with CAP_CODEC_CBOR.
+use std::{fmt, str::FromStr};
+
+use bincode::{Decode, Encode};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::wire::WireError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+    // bincode 2 standard configuration, see docs/wire-format.md
+    #[default]
+    Bincode,
+    // RFC 8949, structs as maps keyed by field name
+    Cbor,
+    // bincode in the framing of the release before the frame, see core::wire.
+    // Never configured, it is what that release's clients are answered in
+    #[serde(skip)]
+    Legacy,
+}
+
+impl Codec {
+    pub const ALL: [Codec; 2] = [Codec::Bincode, Codec::Cbor];
+    // as carried in the frame header, legacy frames carry none
+    pub fn id(self) -> u8 {
+        match self {
+            Codec::Bincode | Codec::Legacy => 0x00,
+            Codec::Cbor => 0x01,
+        }
+    }
+    pub fn from_id(id: u8) -> Result<Self, WireError> {
+        Self::ALL.into_iter().find(|codec| codec.id() == id).ok_or(WireError::UnsupportedCodec(id))
+    }
+    pub fn encode<T: Encode + Serialize>(self, value: &T) -> anyhow::Result<Vec<u8>> {
+        match self {
+            Codec::Bincode | Codec::Legacy => Ok(bincode::encode_to_vec(value, crate::get_binencode_config())?),
+            Codec::Cbor => {
+                let mut encoded = Vec::new();
+                ciborium::into_writer(value, &mut encoded)?;
+                Ok(encoded)
+            }
+        }
+    }
+    // the whole of `data` must be one value, see core::wire for the errors
+    pub fn decode<T: Decode<()> + DeserializeOwned>(self, data: &[u8]) -> anyhow::Result<T> {
+        let (value, len) = match self {
+            Codec::Bincode | Codec::Legacy => bincode::decode_from_slice(data, crate::get_binencode_config()).map_err(WireError::from)?,
+            Codec::Cbor => {
+                let mut rest = data;
+                let value = ciborium::from_reader(&mut rest).map_err(WireError::from)?;
+                (value, data.len() - rest.len())
+            }
+        };
+        if len < data.len() {
+            return Err(WireError::TrailingBytes(data.len() - len).into());
+        }
+        Ok(value)
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Codec::Bincode => write!(f, "bincode"),
+            Codec::Cbor => write!(f, "cbor"),
+            Codec::Legacy => write!(f, "legacy"),
+        }
+    }
+}
+
+impl FromStr for Codec {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Self::ALL.into_iter().find(|codec| codec.to_string() == s)
+            .ok_or_else(|| anyhow::anyhow!("unknown codec {}, expected bincode or cbor", s))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::core::{uprotocol_pkg::{ClientRequestPkg, GetBaseInfo, StatusQueryPkg, Up2pRequest}, wire::WireError};
+
+    use super::Codec;
+
+    #[tokio::test]
+    async fn test_codecs() {
+        // a nonce wider than a byte, so a cut package ends in half a field
+        let request = ClientRequestPkg::new("cli", "peer1", "test", Up2pRequest::Status(StatusQueryPkg::new(vec!["cli-peer2".to_string()]))).in_namespace("acme").with_nonce(u64::MAX);
+        for codec in Codec::ALL {
+            let encoded = codec.encode(&request).unwrap();
+            let decoded: ClientRequestPkg = codec.decode(&encoded).unwrap();
+            assert_eq!(codec.encode(&decoded).unwrap(), encoded, "{}", codec);
+            let Up2pRequest::Status(query) = decoded.get_request() else {
+                panic!("not a status request");
+            };
+            assert_eq!(query.get_global_ids(), ["cli-peer2".to_string()]);
+            assert_eq!(decoded.get_baseinfo().namespace, "acme");
+            assert_eq!(decoded.get_nonce(), u64::MAX);
+
+            let mut trailing = encoded.clone();
+            trailing.push(0);
+            assert_eq!(codec.decode::<ClientRequestPkg>(&trailing).unwrap_err().downcast::<WireError>().unwrap(), WireError::TrailingBytes(1));
+            assert_eq!(codec.decode::<ClientRequestPkg>(&encoded[..encoded.len() - 1]).unwrap_err().downcast::<WireError>().unwrap(), WireError::Truncated);
+        }
+        // bincode is never mistaken for cbor
+        assert!(Codec::Cbor.decode::<ClientRequestPkg>(&Codec::Bincode.encode(&request).unwrap()).is_err());
+        assert_eq!("cbor".parse::<Codec>().unwrap(), Codec::Cbor);
+        assert!("protobuf".parse::<Codec>().is_err());
+    }
+}
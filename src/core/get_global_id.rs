@@ -6,7 +6,7 @@ pub trait GetGlobalId {
 
 impl GetGlobalId for BasePkg {
     fn get_global_id(&self) -> String {
-        crate::utils::get_global_id(&self.client_class, &self.client_instance)
+        crate::utils::get_namespaced_global_id(&self.namespace, &self.client_class, &self.client_instance)
     }
 }
 
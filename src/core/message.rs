@@ -0,0 +1,159 @@
+use super::{
+    bincodec::BinCodec,
+    codec::Codec,
+    uprotocol_pkg::{ClientHelloPkg, ClientRequestAckPkg, ClientRequestPkg, DeniedPkg, DeviceListAckPkg, HelloAckPkg, LanDiscoveryPkg, PeerExchangePkg, PresencePkg, SignedPkg, StatusAckPkg, TcpPunchPkg},
+    wire::{self, WireError},
+    BaseUp2pProtocol,
+};
+
+// Every package up2p sends, decoded in one go from a frame. A new package type is
+// a new variant, which every match over messages then has to handle.
+#[derive(Debug)]
+pub enum Up2pMessage {
+    Hello(ClientHelloPkg),
+    HelloAck(HelloAckPkg),
+    Request(ClientRequestPkg),
+    RequestAck(ClientRequestAckPkg),
+    PkgExchange(PeerExchangePkg),
+    TcpPunch(TcpPunchPkg),
+    LanDiscovery(LanDiscoveryPkg),
+    StatusAck(StatusAckPkg),
+    DeviceListAck(DeviceListAckPkg),
+    Presence(PresencePkg),
+    Denied(DeniedPkg),
+    Signed(SignedPkg),
+}
+
+impl Up2pMessage {
+    // the frame's type byte, one of BaseUp2pProtocol::TYPE_*
+    pub fn get_pkg_type(&self) -> u8 {
+        match self {
+            Up2pMessage::Hello(_) => BaseUp2pProtocol::TYPE_HELLO,
+            Up2pMessage::HelloAck(_) => BaseUp2pProtocol::TYPE_HELLO_ACK,
+            Up2pMessage::Request(_) => BaseUp2pProtocol::TYPE_REQUEST,
+            Up2pMessage::RequestAck(_) => BaseUp2pProtocol::TYPE_REQUEST_ACK,
+            Up2pMessage::PkgExchange(_) => BaseUp2pProtocol::TYPE_PKG_EXCHANGE,
+            Up2pMessage::TcpPunch(_) => BaseUp2pProtocol::TYPE_TCP_PUNCH,
+            Up2pMessage::LanDiscovery(_) => BaseUp2pProtocol::TYPE_LAN_DISCOVERY,
+            Up2pMessage::StatusAck(_) => BaseUp2pProtocol::TYPE_STATUS_ACK,
+            Up2pMessage::DeviceListAck(_) => BaseUp2pProtocol::TYPE_DEVICE_LIST_ACK,
+            Up2pMessage::Presence(_) => BaseUp2pProtocol::TYPE_PRESENCE,
+            Up2pMessage::Denied(_) => BaseUp2pProtocol::TYPE_DENIED,
+            Up2pMessage::Signed(_) => BaseUp2pProtocol::TYPE_SIGNED,
+        }
+    }
+    fn encode_payload(&self, codec: Codec) -> anyhow::Result<Vec<u8>> {
+        match self {
+            Up2pMessage::Hello(pkg) => pkg.encode_with(codec),
+            Up2pMessage::HelloAck(pkg) => pkg.encode_with(codec),
+            Up2pMessage::Request(pkg) => pkg.encode_with(codec),
+            Up2pMessage::RequestAck(pkg) => pkg.encode_with(codec),
+            Up2pMessage::PkgExchange(pkg) => pkg.encode_with(codec),
+            Up2pMessage::TcpPunch(pkg) => pkg.encode_with(codec),
+            Up2pMessage::LanDiscovery(pkg) => pkg.encode_with(codec),
+            Up2pMessage::StatusAck(pkg) => pkg.encode_with(codec),
+            Up2pMessage::DeviceListAck(pkg) => pkg.encode_with(codec),
+            Up2pMessage::Presence(pkg) => pkg.encode_with(codec),
+            Up2pMessage::Denied(pkg) => pkg.encode_with(codec),
+            Up2pMessage::Signed(pkg) => pkg.encode_with(codec),
+        }
+    }
+    fn decode_payload(codec: Codec, package_type: u8, payload: &[u8]) -> anyhow::Result<Self> {
+        Ok(match package_type {
+            BaseUp2pProtocol::TYPE_HELLO => Up2pMessage::Hello(ClientHelloPkg::decode_with(codec, payload)?),
+            BaseUp2pProtocol::TYPE_HELLO_ACK => Up2pMessage::HelloAck(HelloAckPkg::decode_with(codec, payload)?),
+            BaseUp2pProtocol::TYPE_REQUEST => Up2pMessage::Request(ClientRequestPkg::decode_with(codec, payload)?),
+            BaseUp2pProtocol::TYPE_REQUEST_ACK => Up2pMessage::RequestAck(ClientRequestAckPkg::decode_with(codec, payload)?),
+            BaseUp2pProtocol::TYPE_PKG_EXCHANGE => Up2pMessage::PkgExchange(PeerExchangePkg::decode_with(codec, payload)?),
+            BaseUp2pProtocol::TYPE_TCP_PUNCH => Up2pMessage::TcpPunch(TcpPunchPkg::decode_with(codec, payload)?),
+            BaseUp2pProtocol::TYPE_LAN_DISCOVERY => Up2pMessage::LanDiscovery(LanDiscoveryPkg::decode_with(codec, payload)?),
+            BaseUp2pProtocol::TYPE_STATUS_ACK => Up2pMessage::StatusAck(StatusAckPkg::decode_with(codec, payload)?),
+            BaseUp2pProtocol::TYPE_DEVICE_LIST_ACK => Up2pMessage::DeviceListAck(DeviceListAckPkg::decode_with(codec, payload)?),
+            BaseUp2pProtocol::TYPE_PRESENCE => Up2pMessage::Presence(PresencePkg::decode_with(codec, payload)?),
+            BaseUp2pProtocol::TYPE_DENIED => Up2pMessage::Denied(DeniedPkg::decode_with(codec, payload)?),
+            BaseUp2pProtocol::TYPE_SIGNED => Up2pMessage::Signed(SignedPkg::decode_with(codec, payload)?),
+            package_type => return Err(WireError::UnknownType(package_type).into()),
+        })
+    }
+}
+
+// The frame names its codec, so decode_from takes any. decode_with only takes
+// frames in `codec`.
+impl BinCodec<Up2pMessage> for Up2pMessage {
+    fn encode_with(&self, codec: Codec) -> anyhow::Result<Vec<u8>> {
+        Ok(wire::encode_frame(codec, self.get_pkg_type(), &self.encode_payload(codec)?)?)
+    }
+
+    fn decode_with(codec: Codec, data: &[u8]) -> anyhow::Result<Up2pMessage> {
+        let (frame_codec, package_type, payload) = wire::decode_frame(data)?;
+        if frame_codec != codec {
+            return Err(WireError::UnsupportedCodec(frame_codec.id()).into());
+        }
+        Self::decode_payload(codec, package_type, payload)
+    }
+
+    fn decode_from(data: &[u8]) -> anyhow::Result<Up2pMessage> {
+        let (codec, package_type, payload) = wire::decode_frame(data)?;
+        Self::decode_payload(codec, package_type, payload)
+    }
+}
+
+macro_rules! impl_from_pkg {
+    ($($pkg:ty => $variant:ident),* $(,)?) => {
+        $(impl From<$pkg> for Up2pMessage {
+            fn from(pkg: $pkg) -> Self {
+                Up2pMessage::$variant(pkg)
+            }
+        })*
+    };
+}
+
+impl_from_pkg! {
+    ClientHelloPkg => Hello,
+    HelloAckPkg => HelloAck,
+    ClientRequestPkg => Request,
+    ClientRequestAckPkg => RequestAck,
+    PeerExchangePkg => PkgExchange,
+    TcpPunchPkg => TcpPunch,
+    LanDiscoveryPkg => LanDiscovery,
+    StatusAckPkg => StatusAck,
+    DeviceListAckPkg => DeviceListAck,
+    PresencePkg => Presence,
+    DeniedPkg => Denied,
+    SignedPkg => Signed,
+}
+
+#[cfg(test)]
+mod test {
+    use crate::core::{bincodec::BinCodec, codec::Codec, uprotocol_pkg::{ClientRequestPkg, StatusQueryPkg, Up2pRequest}, wire::{self, WireError}, BaseUp2pProtocol};
+
+    use super::Up2pMessage;
+
+    #[tokio::test]
+    async fn test_message_round_trip() {
+        let request = ClientRequestPkg::new("cli", "peer1", "test", Up2pRequest::Status(StatusQueryPkg::new(vec!["cli-peer2".to_string()])));
+        let message = Up2pMessage::from(request);
+        // a frame names its codec, decode_with only takes the one asked for
+        let cbor = message.encode_with(Codec::Cbor).unwrap();
+        assert!(matches!(Up2pMessage::decode_from(&cbor).unwrap(), Up2pMessage::Request(_)));
+        assert_eq!(Up2pMessage::decode_with(Codec::Bincode, &cbor).unwrap_err().downcast::<WireError>().unwrap(), WireError::UnsupportedCodec(Codec::Cbor.id()));
+        let encoded = message.encode_to_vec().unwrap();
+        let Up2pMessage::Request(request) = Up2pMessage::decode_with(Codec::Bincode, &encoded).unwrap() else {
+            panic!("not a request");
+        };
+        let Up2pRequest::Status(query) = request.get_request() else {
+            panic!("not a status request");
+        };
+        assert_eq!(query.get_global_ids(), ["cli-peer2".to_string()]);
+
+        // every server this client talks to has negotiation, its HELLO_ACK is never empty
+        let empty_ack = wire::encode_frame(Codec::Bincode, BaseUp2pProtocol::TYPE_HELLO_ACK, &[]).unwrap();
+        assert_eq!(Up2pMessage::decode_from(&empty_ack).unwrap_err().downcast::<WireError>().unwrap(), WireError::Truncated);
+
+        let unknown = wire::encode_frame(Codec::Bincode, 0x03, &[]).unwrap();
+        assert_eq!(Up2pMessage::decode_from(&unknown).unwrap_err().downcast::<WireError>().unwrap(), WireError::UnknownType(0x03));
+        // a request type nobody knows fails with the request, not later
+        let bad_request = wire::encode_frame(Codec::Bincode, BaseUp2pProtocol::TYPE_REQUEST, &[0, 0, 0, 0, 0x7f, 0, 0]).unwrap();
+        assert!(Up2pMessage::decode_from(&bad_request).is_err());
+    }
+}
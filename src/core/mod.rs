@@ -3,5 +3,11 @@ pub mod uprotocol_pkg;
 pub mod get_global_id;
 pub mod bincodec;
 pub mod request_info;
+pub mod protocol_version;
+pub mod server_key;
+pub mod wire;
+pub mod codec;
+pub mod message;
 
-pub use uprotocol::BaseUp2pProtocol;
\ No newline at end of file
+pub use uprotocol::BaseUp2pProtocol;
+pub use message::Up2pMessage;
\ No newline at end of file
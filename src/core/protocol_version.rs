@@ -0,0 +1,72 @@
+// The protocol version and feature bits exchanged in HELLO and HELLO_ACK. Each
+// side goes by the lower version and the bits both announced, so releases can
+// mix and a feature is only used once both ends have it.
+use super::codec::Codec;
+
+pub const PROTOCOL_VERSION: u16 = 2;
+// hellos without the version fields, from before negotiation
+pub const PROTOCOL_VERSION_LEGACY: u16 = 1;
+// the oldest version a server of this release still registers
+pub const MIN_PROTOCOL_VERSION: u16 = PROTOCOL_VERSION_LEGACY;
+
+// the server signs its packages, see core::server_key
+pub const CAP_SIGNED: u32 = 1 << 0;
+// PRESENCE packages, see Up2pCli::subscribe_presence
+pub const CAP_PRESENCE: u32 = 1 << 1;
+// TCP_PUNCH packages, see Up2pCli::tcp_punch
+pub const CAP_TCP_PUNCH: u32 = 1 << 2;
+// packages in CBOR, announced by servers whose deployment chose it, see core::codec
+pub const CAP_CODEC_CBOR: u32 = 1 << 3;
+// HELLO_ACK hands out a session token the device names itself with afterwards, see HelloAckPkg
+pub const CAP_SESSION: u32 = 1 << 4;
+// everything this release implements
+pub const CAPABILITIES: u32 = CAP_SIGNED | CAP_PRESENCE | CAP_TCP_PUNCH | CAP_CODEC_CBOR | CAP_SESSION;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProtocolVersion {
+    pub version: u16,
+    pub capabilities: u32,
+}
+
+impl ProtocolVersion {
+    pub const LEGACY: Self = Self { version: PROTOCOL_VERSION_LEGACY, capabilities: 0 };
+    pub const CURRENT: Self = Self { version: PROTOCOL_VERSION, capabilities: CAPABILITIES };
+    // what both ends can speak
+    pub fn negotiate(self, peer: Self) -> Self {
+        Self {
+            version: self.version.min(peer.version),
+            capabilities: self.capabilities & peer.capabilities,
+        }
+    }
+    pub fn supports(self, capability: u32) -> bool {
+        self.capabilities & capability == capability
+    }
+    // the codec a session with these capabilities sends in, a legacy one keeps
+    // to the framing of its release
+    pub fn codec(self) -> Codec {
+        if self.version <= PROTOCOL_VERSION_LEGACY {
+            Codec::Legacy
+        } else if self.supports(CAP_CODEC_CBOR) {
+            Codec::Cbor
+        } else {
+            Codec::Bincode
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ProtocolVersion, CAP_PRESENCE, CAP_SIGNED, CAP_TCP_PUNCH};
+
+    #[tokio::test]
+    async fn test_negotiate() {
+        let peer = ProtocolVersion { version: 7, capabilities: CAP_PRESENCE | (1 << 20) };
+        let negotiated = ProtocolVersion::CURRENT.negotiate(peer);
+        assert_eq!(negotiated, peer.negotiate(ProtocolVersion::CURRENT));
+        assert_eq!(negotiated.version, ProtocolVersion::CURRENT.version);
+        assert!(negotiated.supports(CAP_PRESENCE));
+        assert!(!negotiated.supports(CAP_TCP_PUNCH));
+        assert!(!negotiated.supports(CAP_PRESENCE | CAP_SIGNED));
+        assert_eq!(ProtocolVersion::CURRENT.negotiate(ProtocolVersion::LEGACY), ProtocolVersion::LEGACY);
+    }
+}
@@ -0,0 +1,92 @@
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+
+use super::{bincodec::BinCodec, uprotocol_pkg::SignedPkg, wire, Up2pMessage};
+
+// The server's ed25519 key. Every package the server sends goes out wrapped in a
+// TYPE_SIGNED package, clients that pin the verifying key drop the rest.
+// An answer echoes the nonce of its request, so a recorded one does not answer
+// a later request.
+#[derive(Clone)]
+pub struct ServerKey {
+    signing_key: SigningKey,
+}
+
+impl ServerKey {
+    pub fn generate() -> Self {
+        Self { signing_key: SigningKey::from_bytes(&rand::random::<[u8; 32]>()) }
+    }
+    // the 32 byte secret as hex, as written by to_hex
+    pub fn from_hex(secret: &str) -> anyhow::Result<Self> {
+        Ok(Self { signing_key: SigningKey::from_bytes(&decode_key_hex(secret)?) })
+    }
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.signing_key.to_bytes())
+    }
+    // what clients pin, see PinnedServerKey::from_hex
+    pub fn verifying_key_hex(&self) -> String {
+        hex::encode(self.signing_key.verifying_key().to_bytes())
+    }
+    // `package` as an encoded TYPE_SIGNED package, in the package's codec
+    pub fn sign(&self, package: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let signature = self.signing_key.sign(package).to_bytes().to_vec();
+        let codec = wire::peek_codec(package).unwrap_or_default();
+        Up2pMessage::Signed(SignedPkg::new(package.to_vec(), signature)).encode_with(codec)
+    }
+}
+
+impl std::fmt::Debug for ServerKey {
+    // never log the secret
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ServerKey").field("verifying_key", &self.verifying_key_hex()).finish()
+    }
+}
+
+// The server's verifying key as configured on a client
+#[derive(Debug, Clone)]
+pub struct PinnedServerKey {
+    verifying_key: VerifyingKey,
+}
+
+impl PinnedServerKey {
+    pub fn from_hex(verifying_key: &str) -> anyhow::Result<Self> {
+        Ok(Self { verifying_key: VerifyingKey::from_bytes(&decode_key_hex(verifying_key)?)? })
+    }
+    // the signed package, if the server signed it
+    pub fn open<'a>(&self, signed_pkg: &'a SignedPkg) -> anyhow::Result<&'a [u8]> {
+        let signature = Signature::from_slice(signed_pkg.get_signature())?;
+        self.verifying_key.verify(signed_pkg.get_package(), &signature)?;
+        Ok(signed_pkg.get_package())
+    }
+}
+
+// an ed25519 secret or verifying key written as hex
+pub(crate) fn decode_key_hex(key: &str) -> anyhow::Result<[u8; 32]> {
+    hex::decode(key.trim())?.try_into()
+        .map_err(|_| anyhow::anyhow!("key must be 32 bytes"))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::core::{bincodec::BinCodec, protocol_version::ProtocolVersion, uprotocol_pkg::{HelloAckPkg, SignedPkg}, BaseUp2pProtocol, Up2pMessage};
+
+    use super::{PinnedServerKey, ServerKey};
+
+    #[tokio::test]
+    async fn test_sign_and_open() {
+        let server_key = ServerKey::from_hex(&ServerKey::generate().to_hex()).unwrap();
+        let pinned = PinnedServerKey::from_hex(&server_key.verifying_key_hex()).unwrap();
+        let hello_ack = Up2pMessage::HelloAck(HelloAckPkg::new(ProtocolVersion::CURRENT)).encode_to_vec().unwrap();
+        let Up2pMessage::Signed(signed_pkg) = Up2pMessage::decode_from(&server_key.sign(&hello_ack).unwrap()).unwrap() else {
+            panic!("not signed");
+        };
+        assert_eq!(pinned.open(&signed_pkg).unwrap(), hello_ack.as_slice());
+
+        let mut tampered = hello_ack.clone();
+        // the type byte of the frame header
+        tampered[3] = BaseUp2pProtocol::TYPE_REQUEST_ACK;
+        assert!(pinned.open(&SignedPkg::new(tampered, signed_pkg.get_signature().to_vec())).is_err());
+        let other = PinnedServerKey::from_hex(&ServerKey::generate().verifying_key_hex()).unwrap();
+        assert!(other.open(&signed_pkg).is_err());
+        assert!(PinnedServerKey::from_hex("abcd").is_err());
+    }
+}
@@ -1,12 +1,12 @@
-use bincode::{Decode, Encode};
 use serde::{Deserialize, Serialize};
 
-use super::{bincodec::BinCodec, uprotocol_pkg::{ClientHelloPkg, ClientRequestAckPkg, ClientRequestPkg, PeerExchangePkg}};// udp包最大大小
+use super::{bincodec::BinCodec, codec::Codec, wire::{self, WireError}};// udp包最大大小
 
-// 定义了这个app通信的基本协议
-#[derive(Debug, Clone, Serialize, Deserialize, Encode, Decode)]
+// 定义了这个app通信的基本协议, framed as described in core::wire. The raw frame,
+// Up2pMessage is the same with its payload decoded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BaseUp2pProtocol {
-    content_len: u8,
+    codec: Codec,
     package_type: u8,
     content: Vec<u8>,
 }
@@ -20,50 +20,27 @@ impl BaseUp2pProtocol {
     pub const TYPE_REQUEST: u8 = 0x05;
     pub const TYPE_REQUEST_ACK: u8 = 0x06;
     pub const TYPE_PKG_EXCHANGE: u8 = 0x07;
-    pub fn client_hello_with_payload(_payload: ClientHelloPkg) -> anyhow::Result<Self> {
-        let payload = _payload.encode_to_vec()?;
-        Ok(BaseUp2pProtocol {
-            content_len: payload.len() as u8,
-            package_type: Self::TYPE_HELLO,
-            content: payload,
-        })
-    }
-    pub fn request_with_payload(_payload: ClientRequestPkg) -> anyhow::Result<Self> {
-        let payload = _payload.encode_to_vec()?;
-        if payload.len() > u8::MAX as usize {
-            return Err(anyhow::anyhow!("payload too large"));
-        }
-        Ok(BaseUp2pProtocol {
-            content_len: payload.len() as u8,
-            package_type: Self::TYPE_REQUEST,
-            content: payload,
-        })
-    }
-    pub fn response_with_payload(_payload: ClientRequestAckPkg) -> anyhow::Result<Self> {
-        let payload = _payload.encode_to_vec()?;
-        if payload.len() > u8::MAX as usize {
-            return Err(anyhow::anyhow!("payload too large"));
+    pub const TYPE_TCP_PUNCH: u8 = 0x08;
+    pub const TYPE_LAN_DISCOVERY: u8 = 0x09;
+    pub const TYPE_STATUS_ACK: u8 = 0x0A;
+    pub const TYPE_DEVICE_LIST_ACK: u8 = 0x0B;
+    pub const TYPE_PRESENCE: u8 = 0x0C;
+    pub const TYPE_DENIED: u8 = 0x0D;
+    pub const TYPE_SIGNED: u8 = 0x0E;
+    // a package with an already encoded payload, use Up2pMessage to encode a package
+    pub fn new(package_type: u8, content: Vec<u8>) -> anyhow::Result<Self> {
+        if content.len() > wire::MAX_PAYLOAD_LEN {
+            return Err(WireError::PayloadTooLarge(content.len()).into());
         }
-        Ok(BaseUp2pProtocol {
-            content_len: payload.len() as u8,
-            package_type: Self::TYPE_REQUEST_ACK,
-            content: payload.to_vec(),
-        })
+        Ok(BaseUp2pProtocol { codec: Codec::Bincode, package_type, content })
     }
-    pub fn hello_ack_with_payload() -> anyhow::Result<Self> {
-        Ok(BaseUp2pProtocol {
-            content_len: 0,
-            package_type: Self::TYPE_HELLO_ACK,
-            content: vec![],
-        })
+    // the codec `content` is encoded with
+    pub fn with_codec(mut self, codec: Codec) -> Self {
+        self.codec = codec;
+        self
     }
-    pub fn pakge_exchange_with_payload(_payload: PeerExchangePkg) -> anyhow::Result<Self> {
-        let payload = _payload.encode_to_vec()?;
-        Ok(BaseUp2pProtocol {
-            content_len: payload.len() as u8,
-            package_type: Self::TYPE_PKG_EXCHANGE,
-            content: payload,
-        })
+    pub fn get_codec(&self) -> Codec {
+        self.codec
     }
     pub fn get_pkg_type(&self) -> u8 {
         self.package_type
@@ -76,9 +53,37 @@ impl BaseUp2pProtocol {
 impl Default for BaseUp2pProtocol {
     fn default() -> Self {
         BaseUp2pProtocol {
-            content_len: 0,
+            codec: Codec::Bincode,
             package_type: Self::TYPE_HELLO,
             content: Vec::new(),
         }
     }
 }
+
+// The frame names its codec, so decode_from takes any. The content is opaque here,
+// encode_with and decode_with only accept the codec it is in.
+impl BinCodec<BaseUp2pProtocol> for BaseUp2pProtocol {
+    fn encode_with(&self, codec: Codec) -> anyhow::Result<Vec<u8>> {
+        if codec != self.codec {
+            return Err(WireError::UnsupportedCodec(codec.id()).into());
+        }
+        Ok(wire::encode_frame(self.codec, self.package_type, &self.content)?)
+    }
+
+    fn decode_with(codec: Codec, data: &[u8]) -> anyhow::Result<BaseUp2pProtocol> {
+        let base_protocol = Self::decode_from(data)?;
+        if base_protocol.codec != codec {
+            return Err(WireError::UnsupportedCodec(base_protocol.codec.id()).into());
+        }
+        Ok(base_protocol)
+    }
+
+    fn encode_to_vec(&self) -> anyhow::Result<Vec<u8>> {
+        self.encode_with(self.codec)
+    }
+
+    fn decode_from(data: &[u8]) -> anyhow::Result<BaseUp2pProtocol> {
+        let (codec, package_type, content) = wire::decode_frame(data)?;
+        Ok(BaseUp2pProtocol { codec, package_type, content: content.to_vec() })
+    }
+}
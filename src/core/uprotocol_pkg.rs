@@ -1,6 +1,8 @@
-use bincode::{Decode, Encode};
+use bincode::{de::{read::Reader, Decoder}, enc::Encoder, error::{DecodeError, EncodeError}, Decode, Encode};
 use serde::{Deserialize, Serialize};
 
+use super::{bincodec::BinCodec, protocol_version::{ProtocolVersion, PROTOCOL_VERSION_LEGACY}, wire::WireError};
+
 pub trait PkgVerifyIdentity {
     fn verify_identity(&self, identity: &str) -> anyhow::Result<()>;
 }
@@ -17,11 +19,16 @@ where
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Encode, Decode, Clone)]
+// On the wire in bincode only the first three fields, as ever. The namespace
+// trails the package carrying the base info, see encode_namespace.
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct BasePkg {
     pub client_class: String,
     pub client_instance: String,
     pub identity: String,
+    // tenant of the device, empty for the default one. Devices only see their own tenant.
+    #[serde(default)]
+    pub namespace: String,
 }
 
 // This trait means that you can get the base info from this struct
@@ -29,18 +36,124 @@ pub trait GetBaseInfo {
     fn get_baseinfo(&self) -> &BasePkg;
 }
 
+impl BasePkg {
+    // Only the hello carries the credential, everything else names the device
+    // without it, see PeerExchangePkg::without_identity.
+    pub fn without_identity(&self) -> Self {
+        Self { identity: String::new(), ..self.clone() }
+    }
+}
+
 impl PartialEq for BasePkg {
     fn eq(&self, other: &Self) -> bool {
-        self.client_class == other.client_class
+        self.namespace == other.namespace
+            && self.client_class == other.client_class
             && self.client_instance == other.client_instance
     }
 }
 
-// client hello package
-#[derive(Debug, Serialize, Deserialize, Encode, Decode)]
+impl Encode for BasePkg {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        self.client_class.encode(encoder)?;
+        self.client_instance.encode(encoder)?;
+        self.identity.encode(encoder)
+    }
+}
+
+impl<Context> Decode<Context> for BasePkg {
+    fn decode<D: Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        Ok(Self {
+            client_class: String::decode(decoder)?,
+            client_instance: String::decode(decoder)?,
+            identity: String::decode(decoder)?,
+            namespace: String::new(),
+        })
+    }
+}
+
+// Fields added to a package go after the ones older releases know, which stop
+// reading before them. A package without them ends early.
+fn has_trailing<D: Decoder>(decoder: &mut D) -> bool {
+    decoder.reader().peek_read(1).is_some()
+}
+
+// The default namespace is left out, older releases only know that one. It is
+// written anyway when a trailing field `follows` it.
+fn encode_namespace<E: Encoder>(namespace: &str, follows: bool, encoder: &mut E) -> Result<(), EncodeError> {
+    if namespace.is_empty() && !follows {
+        return Ok(());
+    }
+    namespace.encode(encoder)
+}
+
+// a request's nonce, left out when there is none
+fn encode_nonce<E: Encoder>(nonce: u64, encoder: &mut E) -> Result<(), EncodeError> {
+    if nonce == 0 {
+        return Ok(());
+    }
+    nonce.encode(encoder)
+}
+
+fn decode_nonce<Context, D: Decoder<Context = Context>>(decoder: &mut D) -> Result<u64, DecodeError> {
+    if !has_trailing(decoder) {
+        return Ok(0);
+    }
+    u64::decode(decoder)
+}
+
+fn decode_namespace<Context, D: Decoder<Context = Context>>(decoder: &mut D) -> Result<String, DecodeError> {
+    if !has_trailing(decoder) {
+        return Ok(String::new());
+    }
+    String::decode(decoder)
+}
+
+// Client hello package. In bincode the version fields follow msg unless the hello
+// is a legacy one with nothing after them, then the namespace and the nonce.
+#[derive(Debug, Serialize, Deserialize)]
 pub struct ClientHelloPkg {
     baseinfo: BasePkg,
     msg: u8,
+    // appended for negotiation, older clients end the package after msg
+    #[serde(default = "legacy_version")]
+    version: u16,
+    #[serde(default)]
+    capabilities: u32,
+    // echoed in the HELLO_ACK, 0 from clients that don't check answers
+    #[serde(default)]
+    nonce: u64,
+}
+
+fn legacy_version() -> u16 {
+    PROTOCOL_VERSION_LEGACY
+}
+
+impl Encode for ClientHelloPkg {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        self.baseinfo.encode(encoder)?;
+        self.msg.encode(encoder)?;
+        if self.get_protocol() != ProtocolVersion::LEGACY || !self.baseinfo.namespace.is_empty() || self.nonce != 0 {
+            self.version.encode(encoder)?;
+            self.capabilities.encode(encoder)?;
+        }
+        encode_namespace(&self.baseinfo.namespace, self.nonce != 0, encoder)?;
+        encode_nonce(self.nonce, encoder)
+    }
+}
+
+impl<Context> Decode<Context> for ClientHelloPkg {
+    fn decode<D: Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        let mut baseinfo = BasePkg::decode(decoder)?;
+        let msg = u8::decode(decoder)?;
+        let ProtocolVersion { version, capabilities } = if has_trailing(decoder) {
+            ProtocolVersion { version: u16::decode(decoder)?, capabilities: u32::decode(decoder)? }
+        } else {
+            ProtocolVersion::LEGACY
+        };
+        baseinfo.namespace = decode_namespace(decoder)?;
+        let nonce = decode_nonce(decoder)?;
+        Ok(Self { baseinfo, msg, version, capabilities, nonce })
+    }
 }
 
 impl ClientHelloPkg {
@@ -54,13 +167,38 @@ impl ClientHelloPkg {
                 client_class: client_class.to_string(),
                 client_instance: client_instance.to_string(),
                 identity: identity.to_string(),
+                namespace: String::new(),
             },
             msg,
+            version: ProtocolVersion::LEGACY.version,
+            capabilities: ProtocolVersion::LEGACY.capabilities,
+            nonce: 0,
         }
     }
+    pub fn in_namespace(mut self, namespace: &str) -> Self {
+        self.baseinfo.namespace = namespace.to_string();
+        self
+    }
+    // a new hello announces nothing, like one from before negotiation
+    pub fn with_protocol(mut self, protocol: ProtocolVersion) -> Self {
+        self.version = protocol.version;
+        self.capabilities = protocol.capabilities;
+        self
+    }
+    // bind the answer to this hello, see HelloAckPkg::with_nonce
+    pub fn with_nonce(mut self, nonce: u64) -> Self {
+        self.nonce = nonce;
+        self
+    }
+    pub fn get_nonce(&self) -> u64 {
+        self.nonce
+    }
     pub fn get_msg(&self) -> u8 {
         self.msg
     }
+    pub fn get_protocol(&self) -> ProtocolVersion {
+        ProtocolVersion { version: self.version, capabilities: self.capabilities }
+    }
     pub fn get_identity(&self) -> String {
         self.baseinfo.identity.clone()
     }
@@ -74,35 +212,136 @@ impl GetBaseInfo for ClientHelloPkg {
 }
 
 
-// client request package
-#[derive(Debug, Serialize, Deserialize, Encode, Decode)]
+// client request package. On the wire the request is a request_type byte, the
+// request_id and the request's payload as bytes, followed by the namespace and
+// the nonce.
+#[derive(Debug, Serialize, Deserialize)]
 pub struct ClientRequestPkg {
     baseinfo: BasePkg,
-    request_type: u8,
     request_id: u8,
-    request_payload: Vec<u8>,
+    request: Up2pRequest,
+    // echoed in the answer, 0 from clients that don't check answers
+    #[serde(default)]
+    nonce: u64,
+}
+
+// what a ClientRequestPkg asks for, with its payload decoded
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Up2pRequest {
+    // the endpoints of a global id, answered with a REQUEST_ACK
+    Endpoint(String),
+    Status(StatusQueryPkg),
+    // a tcp simultaneous open with a global id, answered with a TCP_PUNCH to both sides
+    TcpPunch(String),
+    // answered like Status for the sender itself
+    AdvertiseStatus(StatusAdvertisePkg),
+    ListDevices(DeviceListQueryPkg),
+    // answered with a DeviceListAckPkg
+    SubscribePresence(PresenceSubscribePkg),
+}
+
+impl Up2pRequest {
+    pub fn get_request_type(&self) -> u8 {
+        match self {
+            Up2pRequest::Endpoint(_) => ClientRequestPkg::REQUEST_ENDPOINT,
+            Up2pRequest::Status(_) => ClientRequestPkg::REQUEST_STATUS,
+            Up2pRequest::TcpPunch(_) => ClientRequestPkg::REQUEST_TCP_PUNCH,
+            Up2pRequest::AdvertiseStatus(_) => ClientRequestPkg::REQUEST_ADVERTISE_STATUS,
+            Up2pRequest::ListDevices(_) => ClientRequestPkg::REQUEST_LIST_DEVICES,
+            Up2pRequest::SubscribePresence(_) => ClientRequestPkg::REQUEST_SUBSCRIBE_PRESENCE,
+        }
+    }
+    // global ids travel as plain utf-8, everything else as its package
+    fn encode_payload(&self) -> anyhow::Result<Vec<u8>> {
+        match self {
+            Up2pRequest::Endpoint(global_id) | Up2pRequest::TcpPunch(global_id) => Ok(global_id.as_bytes().to_vec()),
+            Up2pRequest::Status(query) => query.encode_to_vec(),
+            Up2pRequest::AdvertiseStatus(advertisement) => advertisement.encode_to_vec(),
+            Up2pRequest::ListDevices(query) => query.encode_to_vec(),
+            Up2pRequest::SubscribePresence(subscription) => subscription.encode_to_vec(),
+        }
+    }
+    fn decode_payload(request_type: u8, payload: &[u8]) -> anyhow::Result<Self> {
+        let global_id = || String::from_utf8(payload.to_vec()).map_err(|_| WireError::InvalidUtf8);
+        Ok(match request_type {
+            ClientRequestPkg::REQUEST_ENDPOINT => Up2pRequest::Endpoint(global_id()?),
+            ClientRequestPkg::REQUEST_STATUS => Up2pRequest::Status(StatusQueryPkg::decode_from(payload)?),
+            ClientRequestPkg::REQUEST_TCP_PUNCH => Up2pRequest::TcpPunch(global_id()?),
+            ClientRequestPkg::REQUEST_ADVERTISE_STATUS => Up2pRequest::AdvertiseStatus(StatusAdvertisePkg::decode_from(payload)?),
+            ClientRequestPkg::REQUEST_LIST_DEVICES => Up2pRequest::ListDevices(DeviceListQueryPkg::decode_from(payload)?),
+            ClientRequestPkg::REQUEST_SUBSCRIBE_PRESENCE => Up2pRequest::SubscribePresence(PresenceSubscribePkg::decode_from(payload)?),
+            request_type => return Err(WireError::InvalidValue(format!("request type {}", request_type)).into()),
+        })
+    }
+}
+
+impl Encode for ClientRequestPkg {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        self.baseinfo.encode(encoder)?;
+        self.request.get_request_type().encode(encoder)?;
+        self.request_id.encode(encoder)?;
+        self.request.encode_payload()
+            .map_err(|e| EncodeError::OtherString(e.to_string()))?
+            .encode(encoder)?;
+        encode_namespace(&self.baseinfo.namespace, self.nonce != 0, encoder)?;
+        encode_nonce(self.nonce, encoder)
+    }
+}
+
+impl<Context> Decode<Context> for ClientRequestPkg {
+    fn decode<D: Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        let mut baseinfo = BasePkg::decode(decoder)?;
+        let request_type = u8::decode(decoder)?;
+        let request_id = u8::decode(decoder)?;
+        let payload = Vec::<u8>::decode(decoder)?;
+        let request = Up2pRequest::decode_payload(request_type, &payload)
+            .map_err(|e| DecodeError::OtherString(format!("request payload: {}", e)))?;
+        baseinfo.namespace = decode_namespace(decoder)?;
+        let nonce = decode_nonce(decoder)?;
+        Ok(Self { baseinfo, request_id, request, nonce })
+    }
 }
 
 impl ClientRequestPkg {
     pub const REQUEST_ENDPOINT: u8 = 0x01;
     pub const REQUEST_STATUS: u8 = 0x02;
-    pub fn create_endpoint_request(client_class: &str, client_instance: &str, identity: &str, payload: &str) -> Self {
+    pub const REQUEST_TCP_PUNCH: u8 = 0x03;
+    pub const REQUEST_ADVERTISE_STATUS: u8 = 0x04;
+    pub const REQUEST_LIST_DEVICES: u8 = 0x05;
+    pub const REQUEST_SUBSCRIBE_PRESENCE: u8 = 0x06;
+    pub fn new(client_class: &str, client_instance: &str, identity: &str, request: Up2pRequest) -> Self {
         Self {
             baseinfo: BasePkg {
                 client_class: client_class.to_string(),
                 client_instance: client_instance.to_string(),
-                identity: identity.to_string()
+                identity: identity.to_string(),
+                namespace: String::new(),
             },
-            request_type: Self::REQUEST_ENDPOINT,
             request_id: 0,
-            request_payload: payload.as_bytes().to_vec(),
+            request,
+            nonce: 0,
         }
     }
-    pub fn get_request_type(&self) -> u8 {
-        self.request_type
+    pub fn create_endpoint_request(client_class: &str, client_instance: &str, identity: &str, payload: &str) -> Self {
+        Self::new(client_class, client_instance, identity, Up2pRequest::Endpoint(payload.to_string()))
     }
-    pub fn get_payload_as_global_id(&self) -> anyhow::Result<String> {
-        Ok(String::from_utf8(self.request_payload.clone())?)
+    pub fn create_tcp_punch_request(client_class: &str, client_instance: &str, identity: &str, payload: &str) -> Self {
+        Self::new(client_class, client_instance, identity, Up2pRequest::TcpPunch(payload.to_string()))
+    }
+    pub fn in_namespace(mut self, namespace: &str) -> Self {
+        self.baseinfo.namespace = namespace.to_string();
+        self
+    }
+    // bind the answer to this request, the server echoes the nonce in it
+    pub fn with_nonce(mut self, nonce: u64) -> Self {
+        self.nonce = nonce;
+        self
+    }
+    pub fn get_nonce(&self) -> u64 {
+        self.nonce
+    }
+    pub fn get_request(&self) -> &Up2pRequest {
+        &self.request
     }
     pub fn get_identity(&self) -> String {
         self.baseinfo.identity.clone()
@@ -116,26 +355,505 @@ impl GetBaseInfo for ClientRequestPkg {
     
 }
 
+// Answers carry the nonce of the request they answer, so a signed answer is only
+// good for that request. 0 when the request had none.
 #[derive(Debug, Serialize, Deserialize, Encode, Decode)]
 pub struct ClientRequestAckPkg {
+    // the endpoint the requester should try first
     endoint_address: String,
+    // every endpoint of the device, ipv4 and ipv6
+    endpoint_addresses: Vec<String>,
+    // the device asked for, as the requester named it
+    global_id: String,
+    nonce: u64,
 }
 
 impl ClientRequestAckPkg {
     pub fn new(endoint_address: String) -> Self {
         Self {
+            endpoint_addresses: vec![endoint_address.clone()],
             endoint_address,
+            global_id: String::new(),
+            nonce: 0,
         }
     }
+    pub fn with_endpoint_addresses(mut self, endpoint_addresses: Vec<String>) -> Self {
+        self.endpoint_addresses = endpoint_addresses;
+        self
+    }
+    // the request this answers
+    pub fn answering(mut self, global_id: String, nonce: u64) -> Self {
+        self.global_id = global_id;
+        self.nonce = nonce;
+        self
+    }
+    pub fn get_global_id(&self) -> String {
+        self.global_id.clone()
+    }
+    pub fn get_nonce(&self) -> u64 {
+        self.nonce
+    }
     pub fn get_endpoint_address(&self) -> String {
         self.endoint_address.clone()
     }
+    pub fn get_endpoint_addresses(&self) -> Vec<String> {
+        self.endpoint_addresses.clone()
+    }
+}
+
+// the devices a REQUEST_STATUS asks about
+#[derive(Debug, Serialize, Deserialize, Encode, Decode)]
+pub struct StatusQueryPkg {
+    global_ids: Vec<String>,
+}
+
+impl StatusQueryPkg {
+    pub fn new(global_ids: Vec<String>) -> Self {
+        Self { global_ids }
+    }
+    pub fn get_global_ids(&self) -> &[String] {
+        &self.global_ids
+    }
 }
 
-// target required for pkg forward
+// what a device tells the server about itself
 #[derive(Debug, Serialize, Deserialize, Encode, Decode)]
+pub struct StatusAdvertisePkg {
+    nat_type: u8,
+    capabilities: u32,
+}
+
+impl StatusAdvertisePkg {
+    pub fn new(nat_type: u8, capabilities: u32) -> Self {
+        Self { nat_type, capabilities }
+    }
+    pub fn get_nat_type(&self) -> u8 {
+        self.nat_type
+    }
+    pub fn get_capabilities(&self) -> u32 {
+        self.capabilities
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Encode, Decode)]
+pub struct DeviceStatusPkg {
+    global_id: String,
+    online: bool,
+    // seconds since the device was last seen, None if it never registered
+    last_seen_secs: Option<u64>,
+    nat_type: u8,
+    capabilities: u32,
+}
+
+impl DeviceStatusPkg {
+    pub const NAT_UNKNOWN: u8 = 0x00;
+    pub const NAT_NONE: u8 = 0x01;
+    pub const NAT_FULL_CONE: u8 = 0x02;
+    pub const NAT_RESTRICTED: u8 = 0x03;
+    pub const NAT_PORT_RESTRICTED: u8 = 0x04;
+    pub const NAT_SYMMETRIC: u8 = 0x05;
+    pub fn new(global_id: String, online: bool, last_seen_secs: Option<u64>, nat_type: u8, capabilities: u32) -> Self {
+        Self {
+            global_id,
+            online,
+            last_seen_secs,
+            nat_type,
+            capabilities,
+        }
+    }
+    pub fn unknown(global_id: String) -> Self {
+        Self::new(global_id, false, None, Self::NAT_UNKNOWN, 0)
+    }
+    pub fn get_global_id(&self) -> String {
+        self.global_id.clone()
+    }
+    pub fn is_online(&self) -> bool {
+        self.online
+    }
+    pub fn get_last_seen(&self) -> Option<std::time::Duration> {
+        self.last_seen_secs.map(std::time::Duration::from_secs)
+    }
+    pub fn get_nat_type(&self) -> u8 {
+        self.nat_type
+    }
+    pub fn get_capabilities(&self) -> u32 {
+        self.capabilities
+    }
+}
+
+// answer to REQUEST_STATUS and REQUEST_ADVERTISE_STATUS, in the order asked
+#[derive(Debug, Serialize, Deserialize, Encode, Decode)]
+pub struct StatusAckPkg {
+    statuses: Vec<DeviceStatusPkg>,
+    nonce: u64,
+}
+
+impl StatusAckPkg {
+    pub fn new(statuses: Vec<DeviceStatusPkg>) -> Self {
+        Self { statuses, nonce: 0 }
+    }
+    pub fn with_nonce(mut self, nonce: u64) -> Self {
+        self.nonce = nonce;
+        self
+    }
+    pub fn get_nonce(&self) -> u64 {
+        self.nonce
+    }
+    pub fn get_statuses(&self) -> Vec<DeviceStatusPkg> {
+        self.statuses.clone()
+    }
+}
+
+// One page of the devices registered under a class. Pass the previous page's
+// next_cursor to continue, a limit of 0 asks for as many as fit in one package.
+#[derive(Debug, Serialize, Deserialize, Encode, Decode)]
+pub struct DeviceListQueryPkg {
+    client_class: String,
+    online_only: bool,
+    cursor: Option<String>,
+    limit: u16,
+}
+
+impl DeviceListQueryPkg {
+    pub fn new(client_class: &str, online_only: bool, cursor: Option<String>, limit: u16) -> Self {
+        Self {
+            client_class: client_class.to_string(),
+            online_only,
+            cursor,
+            limit,
+        }
+    }
+    pub fn get_client_class(&self) -> &str {
+        &self.client_class
+    }
+    pub fn is_online_only(&self) -> bool {
+        self.online_only
+    }
+    pub fn get_cursor(&self) -> Option<&str> {
+        self.cursor.as_deref()
+    }
+    pub fn get_limit(&self) -> u16 {
+        self.limit
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Encode, Decode)]
+pub struct DeviceListAckPkg {
+    result: u8,
+    devices: Vec<DeviceStatusPkg>,
+    // None on the last page
+    next_cursor: Option<String>,
+    nonce: u64,
+}
+
+impl DeviceListAckPkg {
+    pub const RESULT_OK: u8 = 0x00;
+    pub const RESULT_DENIED: u8 = 0x01;
+    pub fn new(devices: Vec<DeviceStatusPkg>, next_cursor: Option<String>) -> Self {
+        Self {
+            result: Self::RESULT_OK,
+            devices,
+            next_cursor,
+            nonce: 0,
+        }
+    }
+    pub fn denied() -> Self {
+        Self {
+            result: Self::RESULT_DENIED,
+            devices: Vec::new(),
+            next_cursor: None,
+            nonce: 0,
+        }
+    }
+    pub fn with_nonce(mut self, nonce: u64) -> Self {
+        self.nonce = nonce;
+        self
+    }
+    pub fn get_nonce(&self) -> u64 {
+        self.nonce
+    }
+    pub fn get_result(&self) -> u8 {
+        self.result
+    }
+    pub fn get_devices(&self) -> Vec<DeviceStatusPkg> {
+        self.devices.clone()
+    }
+    pub fn get_next_cursor(&self) -> Option<String> {
+        self.next_cursor.clone()
+    }
+}
+
+// Devices and whole classes to get PresencePkg pushes for, replacing any earlier
+// subscription of the sender. Empty lists unsubscribe.
+#[derive(Debug, Serialize, Deserialize, Encode, Decode)]
+pub struct PresenceSubscribePkg {
+    global_ids: Vec<String>,
+    client_classes: Vec<String>,
+}
+
+impl PresenceSubscribePkg {
+    pub fn new(global_ids: Vec<String>, client_classes: Vec<String>) -> Self {
+        Self { global_ids, client_classes }
+    }
+    pub fn get_global_ids(&self) -> &[String] {
+        &self.global_ids
+    }
+    pub fn get_client_classes(&self) -> &[String] {
+        &self.client_classes
+    }
+}
+
+// pushed by the server to the subscribers of a device, not acknowledged
+#[derive(Debug, Clone, Serialize, Deserialize, Encode, Decode)]
+pub struct PresencePkg {
+    event: u8,
+    client_class: String,
+    status: DeviceStatusPkg,
+    endpoint_addresses: Vec<String>,
+}
+
+impl PresencePkg {
+    // registered after being offline or unknown
+    pub const EVENT_ONLINE: u8 = 0x01;
+    // registered again from another endpoint
+    pub const EVENT_ENDPOINT_CHANGED: u8 = 0x02;
+    // nothing heard for the server's online timeout
+    pub const EVENT_OFFLINE: u8 = 0x03;
+    pub fn new(event: u8, client_class: String, status: DeviceStatusPkg, endpoint_addresses: Vec<String>) -> Self {
+        Self {
+            event,
+            client_class,
+            status,
+            endpoint_addresses,
+        }
+    }
+    pub fn get_event(&self) -> u8 {
+        self.event
+    }
+    pub fn get_client_class(&self) -> String {
+        self.client_class.clone()
+    }
+    pub fn get_status(&self) -> DeviceStatusPkg {
+        self.status.clone()
+    }
+    pub fn get_endpoint_addresses(&self) -> Vec<String> {
+        self.endpoint_addresses.clone()
+    }
+}
+
+// The server refusing a request or relay. `denied_type` is the package type the
+// sender waits for, so the denial ends that wait, or TYPE_DENIED if it waits for nothing.
+#[derive(Debug, Serialize, Deserialize, Encode, Decode)]
+pub struct DeniedPkg {
+    denied_type: u8,
+    target_global_id: String,
+    reason: String,
+    // of the denied request, 0 for a relay
+    nonce: u64,
+}
+
+impl DeniedPkg {
+    pub fn new(denied_type: u8, target_global_id: String, reason: &str) -> Self {
+        Self {
+            denied_type,
+            target_global_id,
+            reason: reason.to_string(),
+            nonce: 0,
+        }
+    }
+    pub fn with_nonce(mut self, nonce: u64) -> Self {
+        self.nonce = nonce;
+        self
+    }
+    pub fn get_nonce(&self) -> u64 {
+        self.nonce
+    }
+    pub fn get_denied_type(&self) -> u8 {
+        self.denied_type
+    }
+    pub fn get_target_global_id(&self) -> String {
+        self.target_global_id.clone()
+    }
+    pub fn get_reason(&self) -> String {
+        self.reason.clone()
+    }
+}
+
+// The server's answer to a hello, with what the server speaks. Servers from
+// before negotiation answer with an empty HELLO_ACK.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HelloAckPkg {
+    version: u16,
+    capabilities: u32,
+    nonce: u64,
+    // With SESSION, the identity of the device's packages after the hello. Trails
+    // the package, left out for devices without SESSION.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    session: String,
+}
+
+impl Encode for HelloAckPkg {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        self.version.encode(encoder)?;
+        self.capabilities.encode(encoder)?;
+        self.nonce.encode(encoder)?;
+        if !self.session.is_empty() {
+            self.session.encode(encoder)?;
+        }
+        Ok(())
+    }
+}
+
+impl<Context> Decode<Context> for HelloAckPkg {
+    fn decode<D: Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        let version = u16::decode(decoder)?;
+        let capabilities = u32::decode(decoder)?;
+        let nonce = u64::decode(decoder)?;
+        let session = if has_trailing(decoder) { String::decode(decoder)? } else { String::new() };
+        Ok(Self { version, capabilities, nonce, session })
+    }
+}
+
+impl HelloAckPkg {
+    pub fn new(protocol: ProtocolVersion) -> Self {
+        Self { version: protocol.version, capabilities: protocol.capabilities, nonce: 0, session: String::new() }
+    }
+    pub fn with_nonce(mut self, nonce: u64) -> Self {
+        self.nonce = nonce;
+        self
+    }
+    pub fn with_session(mut self, session: &str) -> Self {
+        self.session = session.to_string();
+        self
+    }
+    pub fn get_nonce(&self) -> u64 {
+        self.nonce
+    }
+    pub fn get_protocol(&self) -> ProtocolVersion {
+        ProtocolVersion { version: self.version, capabilities: self.capabilities }
+    }
+    pub fn get_session(&self) -> String {
+        self.session.clone()
+    }
+}
+
+// A server package wrapped with the server's signature over it, see core::server_key
+#[derive(Debug, Serialize, Deserialize, Encode, Decode)]
+pub struct SignedPkg {
+    #[serde(with = "serde_bytes")]
+    package: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    signature: Vec<u8>,
+}
+
+impl SignedPkg {
+    pub fn new(package: Vec<u8>, signature: Vec<u8>) -> Self {
+        Self { package, signature }
+    }
+    pub fn get_package(&self) -> &[u8] {
+        &self.package
+    }
+    pub fn get_signature(&self) -> &[u8] {
+        &self.signature
+    }
+}
+
+// sent by the server to both sides of a tcp punch, with the other side's observed tcp endpoint
+#[derive(Debug, Serialize, Deserialize, Encode, Decode)]
+pub struct TcpPunchPkg {
+    peer_global_id: String,
+    endpoint_address: String,
+    // of the requester's punch request, 0 in the peer's copy
+    nonce: u64,
+}
+
+impl TcpPunchPkg {
+    pub fn new(peer_global_id: String, endpoint_address: String) -> Self {
+        Self {
+            peer_global_id,
+            endpoint_address,
+            nonce: 0,
+        }
+    }
+    pub fn with_nonce(mut self, nonce: u64) -> Self {
+        self.nonce = nonce;
+        self
+    }
+    pub fn get_nonce(&self) -> u64 {
+        self.nonce
+    }
+    pub fn get_peer_global_id(&self) -> String {
+        self.peer_global_id.clone()
+    }
+    pub fn get_endpoint_address(&self) -> String {
+        self.endpoint_address.clone()
+    }
+}
+
+// Multicast on the lan by clients with lan discovery enabled, see client_lib::discovery.
+// It carries no identity, the tag shows the sender knows the lan's discovery key.
+#[derive(Debug, Clone, Serialize, Deserialize, Encode, Decode)]
+pub struct LanDiscoveryPkg {
+    client_class: String,
+    client_instance: String,
+    msg: u8,
+    // the port the client's transport listens on, the address is the package source
+    endpoint_port: u16,
+    // tenant of the device, empty for the default one
+    namespace: String,
+    // HMAC-SHA256 over the package with an empty tag, keyed with the discovery key
+    #[serde(with = "serde_bytes")]
+    tag: Vec<u8>,
+}
+
+impl LanDiscoveryPkg {
+    pub const MSG_ANNOUNCE: u8 = 0x01;
+    // everyone hearing a query announces itself
+    pub const MSG_QUERY: u8 = 0x02;
+    pub fn new(base_info: BasePkg, msg: u8, endpoint_port: u16) -> Self {
+        Self {
+            client_class: base_info.client_class,
+            client_instance: base_info.client_instance,
+            msg,
+            endpoint_port,
+            namespace: base_info.namespace,
+            tag: Vec::new(),
+        }
+    }
+    // the announced device, without an identity
+    pub fn get_peer(&self) -> BasePkg {
+        BasePkg {
+            client_class: self.client_class.clone(),
+            client_instance: self.client_instance.clone(),
+            identity: String::new(),
+            namespace: self.namespace.clone(),
+        }
+    }
+    pub fn get_msg(&self) -> u8 {
+        self.msg
+    }
+    pub fn get_endpoint_port(&self) -> u16 {
+        self.endpoint_port
+    }
+    pub fn with_tag(mut self, tag: Vec<u8>) -> Self {
+        self.tag = tag;
+        self
+    }
+    pub fn get_tag(&self) -> &[u8] {
+        &self.tag
+    }
+    // what the tag is computed over
+    pub fn untagged(&self) -> Self {
+        Self { tag: Vec::new(), ..self.clone() }
+    }
+}
+
+// Target required for pkg forward. The target is in the sender's namespace, which
+// trails the package in bincode.
+#[derive(Debug, Serialize, Deserialize)]
 pub struct PeerExchangePkg {
     base_info: BasePkg,
+    #[serde(with = "serde_bytes")]
     payload: Vec<u8>,
     target: Option<BasePkg>,
 }
@@ -152,7 +870,34 @@ impl PeerExchangePkg {
         self.payload.clone()
     }
     pub fn get_target(&self) -> Option<BasePkg> {
-        self.target.clone()
+        self.target.clone().map(|target| BasePkg { namespace: self.base_info.namespace.clone(), ..target })
+    }
+    // what the server relays and peers get, no credential of either device
+    pub fn without_identity(self) -> Self {
+        Self {
+            base_info: self.base_info.without_identity(),
+            target: self.target.as_ref().map(BasePkg::without_identity),
+            ..self
+        }
+    }
+}
+
+impl Encode for PeerExchangePkg {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        self.base_info.encode(encoder)?;
+        self.payload.encode(encoder)?;
+        self.target.encode(encoder)?;
+        encode_namespace(&self.base_info.namespace, false, encoder)
+    }
+}
+
+impl<Context> Decode<Context> for PeerExchangePkg {
+    fn decode<D: Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        let mut base_info = BasePkg::decode(decoder)?;
+        let payload = Vec::<u8>::decode(decoder)?;
+        let target = Option::<BasePkg>::decode(decoder)?;
+        base_info.namespace = decode_namespace(decoder)?;
+        Ok(Self { base_info, payload, target })
     }
 }
 
@@ -167,7 +912,7 @@ mod test {
     use bincode::{config, Decode, Encode};
     use serde::{Deserialize, Serialize};
 
-    use crate::core::uprotocol_pkg::BasePkg;
+    use crate::{core::{bincodec::BinCodec, protocol_version::ProtocolVersion, uprotocol_pkg::{BasePkg, GetBaseInfo}}, test_support::test_base_info};
 
     #[derive(Debug, Serialize, Deserialize, Encode, Decode)]
     struct ReClientHelloPkg {
@@ -195,36 +940,80 @@ mod test {
         assert!(encoded == encoded2);
     }
 
+    #[tokio::test]
+    async fn test_hello_without_version() {
+        // the payload of a hello as the release before negotiation encodes it, it ends after msg
+        let encoded = [3, 99, 108, 105, 6, 108, 101, 103, 97, 99, 121, 4, 116, 101, 115, 116, 1];
+        let client_hello_pkg = super::ClientHelloPkg::decode_from(&encoded).unwrap();
+        assert_eq!(client_hello_pkg.get_baseinfo(), &BasePkg { identity: "test".to_string(), ..test_base_info("cli", "legacy") });
+        assert_eq!(client_hello_pkg.get_msg(), 0x01);
+        assert_eq!(client_hello_pkg.get_protocol(), ProtocolVersion::LEGACY);
+        assert_eq!(client_hello_pkg.encode_to_vec().unwrap(), encoded);
+        let encoded = super::ClientHelloPkg::new("cli", "peer1", "test", 0x01).with_protocol(ProtocolVersion::CURRENT).encode_to_vec().unwrap();
+        assert_eq!(super::ClientHelloPkg::decode_from(&encoded).unwrap().get_protocol(), ProtocolVersion::CURRENT);
+    }
+
+    #[tokio::test]
+    async fn test_namespace_trails() {
+        let hello = super::ClientHelloPkg::new("cli", "peer1", "test", 0x01).in_namespace("acme").encode_to_vec().unwrap();
+        let hello = super::ClientHelloPkg::decode_from(&hello).unwrap();
+        assert_eq!(hello.get_baseinfo().namespace, "acme");
+        assert_eq!(hello.get_protocol(), ProtocolVersion::LEGACY);
+
+        // what older releases read is unchanged, the target is in the sender's namespace
+        let base_info = BasePkg { namespace: "acme".to_string(), ..test_base_info("cli", "peer1") };
+        let exchange = super::PeerExchangePkg::new(base_info.clone(), b"hi".to_vec(), Some(test_base_info("cli", "peer2"))).encode_to_vec().unwrap();
+        let legacy = bincode::encode_to_vec((test_base_info("cli", "peer1"), b"hi".to_vec(), Some(test_base_info("cli", "peer2"))), config::standard()).unwrap();
+        assert!(exchange.starts_with(&legacy));
+        let exchange = super::PeerExchangePkg::decode_from(&exchange).unwrap();
+        assert_eq!(exchange.get_baseinfo(), &base_info);
+        assert_eq!(exchange.get_target().unwrap().namespace, "acme");
+        assert_eq!(super::PeerExchangePkg::decode_from(&legacy).unwrap().get_baseinfo().namespace, "");
+    }
+
     #[tokio::test]
     async fn test_base_info_eq() {
         let base_info1 = BasePkg {
             client_class: "test".to_string(),
             client_instance: "test".to_string(),
             identity: "test".to_string(),
+            namespace: String::new(),
         };
         let base_info2 = BasePkg {
             client_class: "test".to_string(),
             client_instance: "test".to_string(),
             identity: "test".to_string(),
+            namespace: String::new(),
         };
         assert_eq!(base_info1, base_info2);
         let base_info3 = BasePkg {
             client_class: "test".to_string(),
             client_instance: "test".to_string(),
             identity: "test_a".to_string(),
+            namespace: String::new(),
         };
         assert_eq!(base_info1, base_info3);
         let base_info4 = BasePkg {
             client_class: "test_a".to_string(),
             client_instance: "test".to_string(),
             identity: "test".to_string(),
+            namespace: String::new(),
         };
         assert_ne!(base_info1, base_info4);
         let base_info5 = BasePkg {
             client_class: "test".to_string(),
             client_instance: "test_a".to_string(),
             identity: "test".to_string(),
+            namespace: String::new(),
         };
         assert_ne!(base_info1, base_info5);
+        // the same device name in another tenant is another device
+        let base_info6 = BasePkg {
+            client_class: "test".to_string(),
+            client_instance: "test".to_string(),
+            identity: "test".to_string(),
+            namespace: "acme".to_string(),
+        };
+        assert_ne!(base_info1, base_info6);
     }
 }
\ No newline at end of file
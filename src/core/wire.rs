@@ -0,0 +1,261 @@
+// The framing every up2p package travels in, specified in docs/wire-format.md.
+//
+//  0       2         3      4        6
+//  +-------+---------+------+--------+---------------+
+//  | magic | version | type | length | payload ...   |
+//  +-------+---------+------+--------+---------------+
+//
+// magic is "U2", version is FRAME_VERSION with the payload's codec in its high
+// nibble, length is the big endian byte count of the payload and nothing may
+// follow it. The payload is the package of `type`.
+//
+// Frames without the magic are in the framing of the release before it, taken
+// from and sent to that release's clients until the next release drops them:
+// bincode of (payload length as u8, type, payload).
+use std::{fmt, ops::RangeInclusive};
+
+use bincode::error::DecodeError;
+
+use super::codec::Codec;
+
+pub const MAGIC: [u8; 2] = *b"U2";
+pub const FRAME_VERSION: u8 = 0x01;
+pub const HEADER_LEN: usize = 6;
+pub const MAX_PAYLOAD_LEN: usize = u16::MAX as usize;
+// the package types the release before the frame knew
+pub const LEGACY_TYPES: RangeInclusive<u8> = 0x01..=0x07;
+
+// Why a frame or package was rejected. Decoders return it inside anyhow::Error,
+// downcast to tell malformed input apart from other failures.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WireError {
+    // fewer bytes than the header or the field being read needs
+    Truncated,
+    BadMagic([u8; 2]),
+    UnsupportedVersion(u8),
+    // a codec this implementation does not know, or the deployment does not take
+    UnsupportedCodec(u8),
+    // the header's length disagrees with the bytes after it
+    LengthMismatch { declared: usize, actual: usize },
+    PayloadTooLarge(usize),
+    // a package type this implementation does not know
+    UnknownType(u8),
+    // a package decoded without using all of its payload
+    TrailingBytes(usize),
+    // an integer whose varint marker is reserved or too wide for the field
+    MalformedVarint,
+    InvalidUtf8,
+    // a bool, option tag or enum discriminant out of range
+    InvalidValue(String),
+    // a length claiming more than a package can hold
+    LimitExceeded,
+}
+
+impl fmt::Display for WireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WireError::Truncated => write!(f, "truncated package"),
+            WireError::BadMagic(magic) => write!(f, "bad magic {:02x?}", magic),
+            WireError::UnsupportedVersion(version) => write!(f, "unsupported frame version {}", version),
+            WireError::UnsupportedCodec(codec) => write!(f, "unsupported codec {}", codec),
+            WireError::LengthMismatch { declared, actual } => write!(f, "frame declares {} payload bytes but carries {}", declared, actual),
+            WireError::PayloadTooLarge(len) => write!(f, "payload of {} bytes is too large", len),
+            WireError::UnknownType(package_type) => write!(f, "unknown package type {}", package_type),
+            WireError::TrailingBytes(len) => write!(f, "{} trailing bytes after package", len),
+            WireError::MalformedVarint => write!(f, "malformed varint"),
+            WireError::InvalidUtf8 => write!(f, "invalid utf-8 in string"),
+            WireError::InvalidValue(value) => write!(f, "invalid value: {}", value),
+            WireError::LimitExceeded => write!(f, "length exceeds the package limit"),
+        }
+    }
+}
+
+impl std::error::Error for WireError {}
+
+impl From<DecodeError> for WireError {
+    fn from(error: DecodeError) -> Self {
+        match error {
+            DecodeError::UnexpectedEnd { .. } => WireError::Truncated,
+            DecodeError::InvalidIntegerType { .. } => WireError::MalformedVarint,
+            DecodeError::Utf8 { .. } => WireError::InvalidUtf8,
+            DecodeError::LimitExceeded => WireError::LimitExceeded,
+            other => WireError::InvalidValue(other.to_string()),
+        }
+    }
+}
+
+impl From<ciborium::de::Error<std::io::Error>> for WireError {
+    fn from(error: ciborium::de::Error<std::io::Error>) -> Self {
+        match error {
+            ciborium::de::Error::Io(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => WireError::Truncated,
+            ciborium::de::Error::RecursionLimitExceeded => WireError::LimitExceeded,
+            other => WireError::InvalidValue(other.to_string()),
+        }
+    }
+}
+
+pub fn encode_frame(codec: Codec, package_type: u8, payload: &[u8]) -> Result<Vec<u8>, WireError> {
+    if payload.len() > MAX_PAYLOAD_LEN {
+        return Err(WireError::PayloadTooLarge(payload.len()));
+    }
+    if codec == Codec::Legacy {
+        return encode_legacy_frame(package_type, payload);
+    }
+    let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+    frame.extend_from_slice(&MAGIC);
+    frame.push(FRAME_VERSION | (codec.id() << 4));
+    frame.push(package_type);
+    frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    frame.extend_from_slice(payload);
+    Ok(frame)
+}
+
+// the codec, package type and payload of one frame, which must span all of `data`
+pub fn decode_frame(data: &[u8]) -> Result<(Codec, u8, &[u8]), WireError> {
+    if is_legacy(data) {
+        return decode_legacy_frame(data);
+    }
+    if data.len() < HEADER_LEN {
+        return Err(WireError::Truncated);
+    }
+    if data[0..2] != MAGIC {
+        return Err(WireError::BadMagic([data[0], data[1]]));
+    }
+    if data[2] & 0x0f != FRAME_VERSION {
+        return Err(WireError::UnsupportedVersion(data[2] & 0x0f));
+    }
+    let codec = Codec::from_id(data[2] >> 4)?;
+    let declared = u16::from_be_bytes([data[4], data[5]]) as usize;
+    let payload = &data[HEADER_LEN..];
+    if payload.len() != declared {
+        return Err(WireError::LengthMismatch { declared, actual: payload.len() });
+    }
+    Ok((codec, data[3], payload))
+}
+
+// the package type of a frame from its header alone, for checks before decoding
+pub fn peek_type(data: &[u8]) -> Option<u8> {
+    if is_legacy(data) {
+        return Some(data[1]);
+    }
+    (data.len() >= HEADER_LEN && data[0..2] == MAGIC && data[2] & 0x0f == FRAME_VERSION).then(|| data[3])
+}
+
+// the codec of a frame from its header alone
+pub fn peek_codec(data: &[u8]) -> Option<Codec> {
+    if is_legacy(data) {
+        return Some(Codec::Legacy);
+    }
+    peek_type(data)?;
+    Codec::from_id(data[2] >> 4).ok()
+}
+
+// a legacy frame's type is never the second byte of the magic
+fn is_legacy(data: &[u8]) -> bool {
+    data.len() >= 3 && LEGACY_TYPES.contains(&data[1])
+}
+
+fn encode_legacy_frame(package_type: u8, payload: &[u8]) -> Result<Vec<u8>, WireError> {
+    if !LEGACY_TYPES.contains(&package_type) {
+        return Err(WireError::UnknownType(package_type));
+    }
+    let mut frame = vec![payload.len() as u8, package_type];
+    // bincode's varint, a payload is at most u16::MAX bytes
+    if payload.len() < 251 {
+        frame.push(payload.len() as u8);
+    } else {
+        frame.push(251);
+        frame.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+    }
+    frame.extend_from_slice(payload);
+    Ok(frame)
+}
+
+fn decode_legacy_frame(data: &[u8]) -> Result<(Codec, u8, &[u8]), WireError> {
+    let (declared, len): (u64, usize) = bincode::decode_from_slice(&data[2..], crate::get_binencode_config())?;
+    let payload = &data[2 + len..];
+    if payload.len() > MAX_PAYLOAD_LEN {
+        return Err(WireError::PayloadTooLarge(payload.len()));
+    }
+    // in its shortest form, so a frame has one encoding
+    if len != if payload.len() < 251 { 1 } else { 3 } {
+        return Err(WireError::MalformedVarint);
+    }
+    if declared != payload.len() as u64 {
+        return Err(WireError::LengthMismatch { declared: declared as usize, actual: payload.len() });
+    }
+    // the first byte repeats the length, cut to a byte
+    if data[0] != payload.len() as u8 {
+        return Err(WireError::LengthMismatch { declared: data[0] as usize, actual: payload.len() });
+    }
+    Ok((Codec::Legacy, data[1], payload))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::core::{bincodec::BinCodec, codec::Codec, protocol_version::ProtocolVersion, uprotocol_pkg::ClientHelloPkg, Up2pMessage};
+
+    use super::{decode_frame, encode_frame, peek_codec, WireError, HEADER_LEN};
+
+    #[tokio::test]
+    async fn test_frame() {
+        let frame = encode_frame(Codec::Bincode, 0x05, b"payload").unwrap();
+        assert_eq!(&frame[..HEADER_LEN], &[b'U', b'2', 0x01, 0x05, 0x00, 0x07]);
+        assert_eq!(decode_frame(&frame).unwrap(), (Codec::Bincode, 0x05, &b"payload"[..]));
+
+        assert_eq!(decode_frame(&frame[..3]), Err(WireError::Truncated));
+        assert_eq!(decode_frame(&frame[..9]), Err(WireError::LengthMismatch { declared: 7, actual: 3 }));
+        let mut trailing = frame.clone();
+        trailing.push(0);
+        assert_eq!(decode_frame(&trailing), Err(WireError::LengthMismatch { declared: 7, actual: 8 }));
+        let mut bad_magic = frame.clone();
+        bad_magic[0] = 0;
+        assert_eq!(decode_frame(&bad_magic), Err(WireError::BadMagic([0, b'2'])));
+        let mut newer = frame.clone();
+        newer[2] = 0x02;
+        assert_eq!(decode_frame(&newer), Err(WireError::UnsupportedVersion(0x02)));
+        assert!(encode_frame(Codec::Bincode, 0x03, &vec![0; 70000]).is_err());
+
+        let cbor = encode_frame(Codec::Cbor, 0x05, b"payload").unwrap();
+        assert_eq!(cbor[2], 0x11);
+        assert_eq!(peek_codec(&cbor), Some(Codec::Cbor));
+        assert_eq!(decode_frame(&cbor).unwrap(), (Codec::Cbor, 0x05, &b"payload"[..]));
+        let mut unknown_codec = cbor.clone();
+        unknown_codec[2] = 0x71;
+        assert_eq!(decode_frame(&unknown_codec), Err(WireError::UnsupportedCodec(0x07)));
+    }
+
+    #[tokio::test]
+    async fn test_legacy_frame() {
+        // a hello as the release before the frame encodes it
+        let hello = [17, 1, 17, 3, 99, 108, 105, 6, 108, 101, 103, 97, 99, 121, 4, 116, 101, 115, 116, 1];
+        assert_eq!(decode_frame(&hello).unwrap(), (Codec::Legacy, 0x01, &hello[3..]));
+        assert_eq!(peek_codec(&hello), Some(Codec::Legacy));
+        assert_eq!(encode_frame(Codec::Legacy, 0x01, &hello[3..]).unwrap(), hello);
+        assert_eq!(decode_frame(&hello[..19]), Err(WireError::LengthMismatch { declared: 17, actual: 16 }));
+        let mut wrong_len = hello;
+        wrong_len[0] = 16;
+        assert_eq!(decode_frame(&wrong_len), Err(WireError::LengthMismatch { declared: 16, actual: 17 }));
+
+        // the length is a varint, the first byte only its low byte
+        let long = encode_frame(Codec::Legacy, 0x07, &[0; 300]).unwrap();
+        assert_eq!(&long[..5], &[44, 0x07, 251, 44, 1]);
+        assert_eq!(decode_frame(&long).unwrap(), (Codec::Legacy, 0x07, &[0; 300][..]));
+        assert_eq!(decode_frame(&[3, 0x07, 251, 3, 0, 1, 2, 3]), Err(WireError::MalformedVarint));
+        // that release knew no other types
+        assert_eq!(encode_frame(Codec::Legacy, 0x0D, &[]), Err(WireError::UnknownType(0x0D)));
+    }
+
+    // the example in docs/wire-format.md
+    #[tokio::test]
+    async fn test_spec_example() {
+        let hello = Up2pMessage::Hello(ClientHelloPkg::new("cli", "peer1", "test", ClientHelloPkg::MSG_HELLO).with_protocol(ProtocolVersion::CURRENT));
+        assert_eq!(hello.encode_to_vec().unwrap(), [
+            &[0x55, 0x32, 0x01, 0x01, 0x00, 0x12][..],
+            &[0x03], b"cli",
+            &[0x05], b"peer1",
+            &[0x04], b"test",
+            &[0x01, 0x02, 0x1f],
+        ].concat());
+    }
+}
@@ -1,31 +1,34 @@
 use std::{sync::Arc, time::Duration};
 
-use bincode::config;
 use serde::{Deserialize, Serialize};
 use tokio::{net::UdpSocket, time};
-use up2p::core::{bincodec::BinCodec, uprotocol_pkg::{ClientHelloPkg, ClientRequestPkg}, BaseUp2pProtocol};
+use up2p::core::{bincodec::BinCodec, uprotocol_pkg::{ClientHelloPkg, ClientRequestPkg}, BaseUp2pProtocol, Up2pMessage};
 
 pub const CLIENT_CLASS: &str = "demo_up2pc";
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     // client hello
-    let udp_socket = Arc::new(UdpSocket::bind("0.0.0.0:0").await.unwrap());
     let client_config = ClientConfig::parse_config()?;
-    let test_protocol_data = BaseUp2pProtocol::client_hello_with_payload(
+    let server_address = tokio::net::lookup_host(&client_config.server_address).await?.next()
+        .ok_or_else(|| anyhow::anyhow!("failed to resolve {}", client_config.server_address))?;
+    // bind in the family of the server address
+    let bind_address = if server_address.is_ipv6() { "[::]:0" } else { "0.0.0.0:0" };
+    let udp_socket = Arc::new(UdpSocket::bind(bind_address).await.unwrap());
+    let test_protocol_data = Up2pMessage::Hello(
         ClientHelloPkg::new(CLIENT_CLASS, &client_config.client_instance, &client_config.identity, 0x1)
-    ).unwrap();
+    );
     
-    let data = bincode::encode_to_vec(&test_protocol_data, config::standard()).unwrap();
-    udp_socket.send_to(&data, &client_config.server_address).await.unwrap();
+    let data = test_protocol_data.encode_to_vec().unwrap();
+    udp_socket.send_to(&data, server_address).await.unwrap();
     time::sleep(Duration::from_secs(1)).await;
 
     // client request
-    let test_protocol_data = BaseUp2pProtocol::request_with_payload(
+    let test_protocol_data = Up2pMessage::Request(
         ClientRequestPkg::create_endpoint_request(
             CLIENT_CLASS, &client_config.client_instance, &client_config.identity, &up2p::utils::get_global_id(CLIENT_CLASS, &client_config.client_instance)
         )
-    )?;
+    );
     let uu = udp_socket.clone();
     let handle = tokio::spawn(async move {
         let mut buf = vec![0; 1024];
@@ -36,8 +39,8 @@ async fn main() -> anyhow::Result<()> {
         println!("Received: {:?}", s);
     });
     time::sleep(Duration::from_secs(1)).await;
-    let data = bincode::encode_to_vec(&test_protocol_data, config::standard()).unwrap();
-    udp_socket.send_to(&data, client_config.server_address).await.unwrap();
+    let data = test_protocol_data.encode_to_vec().unwrap();
+    udp_socket.send_to(&data, server_address).await.unwrap();
     handle.await.unwrap();
     Ok(())
 }
@@ -54,8 +57,10 @@ impl ClientConfig {
         let config: Self = toml::from_str(toml)?;
         Ok(config)
     }
+    // config path from the first argument, up2pc.toml by default
     pub fn parse_config() -> anyhow::Result<Self> {
-        let config = std::fs::read_to_string("up2pc.toml")?;
+        let path = std::env::args().nth(1).unwrap_or_else(|| "up2pc.toml".to_string());
+        let config = std::fs::read_to_string(path)?;
         Self::parse_toml(&config)
     }
 }
\ No newline at end of file
@@ -1,32 +1,40 @@
 use std::{str::FromStr, sync::Arc};
 
 use serde::{Deserialize, Serialize};
-use tokio::net::UdpSocket;
 use tracing::{info, Level};
-use up2p::{client_lib::app::Up2pCli, core::{request_info::RequestInfo, uprotocol_pkg::BasePkg}};
+use up2p::{client_lib::app::Up2pCli, transport::bind_dual_stack_udp, core::{request_info::RequestInfo, server_key::PinnedServerKey, uprotocol_pkg::BasePkg}};
 
 #[tokio::main]
 async fn main () -> anyhow::Result<()> {
     let client_config = ClientConfig::parse_config().unwrap();
     let _client_instance_config = client_config.clone();
     tracing_subscriber::fmt().with_max_level(Level::from_str(&client_config.log_level)?).init();
-    let udp_socket = Arc::new(UdpSocket::bind("0.0.0.0:0").await.unwrap());
-    let server_address = client_config.server_address.parse::<std::net::SocketAddr>().unwrap();
-    let up2p_client = Up2pCli::new(BasePkg {
+    let udp_socket = Arc::new(bind_dual_stack_udp(0)?);
+    // the first address is the main one, the others let the server learn our other address family
+    let mut server_addresses = tokio::net::lookup_host(&client_config.server_address).await?;
+    let server_address = server_addresses.next()
+        .ok_or_else(|| anyhow::anyhow!("failed to resolve {}", client_config.server_address))?;
+    let mut up2p_client = Up2pCli::new(BasePkg {
         client_instance: client_config.client_instance,
         client_class: "cli".to_string(),
         identity: client_config.identity,
+        namespace: String::new(),
     }, udp_socket, (server_address.ip(), server_address.port()));
+    server_addresses.for_each(|extra_address| up2p_client.0.add_server_address(extra_address));
+    if let Some(server_key) = &client_config.server_key {
+        up2p_client.0.pin_server_key(PinnedServerKey::from_hex(server_key)?);
+    }
     up2p_client.0.start().await.unwrap();
     up2p_client.0.client_hello().await?;
     let r = up2p_client.0.client_request(RequestInfo { client_class: "cli".to_string(), client_instance: "peer1".to_string() }).await?.unwrap();
-    let _result = up2p_client.0.pkg_send_to(
+    up2p_client.0.pkg_send_to(
         // vec 255-0
-        client_config.server_address.parse().unwrap(), Vec::from_iter(0..255u8),
+        server_address, Vec::from_iter(0..255u8),
         Some(BasePkg {
             client_instance: "peer1".to_string(),
             client_class: "cli".to_string(),
             identity: "bbb".to_string(),
+            namespace: String::new(),
         })
     ).await?;
     info!("endpoint: {:?}", r);
@@ -39,6 +47,9 @@ struct ClientConfig {
     identity: String,
     server_address: String,
     log_level: String,
+    // the server's verifying key, packages it did not sign are dropped
+    #[serde(default)]
+    server_key: Option<String>,
 }
 
 impl ClientConfig {
@@ -46,8 +57,10 @@ impl ClientConfig {
         let config: Self = toml::from_str(toml)?;
         Ok(config)
     }
+    // config path from the first argument, peer2.toml by default
     pub fn parse_config() -> anyhow::Result<Self> {
-        let config = std::fs::read_to_string("peer2.toml")?;
+        let path = std::env::args().nth(1).unwrap_or_else(|| "peer2.toml".to_string());
+        let config = std::fs::read_to_string(path)?;
         Self::parse_toml(&config)
     }
 }
\ No newline at end of file
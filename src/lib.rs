@@ -1,8 +1,15 @@
 pub mod core;
 pub mod utils;
 pub mod client_lib;
+pub mod server;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
+pub mod transport;
 // pub mod state;
 
-pub fn get_binencode_config() -> bincode::config::Configuration {
-    bincode::config::standard()
+// no package is larger than a frame, so no length in one may claim more
+pub const DECODE_LIMIT: usize = core::wire::MAX_PAYLOAD_LEN;
+
+pub fn get_binencode_config() -> bincode::config::Configuration<bincode::config::LittleEndian, bincode::config::Varint, bincode::config::Limit<DECODE_LIMIT>> {
+    bincode::config::standard().with_limit::<DECODE_LIMIT>()
 }
\ No newline at end of file
@@ -0,0 +1,111 @@
+use serde::{Deserialize, Serialize};
+
+use crate::core::uprotocol_pkg::BasePkg;
+
+// Who may look up, relay to or punch through to whom. A matching deny rule wins
+// over any allow rule, devices no rule matches get `default_action`.
+//
+// [acl]
+// default_action = "deny"
+// [[acl.rules]]
+// from_class = "cli"
+// to_class = "sensor"
+// [[acl.rules]]
+// action = "deny"
+// from_class = "cli"
+// from_instance = "guest"
+// to_class = "*"
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AclConfig {
+    pub default_action: AclAction,
+    pub rules: Vec<AclRule>,
+}
+
+impl Default for AclConfig {
+    // everyone may reach everyone, as without an acl
+    fn default() -> Self {
+        Self {
+            default_action: AclAction::Allow,
+            rules: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AclAction {
+    Allow,
+    Deny,
+}
+
+// classes match exactly or with "*", a missing instance matches every instance
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AclRule {
+    #[serde(default = "default_rule_action")]
+    pub action: AclAction,
+    pub from_class: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from_instance: Option<String>,
+    pub to_class: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to_instance: Option<String>,
+}
+
+fn default_rule_action() -> AclAction {
+    AclAction::Allow
+}
+
+impl AclRule {
+    fn matches(&self, from: &BasePkg, to_class: &str, to_instance: &str) -> bool {
+        let class_matches = |pattern: &str, class: &str| pattern == "*" || pattern == class;
+        let instance_matches = |pattern: &Option<String>, instance: &str| pattern.as_deref().is_none_or(|pattern| pattern == instance);
+        class_matches(&self.from_class, &from.client_class)
+            && instance_matches(&self.from_instance, &from.client_instance)
+            && class_matches(&self.to_class, to_class)
+            && instance_matches(&self.to_instance, to_instance)
+    }
+}
+
+impl AclConfig {
+    pub fn allows(&self, from: &BasePkg, to_class: &str, to_instance: &str) -> bool {
+        let matching: Vec<&AclRule> = self.rules.iter().filter(|rule| rule.matches(from, to_class, to_instance)).collect();
+        if matching.iter().any(|rule| rule.action == AclAction::Deny) {
+            return false;
+        }
+        matching.iter().any(|rule| rule.action == AclAction::Allow) || self.default_action == AclAction::Allow
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test_support::test_base_info;
+
+    use super::AclConfig;
+
+    #[tokio::test]
+    async fn test_acl_rules() {
+        let acl: AclConfig = toml::from_str(r#"
+            default_action = "deny"
+            [[rules]]
+            from_class = "cli"
+            to_class = "sensor"
+            [[rules]]
+            action = "deny"
+            from_class = "cli"
+            from_instance = "guest"
+            to_class = "*"
+            [[rules]]
+            from_class = "*"
+            to_class = "cli"
+            to_instance = "peer1"
+        "#).unwrap();
+        assert!(acl.allows(&test_base_info("cli", "peer2"), "sensor", "kitchen"));
+        assert!(!acl.allows(&test_base_info("cli", "peer2"), "camera", "door"));
+        // deny wins over the class rule
+        assert!(!acl.allows(&test_base_info("cli", "guest"), "sensor", "kitchen"));
+        assert!(acl.allows(&test_base_info("sensor", "kitchen"), "cli", "peer1"));
+        assert!(!acl.allows(&test_base_info("sensor", "kitchen"), "cli", "peer2"));
+        assert!(AclConfig::default().allows(&test_base_info("cli", "guest"), "camera", "door"));
+    }
+}
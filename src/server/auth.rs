@@ -0,0 +1,41 @@
+use std::sync::RwLock;
+
+use crate::core::uprotocol_pkg::BasePkg;
+
+// Decides whether a package sender is allowed to talk to the server
+pub trait AuthProvider: Send + Sync {
+    fn verify(&self, base_info: &BasePkg) -> anyhow::Result<()>;
+    // turn `global_id` away from now on, see Up2pServer::revoke
+    fn revoke(&self, global_id: &str) -> anyhow::Result<()> {
+        Err(anyhow::anyhow!("Revoking {} is not supported by this auth provider", global_id))
+    }
+    // every device revoked so far, see Up2pServer::evict_revoked
+    fn revoked_devices(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+// every client presents the same shared identity string
+#[derive(Debug)]
+pub struct SharedIdentityAuth {
+    identity: RwLock<String>,
+}
+
+impl SharedIdentityAuth {
+    pub fn new(identity: &str) -> Self {
+        Self { identity: RwLock::new(identity.to_string()) }
+    }
+    // takes effect for the next package
+    pub fn set_identity(&self, identity: &str) {
+        *self.identity.write().unwrap() = identity.to_string();
+    }
+}
+
+impl AuthProvider for SharedIdentityAuth {
+    fn verify(&self, base_info: &BasePkg) -> anyhow::Result<()> {
+        if base_info.identity != *self.identity.read().unwrap() {
+            return Err(anyhow::anyhow!("Identity not match"));
+        }
+        Ok(())
+    }
+}
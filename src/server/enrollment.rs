@@ -0,0 +1,257 @@
+use std::{collections::HashSet, fs::OpenOptions, io::{ErrorKind, Write}, path::{Path, PathBuf}, sync::{Arc, Mutex, RwLock}, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use bincode::{Decode, Encode};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+
+use crate::core::{bincodec::BinCodec, server_key::decode_key_hex, uprotocol_pkg::BasePkg};
+
+use super::auth::AuthProvider;
+
+// tells tokens apart from shared identities
+const TOKEN_PREFIX: &str = "enr.";
+
+// what a token vouches for
+#[derive(Debug, Serialize, Deserialize, Encode, Decode)]
+struct EnrollmentClaims {
+    client_class: String,
+    client_instance: String,
+    // unix seconds
+    expires_at: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Encode, Decode)]
+struct EnrollmentToken {
+    claims: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+// Issues enrollment tokens, held by whoever provisions devices. The server only
+// needs the verifying key.
+pub struct EnrollmentIssuer {
+    signing_key: SigningKey,
+}
+
+impl EnrollmentIssuer {
+    pub fn generate() -> Self {
+        Self { signing_key: SigningKey::from_bytes(&rand::random::<[u8; 32]>()) }
+    }
+    pub fn from_hex(secret: &str) -> anyhow::Result<Self> {
+        Ok(Self { signing_key: SigningKey::from_bytes(&decode_key_hex(secret)?) })
+    }
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.signing_key.to_bytes())
+    }
+    // goes into EnrollmentConfig::verifying_key
+    pub fn verifying_key_hex(&self) -> String {
+        hex::encode(self.signing_key.verifying_key().to_bytes())
+    }
+    // A token for one device, which presents it as its identity. It is short
+    // enough to leave room in the 255 byte hello.
+    pub fn issue(&self, client_class: &str, client_instance: &str, valid_for: Duration) -> anyhow::Result<String> {
+        let expires_at = SystemTime::now().checked_add(valid_for)
+            .ok_or_else(|| anyhow::anyhow!("valid_for is too long"))?;
+        let claims = EnrollmentClaims {
+            client_class: client_class.to_string(),
+            client_instance: client_instance.to_string(),
+            expires_at: unix_secs(expires_at),
+        }.encode_to_vec()?;
+        let signature = self.signing_key.sign(&claims).to_bytes().to_vec();
+        let token = EnrollmentToken { claims, signature }.encode_to_vec()?;
+        Ok(format!("{}{}", TOKEN_PREFIX, URL_SAFE_NO_PAD.encode(token)))
+    }
+}
+
+// [enrollment]
+// verifying_key = "<hex>"
+// revoked_devices = ["cli-peer3"]
+// revocation_file = "revoked_devices.txt"
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EnrollmentConfig {
+    pub verifying_key: String,
+    // global ids turned away whatever they present, until taken off the list
+    pub revoked_devices: Vec<String>,
+    // More of them, one per line. Up2pServer::revoke appends to it, so revocations
+    // outlive reloads and restarts. Read again on every reload.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revocation_file: Option<PathBuf>,
+    // also take the shared identity, while a fleet is only partly enrolled
+    pub allow_shared_identity: bool,
+}
+
+impl EnrollmentConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        EnrollmentState::try_from(self.clone()).map(|_| ())
+    }
+}
+
+// an EnrollmentConfig with its key decoded and revocation_file read, ready for EnrollmentAuth::set_state
+pub struct EnrollmentState {
+    verifying_key: VerifyingKey,
+    revoked_devices: HashSet<String>,
+    revocation_file: Option<PathBuf>,
+    allow_shared_identity: bool,
+}
+
+impl TryFrom<EnrollmentConfig> for EnrollmentState {
+    type Error = anyhow::Error;
+    fn try_from(config: EnrollmentConfig) -> anyhow::Result<Self> {
+        let mut revoked_devices: HashSet<String> = config.revoked_devices.into_iter().collect();
+        if let Some(revocation_file) = &config.revocation_file {
+            revoked_devices.extend(read_revocation_file(revocation_file)?);
+        }
+        Ok(Self {
+            verifying_key: VerifyingKey::from_bytes(&decode_key_hex(&config.verifying_key)?)?,
+            revoked_devices,
+            revocation_file: config.revocation_file,
+            allow_shared_identity: config.allow_shared_identity,
+        })
+    }
+}
+
+// a missing file has no revocations yet
+fn read_revocation_file(path: &Path) -> anyhow::Result<Vec<String>> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(anyhow::anyhow!("revocation_file {}: {}", path.display(), e)),
+    };
+    Ok(content.lines().map(str::trim).filter(|line| !line.is_empty()).map(str::to_string).collect())
+}
+
+// Accepts devices presenting an unexpired token issued for their class and
+// instance, and with allow_shared_identity whatever `shared_identity` accepts.
+// Tenants keep their own identity, this covers the default namespace.
+pub struct EnrollmentAuth {
+    state: RwLock<EnrollmentState>,
+    // revoked without a revocation_file, kept across set_config until the server restarts
+    unpersisted_revocations: Mutex<HashSet<String>>,
+    shared_identity: Arc<dyn AuthProvider>,
+}
+
+impl EnrollmentAuth {
+    pub fn new(config: EnrollmentConfig, shared_identity: Arc<dyn AuthProvider>) -> anyhow::Result<Self> {
+        Ok(Self { state: RwLock::new(config.try_into()?), unpersisted_revocations: Mutex::default(), shared_identity })
+    }
+    // takes effect for the next package, evict the newly revoked with Up2pServer::evict_revoked
+    pub fn set_config(&self, config: EnrollmentConfig) -> anyhow::Result<()> {
+        self.set_state(config.try_into()?);
+        Ok(())
+    }
+    // set_config for a config already checked, cannot fail
+    pub fn set_state(&self, state: EnrollmentState) {
+        *self.state.write().unwrap() = state;
+    }
+    fn verify_token(&self, state: &EnrollmentState, token: &str, base_info: &BasePkg) -> anyhow::Result<()> {
+        let token = EnrollmentToken::decode_from(&URL_SAFE_NO_PAD.decode(token)?)?;
+        state.verifying_key.verify(&token.claims, &Signature::from_slice(&token.signature)?)?;
+        let claims = EnrollmentClaims::decode_from(&token.claims)?;
+        if claims.client_class != base_info.client_class || claims.client_instance != base_info.client_instance {
+            return Err(anyhow::anyhow!("Enrollment token issued for {}-{}", claims.client_class, claims.client_instance));
+        }
+        if unix_secs(SystemTime::now()) >= claims.expires_at {
+            return Err(anyhow::anyhow!("Enrollment token expired"));
+        }
+        Ok(())
+    }
+}
+
+impl AuthProvider for EnrollmentAuth {
+    fn verify(&self, base_info: &BasePkg) -> anyhow::Result<()> {
+        let state = self.state.read().unwrap();
+        let global_id = crate::utils::get_global_id(&base_info.client_class, &base_info.client_instance);
+        if state.revoked_devices.contains(&global_id) || self.unpersisted_revocations.lock().unwrap().contains(&global_id) {
+            return Err(anyhow::anyhow!("Device {} is revoked", global_id));
+        }
+        match base_info.identity.strip_prefix(TOKEN_PREFIX) {
+            Some(token) => self.verify_token(&state, token, base_info),
+            None if state.allow_shared_identity => self.shared_identity.verify(base_info),
+            None => Err(anyhow::anyhow!("Enrollment token required")),
+        }
+    }
+    // written to the revocation_file first, a revocation that could not be kept is not made
+    fn revoke(&self, global_id: &str) -> anyhow::Result<()> {
+        let mut state = self.state.write().unwrap();
+        match &state.revocation_file {
+            Some(revocation_file) => {
+                let mut file = OpenOptions::new().create(true).append(true).open(revocation_file)?;
+                writeln!(file, "{}", global_id)?;
+            }
+            None => {
+                self.unpersisted_revocations.lock().unwrap().insert(global_id.to_string());
+            }
+        }
+        state.revoked_devices.insert(global_id.to_string());
+        Ok(())
+    }
+    fn revoked_devices(&self) -> Vec<String> {
+        let state = self.state.read().unwrap();
+        let unpersisted = self.unpersisted_revocations.lock().unwrap();
+        state.revoked_devices.union(&unpersisted).cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Arc, time::Duration};
+
+    use crate::{core::uprotocol_pkg::BasePkg, server::auth::{AuthProvider, SharedIdentityAuth}, test_support::{test_base_info, TEST_IDENTITY}};
+
+    use super::{EnrollmentAuth, EnrollmentConfig, EnrollmentIssuer};
+
+    #[tokio::test]
+    async fn test_enrollment_tokens() {
+        let issuer = EnrollmentIssuer::from_hex(&EnrollmentIssuer::generate().to_hex()).unwrap();
+        let config = EnrollmentConfig { verifying_key: issuer.verifying_key_hex(), ..EnrollmentConfig::default() };
+        let auth = EnrollmentAuth::new(config.clone(), Arc::new(SharedIdentityAuth::new(TEST_IDENTITY))).unwrap();
+        let with_token = |instance: &str, token: String| BasePkg { identity: token, ..test_base_info("cli", instance) };
+        let token = issuer.issue("cli", "peer1", Duration::from_secs(60)).unwrap();
+        assert!(auth.verify(&with_token("peer1", token.clone())).is_ok());
+        // bound to the device it was issued for
+        assert!(auth.verify(&with_token("peer2", token.clone())).is_err());
+        assert!(auth.verify(&with_token("peer1", issuer.issue("cli", "peer1", Duration::ZERO).unwrap())).is_err());
+        let forged = EnrollmentIssuer::generate().issue("cli", "peer1", Duration::from_secs(60)).unwrap();
+        assert!(auth.verify(&with_token("peer1", forged)).is_err());
+
+        assert!(auth.verify(&test_base_info("cli", "peer2")).is_err());
+        let config = EnrollmentConfig { allow_shared_identity: true, ..config };
+        auth.set_config(config.clone()).unwrap();
+        assert!(auth.verify(&test_base_info("cli", "peer2")).is_ok());
+        auth.revoke("cli-peer1").unwrap();
+        assert!(auth.verify(&with_token("peer1", token.clone())).is_err());
+        assert!(auth.verify(&test_base_info("cli", "peer1")).is_err());
+        // a reload keeps it
+        auth.set_config(config).unwrap();
+        assert!(auth.verify(&with_token("peer1", token)).is_err());
+        assert_eq!(auth.revoked_devices(), vec!["cli-peer1".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_revocation_file() {
+        let revocation_file = std::env::temp_dir().join(format!("up2p-test-{}-revoked.txt", std::process::id()));
+        let _ = std::fs::remove_file(&revocation_file);
+        let config = EnrollmentConfig {
+            verifying_key: EnrollmentIssuer::generate().verifying_key_hex(),
+            revocation_file: Some(revocation_file.clone()),
+            allow_shared_identity: true,
+            ..EnrollmentConfig::default()
+        };
+        let auth = EnrollmentAuth::new(config.clone(), Arc::new(SharedIdentityAuth::new(TEST_IDENTITY))).unwrap();
+        auth.revoke("cli-peer1").unwrap();
+        // a restarted server reads it back
+        let restarted = EnrollmentAuth::new(config.clone(), Arc::new(SharedIdentityAuth::new(TEST_IDENTITY))).unwrap();
+        assert!(restarted.verify(&test_base_info("cli", "peer1")).is_err());
+        assert!(restarted.verify(&test_base_info("cli", "peer2")).is_ok());
+        // taken out of the file, the next reload lets it back in
+        std::fs::write(&revocation_file, "").unwrap();
+        restarted.set_config(config).unwrap();
+        assert!(restarted.verify(&test_base_info("cli", "peer1")).is_ok());
+        std::fs::remove_file(&revocation_file).unwrap();
+    }
+}
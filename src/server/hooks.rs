@@ -0,0 +1,16 @@
+use std::net::SocketAddr;
+
+use crate::core::uprotocol_pkg::BasePkg;
+
+// Callbacks for embedding applications, called after the server handled a package.
+// Every method defaults to a no-op.
+pub trait ServerHooks: Send + Sync {
+    fn on_register(&self, _base_info: &BasePkg, _endpoint_addr: SocketAddr) {}
+    fn on_endpoint_request(&self, _requester: &BasePkg, _requested_global_id: &str, _found: Option<SocketAddr>) {}
+    fn on_relay(&self, _src: &BasePkg, _dst: &BasePkg, _bytes: usize) {}
+}
+
+#[derive(Debug, Default)]
+pub struct NoopHooks;
+
+impl ServerHooks for NoopHooks {}
@@ -0,0 +1,41 @@
+use serde::{Deserialize, Serialize};
+
+// Who may list the devices of a class. Nothing is listable unless a rule allows it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ListingConfig {
+    pub rules: Vec<ListingRule>,
+}
+
+// devices of `requester_class` may list those of `client_class`, "*" matches any class
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListingRule {
+    pub requester_class: String,
+    pub client_class: String,
+}
+
+impl ListingConfig {
+    pub fn allows(&self, requester_class: &str, client_class: &str) -> bool {
+        let matches = |pattern: &str, class: &str| pattern == "*" || pattern == class;
+        self.rules.iter().any(|rule| matches(&rule.requester_class, requester_class) && matches(&rule.client_class, client_class))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ListingConfig, ListingRule};
+
+    #[tokio::test]
+    async fn test_listing_rules() {
+        let rule = |requester_class: &str, client_class: &str| ListingRule {
+            requester_class: requester_class.to_string(),
+            client_class: client_class.to_string(),
+        };
+        assert!(!ListingConfig::default().allows("controller", "sensor"));
+        let listing = ListingConfig { rules: vec![rule("controller", "sensor"), rule("admin", "*")] };
+        assert!(listing.allows("controller", "sensor"));
+        assert!(!listing.allows("controller", "camera"));
+        assert!(!listing.allows("sensor", "sensor"));
+        assert!(listing.allows("admin", "camera"));
+    }
+}
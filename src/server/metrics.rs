@@ -0,0 +1,209 @@
+use std::{fmt::Write as _, net::SocketAddr, sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex}};
+
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::{TcpListener, TcpStream}, sync::mpsc::WeakSender};
+use tracing::{debug, info, warn};
+
+use super::udp_event_handle::Up2pEvent;
+
+// counters and gauges exported on /metrics in prometheus text format
+#[derive(Debug)]
+pub struct ServerMetrics {
+    packets_received: [AtomicU64; 256],
+    pub decode_failures: AtomicU64,
+    pub auth_failures: AtomicU64,
+    pub relayed_bytes: AtomicU64,
+    // entries stay after going offline, only eviction lowers it
+    pub registered_devices: AtomicU64,
+    dropped_packets: [AtomicU64; 5],
+    // the udp event channel and the worker shards, their backlog is read from
+    // the channels themselves at scrape time
+    event_channels: Mutex<Vec<WeakSender<Up2pEvent>>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum DropReason {
+    Banned = 0,
+    RateLimited = 1,
+    Malformed = 2,
+    Denied = 3,
+    // the worker of the sender's shard is behind
+    Overloaded = 4,
+}
+
+impl DropReason {
+    const ALL: [DropReason; 5] = [DropReason::Banned, DropReason::RateLimited, DropReason::Malformed, DropReason::Denied, DropReason::Overloaded];
+    fn label(&self) -> &'static str {
+        match self {
+            DropReason::Banned => "banned",
+            DropReason::RateLimited => "rate_limited",
+            DropReason::Malformed => "malformed",
+            DropReason::Denied => "denied",
+            DropReason::Overloaded => "overloaded",
+        }
+    }
+}
+
+impl Default for ServerMetrics {
+    fn default() -> Self {
+        Self {
+            packets_received: std::array::from_fn(|_| AtomicU64::new(0)),
+            decode_failures: AtomicU64::new(0),
+            auth_failures: AtomicU64::new(0),
+            relayed_bytes: AtomicU64::new(0),
+            registered_devices: AtomicU64::new(0),
+            dropped_packets: std::array::from_fn(|_| AtomicU64::new(0)),
+            event_channels: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl ServerMetrics {
+    pub fn inc_packets_received(&self, package_type: u8) {
+        self.packets_received[package_type as usize].fetch_add(1, Ordering::Relaxed);
+    }
+    pub fn inc_decode_failures(&self) {
+        self.decode_failures.fetch_add(1, Ordering::Relaxed);
+    }
+    pub fn inc_auth_failures(&self) {
+        self.auth_failures.fetch_add(1, Ordering::Relaxed);
+    }
+    pub fn inc_dropped_packets(&self, reason: DropReason) {
+        self.dropped_packets[reason as usize].fetch_add(1, Ordering::Relaxed);
+    }
+    pub fn add_relayed_bytes(&self, bytes: usize) {
+        self.relayed_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+    pub fn set_registered_devices(&self, count: usize) {
+        self.registered_devices.store(count as u64, Ordering::Relaxed);
+    }
+    pub fn add_event_channel(&self, tx: WeakSender<Up2pEvent>) {
+        let mut event_channels = self.event_channels.lock().unwrap();
+        // the channels of a server that stopped running
+        event_channels.retain(|tx| tx.strong_count() > 0);
+        event_channels.push(tx);
+    }
+    fn channel_backlog(&self) -> u64 {
+        self.event_channels.lock().unwrap().iter()
+            .filter_map(|tx| tx.upgrade())
+            .map(|tx| (tx.max_capacity() - tx.capacity()) as u64)
+            .sum()
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "# HELP up2p_packets_received_total Packets received per base protocol type.");
+        let _ = writeln!(out, "# TYPE up2p_packets_received_total counter");
+        for (package_type, counter) in self.packets_received.iter().enumerate() {
+            let count = counter.load(Ordering::Relaxed);
+            if count > 0 {
+                let _ = writeln!(out, "up2p_packets_received_total{{type=\"{}\"}} {}", package_type_name(package_type as u8), count);
+            }
+        }
+        write_metric(&mut out, "up2p_decode_failures_total", "counter", "Packages that failed to decode.", self.decode_failures.load(Ordering::Relaxed));
+        write_metric(&mut out, "up2p_auth_failures_total", "counter", "Packages rejected by identity verification.", self.auth_failures.load(Ordering::Relaxed));
+        let _ = writeln!(out, "# HELP up2p_dropped_packets_total Packets dropped before routing.");
+        let _ = writeln!(out, "# TYPE up2p_dropped_packets_total counter");
+        for reason in DropReason::ALL {
+            let _ = writeln!(out, "up2p_dropped_packets_total{{reason=\"{}\"}} {}", reason.label(), self.dropped_packets[reason as usize].load(Ordering::Relaxed));
+        }
+        write_metric(&mut out, "up2p_relayed_bytes_total", "counter", "Bytes forwarded between peers by the server.", self.relayed_bytes.load(Ordering::Relaxed));
+        write_metric(&mut out, "up2p_registered_devices", "gauge", "Devices in the device list, offline ones included until evicted.", self.registered_devices.load(Ordering::Relaxed));
+        write_metric(&mut out, "up2p_event_channel_backlog", "gauge", "Datagrams waiting in the udp event channel and the worker shards.", self.channel_backlog());
+        out
+    }
+}
+
+fn write_metric(out: &mut String, name: &str, metric_type: &str, help: &str, value: u64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, metric_type);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+fn package_type_name(package_type: u8) -> String {
+    use crate::core::BaseUp2pProtocol;
+    match package_type {
+        BaseUp2pProtocol::TYPE_HELLO => "hello".to_string(),
+        BaseUp2pProtocol::TYPE_HELLO_ACK => "hello_ack".to_string(),
+        BaseUp2pProtocol::TYPE_DATA => "data".to_string(),
+        BaseUp2pProtocol::TYPE_DATA_ACK => "data_ack".to_string(),
+        BaseUp2pProtocol::TYPE_REQUEST => "request".to_string(),
+        BaseUp2pProtocol::TYPE_REQUEST_ACK => "request_ack".to_string(),
+        BaseUp2pProtocol::TYPE_PKG_EXCHANGE => "pkg_exchange".to_string(),
+        other => format!("0x{:02x}", other),
+    }
+}
+
+// minimal http endpoint, only answers GET /metrics
+pub async fn serve_metrics(address: SocketAddr, metrics: Arc<ServerMetrics>) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(address).await?;
+    info!("Metrics endpoint listening on http://{}/metrics", address);
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("Failed to accept metrics connection: {}", e);
+                continue;
+            }
+        };
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_metrics_conn(stream, &metrics).await {
+                debug!("Metrics connection from {} failed: {}", peer, e);
+            }
+        });
+    }
+}
+
+async fn handle_metrics_conn(mut stream: TcpStream, metrics: &ServerMetrics) -> anyhow::Result<()> {
+    let mut buf = [0u8; 1024];
+    let len = stream.read(&mut buf).await?;
+    let request = String::from_utf8_lossy(&buf[..len]);
+    let request_line = request.lines().next().unwrap_or_default();
+    let mut parts = request_line.split_whitespace();
+    let response = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            let body = metrics.render();
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(), body
+            )
+        }
+        _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+    };
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{core::BaseUp2pProtocol, server::udp_event_handle::Up2pEvent};
+
+    use super::ServerMetrics;
+
+    #[tokio::test]
+    async fn test_render_metrics() {
+        let metrics = ServerMetrics::default();
+        metrics.inc_packets_received(BaseUp2pProtocol::TYPE_HELLO);
+        metrics.inc_packets_received(BaseUp2pProtocol::TYPE_HELLO);
+        metrics.inc_packets_received(0xff);
+        metrics.add_relayed_bytes(42);
+        let rendered = metrics.render();
+        assert!(rendered.contains("up2p_packets_received_total{type=\"hello\"} 2"));
+        assert!(rendered.contains("up2p_packets_received_total{type=\"0xff\"} 1"));
+        assert!(!rendered.contains("type=\"request\""));
+        assert!(rendered.contains("up2p_relayed_bytes_total 42"));
+        assert!(rendered.contains("up2p_event_channel_backlog 0"));
+
+        // the backlog of every channel, the shards' included
+        let (udp_tx, _udp_rx) = tokio::sync::mpsc::channel(4);
+        let (shard_tx, _shard_rx) = tokio::sync::mpsc::channel(4);
+        metrics.add_event_channel(udp_tx.downgrade());
+        metrics.add_event_channel(shard_tx.downgrade());
+        let event = || Up2pEvent::new(vec![0], "127.0.0.1:9000".parse().unwrap());
+        udp_tx.try_send(event()).unwrap();
+        shard_tx.try_send(event()).unwrap();
+        shard_tx.try_send(event()).unwrap();
+        assert!(metrics.render().contains("up2p_event_channel_backlog 3"));
+    }
+}
@@ -0,0 +1,458 @@
+// Embeddable rendezvous server. Each Up2pServer owns its sockets, registry and
+// limits, so several of them can run in one process.
+//
+// let server = Up2pServer::builder()
+//     .bind_address("0.0.0.0:9008".parse()?)
+//     .auth(Arc::new(SharedIdentityAuth::new("identity")))
+//     .build().await?;
+// server.run().await?;
+pub mod acl;
+pub mod auth;
+pub mod enrollment;
+pub mod hooks;
+pub mod listing;
+pub mod metrics;
+mod presence;
+pub mod rate_limit;
+pub mod registry;
+pub mod tenant;
+mod router;
+mod session;
+mod udp_event_handle;
+
+use std::{collections::hash_map::DefaultHasher, hash::{Hash, Hasher}, net::{IpAddr, SocketAddr}, sync::{Arc, Mutex, RwLock}, time::Duration};
+
+use tokio::{net::UdpSocket, sync::mpsc, task::JoinSet};
+
+use crate::{core::{codec::Codec, wire, protocol_version::{ProtocolVersion, CAP_CODEC_CBOR, CAP_SIGNED}, server_key::ServerKey}, transport::{stream::StreamListenerTransport, DatagramTransport}};
+use tracing::{info, warn};
+
+use acl::AclConfig;
+use auth::AuthProvider;
+use hooks::{NoopHooks, ServerHooks};
+use listing::ListingConfig;
+use metrics::{DropReason, ServerMetrics};
+use presence::PresenceHub;
+use rate_limit::{RateLimitConfig, RateLimiter};
+use registry::{DeviceRegistry, MemoryRegistry};
+use session::Sessions;
+use tenant::{TenantConfig, Tenants};
+use udp_event_handle::Up2pEvent;
+
+pub const DEFAULT_BIND_ADDRESS: &str = "0.0.0.0:9008";
+pub const DEFAULT_ONLINE_TIMEOUT: Duration = Duration::from_secs(120);
+// datagrams the receive tasks queue for dispatch, and events each worker's shard queues
+const EVENT_CHANNEL_LEN: usize = 32;
+const SHARD_QUEUE_LEN: usize = 32;
+
+// state shared by the receive tasks and the routing workers of one server
+pub(crate) struct ServerContext {
+    transports: Vec<Arc<dyn DatagramTransport>>,
+    auth: Arc<dyn AuthProvider>,
+    registry: Arc<dyn DeviceRegistry>,
+    hooks: Arc<dyn ServerHooks>,
+    metrics: Arc<ServerMetrics>,
+    server_key: Option<ServerKey>,
+    codec: Codec,
+    online_timeout: Duration,
+    presence: PresenceHub,
+    sessions: Sessions,
+    rate_limit: RwLock<Arc<RateLimitConfig>>,
+    listing: RwLock<Arc<ListingConfig>>,
+    acl: RwLock<Arc<AclConfig>>,
+    tenants: RwLock<Arc<Tenants>>,
+    ip_limiter: Mutex<RateLimiter<IpAddr>>,
+    device_limiter: Mutex<RateLimiter<String>>,
+}
+
+impl ServerContext {
+    fn rate_limit(&self) -> Arc<RateLimitConfig> {
+        self.rate_limit.read().unwrap().clone()
+    }
+    fn listing(&self) -> Arc<ListingConfig> {
+        self.listing.read().unwrap().clone()
+    }
+    fn acl(&self) -> Arc<AclConfig> {
+        self.acl.read().unwrap().clone()
+    }
+    fn tenants(&self) -> Arc<Tenants> {
+        self.tenants.read().unwrap().clone()
+    }
+    // a peer connected over tcp or websocket is answered on its connection,
+    // everyone else through the first datagram transport
+    fn transport_for(&self, target: SocketAddr) -> &Arc<dyn DatagramTransport> {
+        self.transports.iter()
+            .find(|transport| transport.is_connected(target))
+            .unwrap_or(&self.transports[0])
+    }
+    // what this server announces in HELLO_ACK
+    fn protocol(&self) -> ProtocolVersion {
+        let mut protocol = ProtocolVersion::CURRENT;
+        if self.server_key.is_none() {
+            protocol.capabilities &= !CAP_SIGNED;
+        }
+        if self.codec != Codec::Cbor {
+            protocol.capabilities &= !CAP_CODEC_CBOR;
+        }
+        protocol
+    }
+    // bincode always, for hellos and clients that did not negotiate, legacy frames
+    // for the deprecation window and the deployment's codec
+    fn accepts(&self, codec: Codec) -> bool {
+        codec == Codec::Bincode || codec == Codec::Legacy || codec == self.codec
+    }
+    // every package leaves signed once the server has a key, but legacy clients
+    // have no SIGNED and get their packages as they were
+    async fn send_to(&self, package: &[u8], target: SocketAddr) -> anyhow::Result<usize> {
+        let sent = match &self.server_key {
+            Some(server_key) if wire::peek_codec(package) != Some(Codec::Legacy) => self.transport_for(target).send_to(&server_key.sign(package)?, target).await?,
+            _ => self.transport_for(target).send_to(package, target).await?,
+        };
+        Ok(sent)
+    }
+}
+
+pub struct Up2pServerBuilder {
+    bind_address: SocketAddr,
+    transports: Vec<Arc<dyn DatagramTransport>>,
+    tcp_address: Option<SocketAddr>,
+    websocket_address: Option<SocketAddr>,
+    auth: Option<Arc<dyn AuthProvider>>,
+    registry: Arc<dyn DeviceRegistry>,
+    hooks: Arc<dyn ServerHooks>,
+    rate_limit: RateLimitConfig,
+    listing: ListingConfig,
+    acl: AclConfig,
+    tenants: Vec<TenantConfig>,
+    server_key: Option<ServerKey>,
+    codec: Codec,
+    online_timeout: Duration,
+    workers: usize,
+    reuseport_sockets: usize,
+}
+
+impl Default for Up2pServerBuilder {
+    fn default() -> Self {
+        Self {
+            bind_address: DEFAULT_BIND_ADDRESS.parse().unwrap(),
+            transports: Vec::new(),
+            tcp_address: None,
+            websocket_address: None,
+            auth: None,
+            registry: Arc::new(MemoryRegistry::new()),
+            hooks: Arc::new(NoopHooks),
+            rate_limit: RateLimitConfig::default(),
+            listing: ListingConfig::default(),
+            acl: AclConfig::default(),
+            tenants: Vec::new(),
+            server_key: None,
+            codec: Codec::Bincode,
+            online_timeout: DEFAULT_ONLINE_TIMEOUT,
+            workers: std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            reuseport_sockets: 1,
+        }
+    }
+}
+
+impl Up2pServerBuilder {
+    // use port 0 to let the os pick one, see Up2pServer::local_addr
+    pub fn bind_address(mut self, bind_address: SocketAddr) -> Self {
+        self.bind_address = bind_address;
+        self
+    }
+    // serve on this transport instead of binding udp sockets, may be called more than once
+    pub fn transport(mut self, transport: Arc<dyn DatagramTransport>) -> Self {
+        self.transports.push(transport);
+        self
+    }
+    // also accept length prefixed frames over tcp, for clients whose udp is blocked
+    pub fn tcp_address(mut self, tcp_address: SocketAddr) -> Self {
+        self.tcp_address = Some(tcp_address);
+        self
+    }
+    // also accept websocket connections, one package per binary message
+    pub fn websocket_address(mut self, websocket_address: SocketAddr) -> Self {
+        self.websocket_address = Some(websocket_address);
+        self
+    }
+    pub fn auth(mut self, auth: Arc<dyn AuthProvider>) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+    pub fn registry(mut self, registry: Arc<dyn DeviceRegistry>) -> Self {
+        self.registry = registry;
+        self
+    }
+    pub fn hooks(mut self, hooks: Arc<dyn ServerHooks>) -> Self {
+        self.hooks = hooks;
+        self
+    }
+    pub fn rate_limit(mut self, rate_limit: RateLimitConfig) -> Self {
+        self.rate_limit = rate_limit;
+        self
+    }
+    // which classes may list which, no device can list others by default
+    pub fn listing(mut self, listing: ListingConfig) -> Self {
+        self.listing = listing;
+        self
+    }
+    // who may reach whom, everyone may reach everyone by default
+    pub fn acl(mut self, acl: AclConfig) -> Self {
+        self.acl = acl;
+        self
+    }
+    // Devices with a namespace authenticate against its tenant instead of the
+    // auth provider, which keeps the default namespace.
+    pub fn tenants(mut self, tenants: Vec<TenantConfig>) -> Self {
+        self.tenants = tenants;
+        self
+    }
+    // sign every package sent, for clients that pin the key's verifying key
+    pub fn server_key(mut self, server_key: ServerKey) -> Self {
+        self.server_key = Some(server_key);
+        self
+    }
+    // Also take packages in `codec` and send in it to devices that negotiate it,
+    // bincode is always spoken
+    pub fn codec(mut self, codec: Codec) -> Self {
+        self.codec = codec;
+        self
+    }
+    // a device that sent nothing for this long is reported offline, presence
+    // subscribers hear about it within a quarter of that
+    pub fn online_timeout(mut self, online_timeout: Duration) -> Self {
+        self.online_timeout = online_timeout;
+        self
+    }
+    // number of routing workers, defaults to the number of cpus
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+    // number of SO_REUSEPORT sockets bound to the bind address
+    pub fn reuseport_sockets(mut self, reuseport_sockets: usize) -> Self {
+        self.reuseport_sockets = reuseport_sockets.max(1);
+        self
+    }
+
+    // binds the sockets, the server starts handling packets once run is awaited
+    pub async fn build(self) -> anyhow::Result<Up2pServer> {
+        let auth = self.auth.ok_or_else(|| anyhow::anyhow!("Up2pServer requires an auth provider"))?;
+        let mut transports = if self.transports.is_empty() {
+            bind_udp_sockets(self.bind_address, self.reuseport_sockets)?.into_iter()
+                .map(|udp_socket| udp_socket as Arc<dyn DatagramTransport>)
+                .collect()
+        } else {
+            self.transports
+        };
+        let mut tcp_local_addr = None;
+        if let Some(tcp_address) = self.tcp_address {
+            let listener = StreamListenerTransport::bind_tcp(tcp_address).await?;
+            tcp_local_addr = Some(listener.local_addr()?);
+            transports.push(listener);
+        }
+        let mut websocket_local_addr = None;
+        if let Some(websocket_address) = self.websocket_address {
+            let listener = StreamListenerTransport::bind_websocket(websocket_address).await?;
+            websocket_local_addr = Some(listener.local_addr()?);
+            transports.push(listener);
+        }
+        let context = ServerContext {
+            transports,
+            auth,
+            registry: self.registry,
+            hooks: self.hooks,
+            metrics: Arc::new(ServerMetrics::default()),
+            server_key: self.server_key,
+            codec: self.codec,
+            online_timeout: self.online_timeout,
+            presence: PresenceHub::default(),
+            sessions: Sessions::default(),
+            ip_limiter: Mutex::new(RateLimiter::new(self.rate_limit.per_ip_rate, self.rate_limit.per_ip_burst)),
+            device_limiter: Mutex::new(RateLimiter::new(self.rate_limit.per_device_rate, self.rate_limit.per_device_burst)),
+            rate_limit: RwLock::new(Arc::new(self.rate_limit)),
+            listing: RwLock::new(Arc::new(self.listing)),
+            acl: RwLock::new(Arc::new(self.acl)),
+            tenants: RwLock::new(Arc::new(Tenants::new(self.tenants))),
+        };
+        Ok(Up2pServer {
+            context: Arc::new(context),
+            tcp_local_addr,
+            websocket_local_addr,
+            workers: self.workers,
+        })
+    }
+}
+
+pub struct Up2pServer {
+    context: Arc<ServerContext>,
+    tcp_local_addr: Option<SocketAddr>,
+    websocket_local_addr: Option<SocketAddr>,
+    workers: usize,
+}
+
+impl Up2pServer {
+    pub fn builder() -> Up2pServerBuilder {
+        Up2pServerBuilder::default()
+    }
+    pub fn local_addr(&self) -> anyhow::Result<SocketAddr> {
+        Ok(self.context.transports[0].local_addr()?)
+    }
+    pub fn tcp_local_addr(&self) -> Option<SocketAddr> {
+        self.tcp_local_addr
+    }
+    pub fn websocket_local_addr(&self) -> Option<SocketAddr> {
+        self.websocket_local_addr
+    }
+    pub fn metrics(&self) -> Arc<ServerMetrics> {
+        self.context.metrics.clone()
+    }
+    pub fn registry(&self) -> Arc<dyn DeviceRegistry> {
+        self.context.registry.clone()
+    }
+    // applies to the next package, existing buckets keep their tokens
+    pub fn set_rate_limit(&self, rate_limit: RateLimitConfig) {
+        self.context.ip_limiter.lock().unwrap().set_limits(rate_limit.per_ip_rate, rate_limit.per_ip_burst);
+        self.context.device_limiter.lock().unwrap().set_limits(rate_limit.per_device_rate, rate_limit.per_device_burst);
+        *self.context.rate_limit.write().unwrap() = Arc::new(rate_limit);
+    }
+    // takes effect for the next listing request
+    pub fn set_listing(&self, listing: ListingConfig) {
+        *self.context.listing.write().unwrap() = Arc::new(listing);
+    }
+    // takes effect for the next package, relays already sent are not recalled
+    pub fn set_acl(&self, acl: AclConfig) {
+        *self.context.acl.write().unwrap() = Arc::new(acl);
+    }
+    // takes effect for the next package, tenant rate limits start over
+    pub fn set_tenants(&self, tenants: Vec<TenantConfig>) {
+        *self.context.tenants.write().unwrap() = Arc::new(Tenants::new(tenants));
+    }
+    // Revoke a device of the default namespace with the auth provider and evict it,
+    // so lookups no longer find it and its next package is turned away.
+    pub async fn revoke(&self, global_id: &str) -> anyhow::Result<()> {
+        self.context.auth.revoke(global_id)?;
+        router::evict(&self.context, global_id).await;
+        Ok(())
+    }
+    // evict every device the auth provider revoked, e.g. after its config was reloaded
+    pub async fn evict_revoked(&self) {
+        for global_id in self.context.auth.revoked_devices() {
+            router::evict(&self.context, &global_id).await;
+        }
+    }
+    // Evict every device the credential of its hello no longer verifies for, e.g.
+    // after the identity, tenants or enrollment were reloaded. It has to say hello again.
+    pub async fn evict_unverified(&self) {
+        for global_id in router::unverified(&self.context) {
+            router::evict(&self.context, &global_id).await;
+        }
+    }
+
+    // Runs until the future is dropped, which also stops every task of this server.
+    // Events are sharded by source address over a fixed set of workers, so packets
+    // from one client keep their order. A full shard drops its events instead of
+    // holding up the others.
+    pub async fn run(&self) -> anyhow::Result<()> {
+        info!("Listening on {} with {} transport(s) and {} worker(s)", self.local_addr()?, self.context.transports.len(), self.workers);
+        let mut tasks = JoinSet::new();
+        let mut rx = udp_event_handle::udp_event_handle(self.context.clone(), &mut tasks);
+        let shards = (0..self.workers).map(|_| {
+            let (tx, mut rx) = mpsc::channel::<Up2pEvent>(SHARD_QUEUE_LEN);
+            self.context.metrics.add_event_channel(tx.downgrade());
+            let context = self.context.clone();
+            tasks.spawn(async move {
+                while let Some(event) = rx.recv().await {
+                    router::route(&context, event).await;
+                }
+            });
+            tx
+        }).collect::<Vec<_>>();
+        tasks.spawn(router::expire_presence(self.context.clone()));
+        loop {
+            if let Some(event) = rx.recv().await {
+                let mut hasher = DefaultHasher::new();
+                event.get_addr().hash(&mut hasher);
+                let shard = &shards[hasher.finish() as usize % shards.len()];
+                match shard.try_send(event) {
+                    Ok(()) => {}
+                    Err(mpsc::error::TrySendError::Full(_)) => self.context.metrics.inc_dropped_packets(DropReason::Overloaded),
+                    Err(e) => warn!("Failed to dispatch event to worker, {}", e),
+                }
+            } else {
+                warn!("Recieved None from the channel");
+                return Err(anyhow::anyhow!("udp event channel closed"));
+            }
+        }
+    }
+}
+
+// bind `count` sockets to the same address, more than one requires SO_REUSEPORT
+// so the kernel can spread clients across the receive tasks
+fn bind_udp_sockets(address: SocketAddr, count: usize) -> anyhow::Result<Vec<Arc<UdpSocket>>> {
+    let mut udp_sockets: Vec<Arc<UdpSocket>> = Vec::with_capacity(count);
+    for _ in 0..count {
+        let socket = socket2::Socket::new(
+            socket2::Domain::for_address(address), socket2::Type::DGRAM, Some(socket2::Protocol::UDP)
+        )?;
+        if count > 1 {
+            #[cfg(unix)]
+            socket.set_reuse_port(true)?;
+            #[cfg(not(unix))]
+            anyhow::bail!("reuseport_sockets > 1 is only supported on unix");
+        }
+        // an unspecified ipv6 address serves ipv4 clients as well
+        if address.is_ipv6() && address.ip().is_unspecified() {
+            socket.set_only_v6(false)?;
+        }
+        socket.set_nonblocking(true)?;
+        // with port 0 the later sockets have to join the port the first one got
+        let address = match udp_sockets.first() {
+            Some(first) => first.local_addr()?,
+            None => address,
+        };
+        socket.bind(&address.into())?;
+        udp_sockets.push(Arc::new(UdpSocket::from_std(socket.into())?));
+    }
+    Ok(udp_sockets)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Arc, time::Duration};
+
+    use tokio::net::UdpSocket;
+
+    use crate::core::{bincodec::BinCodec, uprotocol_pkg::ClientHelloPkg, BaseUp2pProtocol, Up2pMessage};
+    use super::{auth::SharedIdentityAuth, Up2pServer};
+
+    #[tokio::test]
+    async fn test_two_servers_in_one_process() {
+        let mut servers = Vec::new();
+        for identity in ["aaa", "bbb"] {
+            let server = Arc::new(Up2pServer::builder()
+                .bind_address("127.0.0.1:0".parse().unwrap())
+                .auth(Arc::new(SharedIdentityAuth::new(identity)))
+                .workers(2)
+                .build().await.unwrap());
+            let running = server.clone();
+            tokio::spawn(async move { running.run().await });
+            servers.push(server);
+        }
+        let udp_socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let hello = Up2pMessage::Hello(
+            ClientHelloPkg::new("cli", "peer1", "aaa", ClientHelloPkg::MSG_HELLO)
+        ).encode_to_vec().unwrap();
+        for server in &servers {
+            udp_socket.send_to(&hello, server.local_addr().unwrap()).await.unwrap();
+        }
+        // only the server sharing the identity answers
+        let mut buf = [0u8; 1500];
+        let (len, from) = tokio::time::timeout(Duration::from_secs(1), udp_socket.recv_from(&mut buf)).await.unwrap().unwrap();
+        assert_eq!(from, servers[0].local_addr().unwrap());
+        assert_eq!(BaseUp2pProtocol::decode_from(&buf[..len]).unwrap().get_pkg_type(), BaseUp2pProtocol::TYPE_HELLO_ACK);
+        assert_eq!(servers[0].registry().lookup("cli-peer1"), Some(udp_socket.local_addr().unwrap()));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(servers[1].registry().is_empty());
+    }
+}
@@ -0,0 +1,108 @@
+use std::{collections::{HashMap, HashSet}, net::SocketAddr, sync::Mutex};
+
+use crate::core::{codec::Codec, get_global_id::GetGlobalId, uprotocol_pkg::{BasePkg, PresencePkg}};
+
+// what one device wants to hear about, pushed to the endpoint it subscribed from
+// in the codec it subscribed in
+#[derive(Debug)]
+struct Subscription {
+    // the subscriber without its identity, for the acl checks at push time
+    subscriber: BasePkg,
+    endpoint: SocketAddr,
+    codec: Codec,
+    global_ids: HashSet<String>,
+    client_classes: HashSet<String>,
+}
+
+// Presence subscriptions and the devices last reported online, so every
+// subscriber sees one online and one offline event per session of a device.
+// Global ids and classes are namespace qualified, see utils::qualify_global_id.
+#[derive(Debug, Default)]
+pub(crate) struct PresenceHub {
+    // keyed by the subscriber's global id, a new subscription replaces the old one
+    subscriptions: Mutex<HashMap<String, Subscription>>,
+    online: Mutex<HashSet<String>>,
+}
+
+impl PresenceHub {
+    pub(crate) fn subscribe(&self, subscriber: &BasePkg, endpoint: SocketAddr, codec: Codec, global_ids: Vec<String>, client_classes: Vec<String>) {
+        let subscription = Subscription {
+            subscriber: subscriber.without_identity(),
+            endpoint,
+            codec,
+            global_ids: global_ids.into_iter().collect(),
+            client_classes: client_classes.into_iter().collect(),
+        };
+        self.subscriptions.lock().unwrap().insert(subscriber.get_global_id(), subscription);
+    }
+    // the presence event a registration amounts to, if any
+    pub(crate) fn registered(&self, global_id: &str, endpoints_changed: bool) -> Option<u8> {
+        if self.online.lock().unwrap().insert(global_id.to_string()) {
+            Some(PresencePkg::EVENT_ONLINE)
+        } else if endpoints_changed {
+            Some(PresencePkg::EVENT_ENDPOINT_CHANGED)
+        } else {
+            None
+        }
+    }
+    // Forget an evicted device and its own subscription, true if it was reported online
+    pub(crate) fn forget(&self, global_id: &str) -> bool {
+        self.subscriptions.lock().unwrap().remove(global_id);
+        self.online.lock().unwrap().remove(global_id)
+    }
+    // Forget the devices `is_online` no longer holds for, along with their own
+    // subscriptions, and return their global ids.
+    pub(crate) fn expire(&self, is_online: impl Fn(&str) -> bool) -> Vec<String> {
+        let mut expired = Vec::new();
+        self.online.lock().unwrap().retain(|global_id| {
+            let online = is_online(global_id);
+            if !online {
+                expired.push(global_id.clone());
+            }
+            online
+        });
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        for global_id in &expired {
+            subscriptions.remove(global_id);
+        }
+        expired
+    }
+    pub(crate) fn subscribers(&self, global_id: &str, client_class: &str) -> Vec<(BasePkg, SocketAddr, Codec)> {
+        self.subscriptions.lock().unwrap().iter()
+            .filter(|(subscriber, subscription)| {
+                subscriber.as_str() != global_id
+                    && (subscription.global_ids.contains(global_id) || subscription.client_classes.contains(client_class))
+            })
+            .map(|(_, subscription)| (subscription.subscriber.clone(), subscription.endpoint, subscription.codec))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{core::{codec::Codec, uprotocol_pkg::PresencePkg}, test_support::test_base_info};
+
+    use super::PresenceHub;
+
+    #[tokio::test]
+    async fn test_presence_hub() {
+        let presence = PresenceHub::default();
+        let endpoint = "127.0.0.1:9000".parse().unwrap();
+        let controller = test_base_info("controller", "main");
+        presence.subscribe(&controller, endpoint, Codec::Cbor, vec!["cli-peer1".to_string()], vec!["sensor".to_string()]);
+        assert_eq!(presence.subscribers("cli-peer1", "cli"), vec![(controller.clone(), endpoint, Codec::Cbor)]);
+        assert_eq!(presence.subscribers("sensor-kitchen", "sensor"), vec![(controller.clone(), endpoint, Codec::Cbor)]);
+        assert!(presence.subscribers("cli-peer2", "cli").is_empty());
+
+        assert_eq!(presence.registered("controller-main", false), Some(PresencePkg::EVENT_ONLINE));
+        assert_eq!(presence.registered("cli-peer1", false), Some(PresencePkg::EVENT_ONLINE));
+        assert_eq!(presence.registered("cli-peer1", false), None);
+        assert_eq!(presence.registered("cli-peer1", true), Some(PresencePkg::EVENT_ENDPOINT_CHANGED));
+
+        // an expired subscriber stops getting events
+        let expired = presence.expire(|global_id| global_id != "controller-main");
+        assert_eq!(expired, vec!["controller-main".to_string()]);
+        assert!(presence.subscribers("cli-peer1", "cli").is_empty());
+        assert_eq!(presence.registered("controller-main", false), Some(PresencePkg::EVENT_ONLINE));
+    }
+}
@@ -0,0 +1,151 @@
+use std::{collections::HashMap, hash::Hash, net::IpAddr, time::Instant};
+
+use serde::{Deserialize, Serialize};
+use crate::core::{wire, BaseUp2pProtocol};
+
+// buckets are pruned once the map grows past this many keys
+const PRUNE_THRESHOLD: usize = 4096;
+// largest datagram the server accepts before decoding
+const MAX_DATAGRAM_SIZE: usize = 1500;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    // packets per second allowed from one source ip, 0 disables the limit
+    pub per_ip_rate: f64,
+    pub per_ip_burst: f64,
+    // packets per second allowed for one global id, 0 disables the limit
+    pub per_device_rate: f64,
+    pub per_device_burst: f64,
+    pub banned_ips: Vec<IpAddr>,
+    // global ids, e.g. "cli-peer1"
+    pub banned_devices: Vec<String>,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            per_ip_rate: 100.0,
+            per_ip_burst: 200.0,
+            per_device_rate: 50.0,
+            per_device_burst: 100.0,
+            banned_ips: Vec::new(),
+            banned_devices: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(burst: f64, now: Instant) -> Self {
+        Self { tokens: burst, last_refill: now }
+    }
+    fn refill(&mut self, rate: f64, burst: f64, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(burst);
+        self.last_refill = now;
+    }
+}
+
+#[derive(Debug)]
+pub struct RateLimiter<K> {
+    rate: f64,
+    burst: f64,
+    buckets: HashMap<K, TokenBucket>,
+}
+
+impl<K: Hash + Eq> RateLimiter<K> {
+    pub fn new(rate: f64, burst: f64) -> Self {
+        Self { rate, burst: burst.max(1.0), buckets: HashMap::new() }
+    }
+
+    // applied on config reload, existing buckets keep their tokens
+    pub fn set_limits(&mut self, rate: f64, burst: f64) {
+        self.rate = rate;
+        self.burst = burst.max(1.0);
+    }
+
+    // take one token for key, false if the key is over its limit
+    pub fn check(&mut self, key: K, now: Instant) -> bool {
+        if self.rate <= 0.0 {
+            return true;
+        }
+        if self.buckets.len() >= PRUNE_THRESHOLD {
+            self.prune(now);
+        }
+        let (rate, burst) = (self.rate, self.burst);
+        let bucket = self.buckets.entry(key).or_insert_with(|| TokenBucket::new(burst, now));
+        bucket.refill(rate, burst, now);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    // a bucket that has refilled completely carries no state worth keeping
+    fn prune(&mut self, now: Instant) {
+        let (rate, burst) = (self.rate, self.burst);
+        self.buckets.retain(|_, bucket| {
+            bucket.refill(rate, burst, now);
+            bucket.tokens < burst
+        });
+    }
+}
+
+// Cheap sanity check on the raw datagram before paying for a full decode.
+// The frame header carries the package type, and the server only ever receives
+// the client to server types.
+pub fn pre_auth_check(data: &[u8]) -> bool {
+    if data.len() > MAX_DATAGRAM_SIZE {
+        return false;
+    }
+    matches!(
+        wire::peek_type(data),
+        Some(BaseUp2pProtocol::TYPE_HELLO | BaseUp2pProtocol::TYPE_REQUEST | BaseUp2pProtocol::TYPE_PKG_EXCHANGE)
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use crate::core::{bincodec::BinCodec, protocol_version::ProtocolVersion, uprotocol_pkg::{ClientHelloPkg, HelloAckPkg}, Up2pMessage};
+
+    use super::{pre_auth_check, RateLimiter};
+
+    #[tokio::test]
+    async fn test_token_bucket() {
+        let now = Instant::now();
+        let mut limiter = RateLimiter::new(10.0, 2.0);
+        assert!(limiter.check("a", now));
+        assert!(limiter.check("a", now));
+        assert!(!limiter.check("a", now));
+        // other keys have their own bucket
+        assert!(limiter.check("b", now));
+        // 10 packets per second refills one token every 100ms
+        assert!(limiter.check("a", now + Duration::from_millis(100)));
+        assert!(!limiter.check("a", now + Duration::from_millis(100)));
+        // a disabled limiter lets everything through
+        let mut disabled = RateLimiter::new(0.0, 0.0);
+        assert!((0..1000).all(|_| disabled.check("a", now)));
+    }
+
+    #[tokio::test]
+    async fn test_pre_auth_check() {
+        let hello = Up2pMessage::Hello(
+            ClientHelloPkg::new("client_class", "client_instance", "identity", ClientHelloPkg::MSG_HELLO)
+        ).encode_to_vec().unwrap();
+        assert!(pre_auth_check(&hello));
+        let hello_ack = Up2pMessage::HelloAck(HelloAckPkg::new(ProtocolVersion::CURRENT)).encode_to_vec().unwrap();
+        assert!(!pre_auth_check(&hello_ack));
+        assert!(!pre_auth_check(&[0x00]));
+        assert!(!pre_auth_check(&vec![0u8; 2000]));
+    }
+}
@@ -0,0 +1,118 @@
+use std::{collections::HashMap, net::SocketAddr, sync::RwLock, time::SystemTime};
+
+use crate::core::{get_global_id::GetGlobalId, protocol_version::ProtocolVersion, uprotocol_pkg::{BasePkg, DeviceStatusPkg}};
+
+// What the server knows about one registered device
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceRecord {
+    pub namespace: String,
+    pub client_class: String,
+    pub client_instance: String,
+    // at most one endpoint per address family, in the order the families were first registered
+    pub endpoints: Vec<SocketAddr>,
+    // last authenticated package from the device
+    pub last_seen: SystemTime,
+    // as advertised by the device, see DeviceStatusPkg
+    pub nat_type: u8,
+    pub capabilities: u32,
+    // negotiated in the last hello
+    pub protocol: ProtocolVersion,
+}
+
+// Where the server keeps the observed endpoints of each registered device, keyed by
+// global id, which carries the namespace of tenants' devices.
+// Registering replaces the endpoint of the same address family and keeps the other.
+pub trait DeviceRegistry: Send + Sync {
+    fn register(&self, base_info: &BasePkg, endpoint_addr: SocketAddr);
+    // refresh last_seen of a registered device
+    fn touch(&self, global_id: &str);
+    fn advertise(&self, global_id: &str, nat_type: u8, capabilities: u32);
+    fn set_protocol(&self, global_id: &str, protocol: ProtocolVersion);
+    fn get(&self, global_id: &str) -> Option<DeviceRecord>;
+    // devices of `client_class` in `namespace` ordered by global id, starting after `after`
+    fn list(&self, namespace: &str, client_class: &str, after: Option<&str>) -> Vec<(String, DeviceRecord)>;
+    // devices registered in `namespace`
+    fn count(&self, namespace: &str) -> usize;
+    // forget the device, e.g. once it is revoked
+    fn remove(&self, global_id: &str) -> Option<DeviceRecord>;
+    fn lookup_all(&self, global_id: &str) -> Vec<SocketAddr> {
+        self.get(global_id).map(|record| record.endpoints).unwrap_or_default()
+    }
+    fn lookup(&self, global_id: &str) -> Option<SocketAddr> {
+        self.lookup_all(global_id).first().copied()
+    }
+    fn len(&self) -> usize;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryRegistry {
+    devices: RwLock<HashMap<String, DeviceRecord>>,
+}
+
+impl MemoryRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl DeviceRegistry for MemoryRegistry {
+    fn register(&self, base_info: &BasePkg, endpoint_addr: SocketAddr) {
+        let mut devices = self.devices.write().unwrap();
+        let record = devices.entry(base_info.get_global_id()).or_insert_with(|| DeviceRecord {
+            namespace: base_info.namespace.clone(),
+            client_class: base_info.client_class.clone(),
+            client_instance: base_info.client_instance.clone(),
+            endpoints: Vec::new(),
+            last_seen: SystemTime::now(),
+            nat_type: DeviceStatusPkg::NAT_UNKNOWN,
+            capabilities: 0,
+            protocol: ProtocolVersion::LEGACY,
+        });
+        match record.endpoints.iter_mut().find(|endpoint| endpoint.is_ipv6() == endpoint_addr.is_ipv6()) {
+            Some(endpoint) => *endpoint = endpoint_addr,
+            None => record.endpoints.push(endpoint_addr),
+        }
+        record.last_seen = SystemTime::now();
+    }
+    fn touch(&self, global_id: &str) {
+        if let Some(record) = self.devices.write().unwrap().get_mut(global_id) {
+            record.last_seen = SystemTime::now();
+        }
+    }
+    fn advertise(&self, global_id: &str, nat_type: u8, capabilities: u32) {
+        if let Some(record) = self.devices.write().unwrap().get_mut(global_id) {
+            record.nat_type = nat_type;
+            record.capabilities = capabilities;
+        }
+    }
+    fn set_protocol(&self, global_id: &str, protocol: ProtocolVersion) {
+        if let Some(record) = self.devices.write().unwrap().get_mut(global_id) {
+            record.protocol = protocol;
+        }
+    }
+    fn get(&self, global_id: &str) -> Option<DeviceRecord> {
+        self.devices.read().unwrap().get(global_id).cloned()
+    }
+    fn list(&self, namespace: &str, client_class: &str, after: Option<&str>) -> Vec<(String, DeviceRecord)> {
+        let mut listed: Vec<(String, DeviceRecord)> = self.devices.read().unwrap().iter()
+            .filter(|(global_id, record)| {
+                record.namespace == namespace && record.client_class == client_class && after.is_none_or(|after| global_id.as_str() > after)
+            })
+            .map(|(global_id, record)| (global_id.clone(), record.clone()))
+            .collect();
+        listed.sort_by(|(a, _), (b, _)| a.cmp(b));
+        listed
+    }
+    fn count(&self, namespace: &str) -> usize {
+        self.devices.read().unwrap().values().filter(|record| record.namespace == namespace).count()
+    }
+    fn remove(&self, global_id: &str) -> Option<DeviceRecord> {
+        self.devices.write().unwrap().remove(global_id)
+    }
+    fn len(&self) -> usize {
+        self.devices.read().unwrap().len()
+    }
+}
@@ -0,0 +1,514 @@
+use std::{net::SocketAddr, sync::Arc, time::{Duration, Instant, SystemTime}};
+
+use tracing::{debug, info, warn};
+use crate::core::{bincodec::BinCodec, codec::Codec, get_global_id::GetGlobalId, protocol_version::{ProtocolVersion, CAP_SESSION, CAP_TCP_PUNCH, MIN_PROTOCOL_VERSION}, uprotocol_pkg::{BasePkg, ClientHelloPkg, ClientRequestAckPkg, ClientRequestPkg, DeniedPkg, DeviceListAckPkg, DeviceListQueryPkg, DeviceStatusPkg, GetBaseInfo, HelloAckPkg, PeerExchangePkg, PresencePkg, StatusAckPkg, TcpPunchPkg, Up2pRequest}, wire, BaseUp2pProtocol, Up2pMessage};
+
+use super::{metrics::DropReason, registry::DeviceRecord, session::SESSION_PREFIX, udp_event_handle::Up2pEvent, ServerContext};
+
+// Per device ban list and rate limit and the tenant's rate limit, checked once the
+// package authenticated so a spoofer can't spend another device's budget. Floods
+// before that are the per ip limit's.
+fn admit_device(context: &ServerContext, base_info: &BasePkg) -> anyhow::Result<()> {
+    let global_id = base_info.get_global_id();
+    if context.rate_limit().banned_devices.iter().any(|banned| *banned == global_id) {
+        context.metrics.inc_dropped_packets(DropReason::Banned);
+        return Err(anyhow::anyhow!("Device {} is banned", global_id));
+    }
+    if !context.device_limiter.lock().unwrap().check(global_id.to_string(), Instant::now()) {
+        context.metrics.inc_dropped_packets(DropReason::RateLimited);
+        return Err(anyhow::anyhow!("Device {} is rate limited", global_id));
+    }
+    if !base_info.namespace.is_empty() && !context.tenants().check_rate(&base_info.namespace) {
+        context.metrics.inc_dropped_packets(DropReason::RateLimited);
+        return Err(anyhow::anyhow!("Namespace {} is rate limited", base_info.namespace));
+    }
+    Ok(())
+}
+
+// '/' separates the namespace in global ids
+fn check_names(base_info: &BasePkg) -> anyhow::Result<()> {
+    if [&base_info.namespace, &base_info.client_class, &base_info.client_instance].iter().any(|part| part.contains('/')) {
+        return Err(anyhow::anyhow!("'/' in the base info of {}", base_info.get_global_id()));
+    }
+    Ok(())
+}
+
+// The auth provider covers the default namespace, tenants their own
+fn verify_credential(context: &ServerContext, base_info: &BasePkg) -> anyhow::Result<()> {
+    check_names(base_info)?;
+    if base_info.namespace.is_empty() {
+        context.auth.verify(base_info)
+    } else {
+        context.tenants().verify(base_info)
+    }
+}
+
+fn authenticate(context: &ServerContext, base_info: &BasePkg) -> anyhow::Result<()> {
+    verify_credential(context, base_info).inspect_err(|_| context.metrics.inc_auth_failures())
+}
+
+// Only the hello carries the device's credential, the packages after it carry the
+// session token its HELLO_ACK handed out and have to come from the endpoint it
+// registered. Devices without SESSION send the credential with every package.
+fn authenticate_sender(context: &ServerContext, base_info: &BasePkg, endpoint_addr: SocketAddr) -> anyhow::Result<()> {
+    if !base_info.identity.is_empty() && !base_info.identity.starts_with(SESSION_PREFIX) {
+        return authenticate(context, base_info);
+    }
+    let global_id = base_info.get_global_id();
+    let in_session = check_names(base_info).is_ok()
+        && context.registry.lookup_all(&global_id).contains(&endpoint_addr)
+        && context.sessions.check(&global_id, &base_info.identity);
+    if !in_session {
+        context.metrics.inc_auth_failures();
+        return Err(anyhow::anyhow!("Package without a valid session from {}", endpoint_addr));
+    }
+    Ok(())
+}
+
+// registered devices whose hello would be turned away now
+pub(crate) fn unverified(context: &ServerContext) -> Vec<String> {
+    context.sessions.hellos().into_iter()
+        .filter(|(_, hello)| verify_credential(context, hello).is_err())
+        .map(|(global_id, _)| global_id)
+        .collect()
+}
+
+// A global id or class a device asked about, as the server keys it. Devices name
+// others without a namespace and only ever reach their own.
+fn scoped(namespace: &str, name: &str) -> anyhow::Result<String> {
+    if name.contains('/') {
+        return Err(anyhow::anyhow!("Invalid global id or class: {}", name));
+    }
+    Ok(crate::utils::qualify_global_id(namespace, name))
+}
+
+// the global id as the device's own tenant sees it
+fn unscoped(global_id: &str) -> &str {
+    global_id.split_once('/').map_or(global_id, |(_, global_id)| global_id)
+}
+
+// a tenant's new device is turned away once the tenant has max_devices
+async fn admit_registration(context: &ServerContext, base_info: &BasePkg, endpoint_addr: SocketAddr, codec: Codec, nonce: u64) -> anyhow::Result<()> {
+    let Some(max_devices) = context.tenants().get(&base_info.namespace).and_then(|tenant| tenant.max_devices) else {
+        return Ok(());
+    };
+    if context.registry.get(&base_info.get_global_id()).is_some() || context.registry.count(&base_info.namespace) < max_devices {
+        return Ok(());
+    }
+    let global_id = crate::utils::get_global_id(&base_info.client_class, &base_info.client_instance);
+    send_denied(context, BaseUp2pProtocol::TYPE_HELLO_ACK, global_id, "tenant device limit reached", endpoint_addr, codec, nonce).await?;
+    Err(anyhow::anyhow!("Namespace {} has {} devices already", base_info.namespace, max_devices))
+}
+
+// Whether the acl lets `from` reach the device of class and instance `to`, answering
+// the sender with a denial when it doesn't. `denied_type` is the package type the
+// sender is waiting for, `nonce` that of its request.
+async fn authorize_reach(context: &ServerContext, from: &BasePkg, (to_class, to_instance): (&str, &str), denied_type: u8, endpoint_addr: SocketAddr, codec: Codec, nonce: u64) -> anyhow::Result<()> {
+    if context.acl().allows(from, to_class, to_instance) {
+        return Ok(());
+    }
+    let to_global_id = crate::utils::get_global_id(to_class, to_instance);
+    send_denied(context, denied_type, to_global_id.clone(), "denied by acl", endpoint_addr, codec, nonce).await?;
+    Err(anyhow::anyhow!("Acl denies {} reaching {}", from.get_global_id(), to_global_id))
+}
+
+// Tell the sender right away instead of letting it wait for `denied_type`. Answers
+// go out in the codec of the package they answer.
+async fn send_denied(context: &ServerContext, denied_type: u8, target_global_id: String, reason: &str, endpoint_addr: SocketAddr, codec: Codec, nonce: u64) -> anyhow::Result<()> {
+    context.metrics.inc_dropped_packets(DropReason::Denied);
+    // legacy clients have no DENIED and wait out their timeout as they always did
+    if codec == Codec::Legacy {
+        return Ok(());
+    }
+    let encoded = Up2pMessage::Denied(DeniedPkg::new(denied_type, target_global_id, reason).with_nonce(nonce)).encode_with(codec)?;
+    context.send_to(&encoded, endpoint_addr).await?;
+    Ok(())
+}
+
+// Direct ipv6 when both devices registered an ipv6 endpoint, otherwise the
+// target's endpoint in the family the request came from.
+fn preferred_endpoint(requester: &[SocketAddr], request_addr: SocketAddr, target: &[SocketAddr]) -> Option<SocketAddr> {
+    let target_v6 = target.iter().find(|endpoint| endpoint.is_ipv6());
+    if requester.iter().any(|endpoint| endpoint.is_ipv6()) && target_v6.is_some() {
+        return target_v6.copied();
+    }
+    target.iter().find(|endpoint| endpoint.is_ipv6() == request_addr.is_ipv6())
+        .or(target.first())
+        .copied()
+}
+
+// a device list page stops before its package grows past this, clients read 1500 byte datagrams
+const MAX_DEVICE_LIST_LEN: usize = 1200;
+const MAX_DEVICE_LIST_PAGE: usize = 64;
+
+// The status of `global_id` in the requester's namespace, reported under the id
+// asked for. Devices the acl keeps from the requester look unknown.
+fn device_status(context: &ServerContext, requester: &BasePkg, global_id: &str) -> DeviceStatusPkg {
+    match scoped(&requester.namespace, global_id).ok().and_then(|global_id| context.registry.get(&global_id)) {
+        Some(record) if context.acl().allows(requester, &record.client_class, &record.client_instance) => record_status(context, &record),
+        _ => DeviceStatusPkg::unknown(global_id.to_string()),
+    }
+}
+
+// online means the device sent something within the online timeout
+fn is_online(context: &ServerContext, record: &DeviceRecord) -> bool {
+    SystemTime::now().duration_since(record.last_seen).unwrap_or_default() < context.online_timeout
+}
+
+fn record_status(context: &ServerContext, record: &DeviceRecord) -> DeviceStatusPkg {
+    let last_seen = SystemTime::now().duration_since(record.last_seen).unwrap_or_default();
+    DeviceStatusPkg::new(
+        crate::utils::get_global_id(&record.client_class, &record.client_instance),
+        is_online(context, record),
+        Some(last_seen.as_secs()),
+        record.nat_type,
+        record.capabilities,
+    )
+}
+
+async fn send_status_ack(context: &ServerContext, statuses: Vec<DeviceStatusPkg>, endpoint_addr: SocketAddr, codec: Codec, nonce: u64) -> anyhow::Result<()> {
+    let encoded = Up2pMessage::StatusAck(StatusAckPkg::new(statuses).with_nonce(nonce)).encode_with(codec)?;
+    context.send_to(&encoded, endpoint_addr).await?;
+    Ok(())
+}
+
+// the codec packages the device did not ask for are sent in
+fn negotiated_codec(context: &ServerContext, global_id: &str) -> Codec {
+    context.registry.get(global_id).map_or(Codec::Bincode, |record| record.protocol.codec())
+}
+
+// Push `event` about `global_id` to its subscribers, best effort like any datagram.
+// Subscribers the acl keeps from the device get nothing, the push has its endpoints.
+async fn publish_presence(context: &ServerContext, event: u8, global_id: &str) {
+    let Some(record) = context.registry.get(global_id) else {
+        return;
+    };
+    let subscribers = context.presence.subscribers(global_id, &crate::utils::qualify_global_id(&record.namespace, &record.client_class));
+    if subscribers.is_empty() {
+        return;
+    }
+    let endpoint_addresses = record.endpoints.iter().map(|endpoint| endpoint.to_string()).collect();
+    let presence = Up2pMessage::Presence(PresencePkg::new(event, record.client_class.clone(), record_status(context, &record), endpoint_addresses));
+    let acl = context.acl();
+    for (subscriber, endpoint, codec) in subscribers {
+        if !acl.allows(&subscriber, &record.client_class, &record.client_instance) {
+            continue;
+        }
+        let encoded = match presence.encode_with(codec) {
+            Ok(encoded) => encoded,
+            Err(e) => {
+                warn!("Failed to encode presence package: {:?}", e);
+                return;
+            }
+        };
+        if let Err(e) = context.send_to(&encoded, endpoint).await {
+            warn!("Failed to push presence to {}: {}", endpoint, e);
+        }
+    }
+}
+
+// Drop a device's registration, its endpoint answers no lookups and no longer
+// speaks for it. Subscribers hear it went offline.
+pub(crate) async fn evict(context: &ServerContext, global_id: &str) {
+    if context.presence.forget(global_id) {
+        publish_presence(context, PresencePkg::EVENT_OFFLINE, global_id).await;
+    }
+    context.sessions.end(global_id);
+    if context.registry.remove(global_id).is_some() {
+        info!("Evicted device {}", global_id);
+        context.metrics.set_registered_devices(context.registry.len());
+    }
+}
+
+// reports devices whose online timeout ran out, runs for the life of the server
+pub async fn expire_presence(context: Arc<ServerContext>) {
+    let mut ticker = tokio::time::interval((context.online_timeout / 4).max(Duration::from_millis(10)));
+    loop {
+        ticker.tick().await;
+        let expired = context.presence.expire(|global_id| {
+            context.registry.get(global_id).is_some_and(|record| is_online(&context, &record))
+        });
+        for global_id in expired {
+            debug!("Device went offline: {}", global_id);
+            publish_presence(&context, PresencePkg::EVENT_OFFLINE, &global_id).await;
+        }
+    }
+}
+
+// A page of the devices of the queried class that the acl lets `requester` see
+fn device_list_page(context: &ServerContext, requester: &BasePkg, query: &DeviceListQueryPkg, codec: Codec) -> anyhow::Result<DeviceListAckPkg> {
+    let namespace = &requester.namespace;
+    let acl = context.acl();
+    let limit = match query.get_limit() {
+        0 => MAX_DEVICE_LIST_PAGE,
+        limit => (limit as usize).min(MAX_DEVICE_LIST_PAGE),
+    };
+    let mut devices = Vec::new();
+    let mut len = 0;
+    let cursor = query.get_cursor().map(|cursor| scoped(namespace, cursor)).transpose()?;
+    let mut listed = context.registry.list(namespace, query.get_client_class(), cursor.as_deref()).into_iter()
+        .filter(|(_, record)| acl.allows(requester, &record.client_class, &record.client_instance))
+        .map(|(_, record)| record_status(context, &record))
+        .filter(|status| status.is_online() || !query.is_online_only())
+        .peekable();
+    while let Some(status) = listed.peek() {
+        let status_len = status.encode_with(codec)?.len();
+        if devices.len() == limit || len + status_len > MAX_DEVICE_LIST_LEN {
+            break;
+        }
+        len += status_len;
+        devices.extend(listed.next());
+    }
+    let next_cursor = match listed.peek() {
+        Some(_) => devices.last().map(DeviceStatusPkg::get_global_id),
+        None => None,
+    };
+    Ok(DeviceListAckPkg::new(devices, next_cursor))
+}
+
+pub async fn route(context: &ServerContext, event: Up2pEvent) {
+    let ubase_protocal_pkg = event.get_data();
+    let codec = wire::peek_codec(&ubase_protocal_pkg).unwrap_or_default();
+    if !context.accepts(codec) {
+        context.metrics.inc_decode_failures();
+        warn!("Package in {} from {} not accepted by this deployment", codec, event.get_addr());
+        return;
+    }
+    let message = match Up2pMessage::decode_from(&ubase_protocal_pkg) {
+        Ok(message) => message,
+        Err(e) => {
+            context.metrics.inc_decode_failures();
+            warn!("Failed to decode base protocal package: {:?}", e);
+            return;
+        }
+    };
+    info!("Recieved a base protocal package: {:?}", message);
+    context.metrics.inc_packets_received(message.get_pkg_type());
+    match message {
+        Up2pMessage::Hello(client_hello_pkg) => {
+            if let Err(e) = handle_client_hello_pkg(context, client_hello_pkg, event.get_addr(), codec).await {
+                warn!("Failed to handle client hello package: {:?}", e);
+            };
+        },
+        Up2pMessage::Request(client_request_pkg) => {
+            if let Err(e) = handle_client_request_pkg(context, client_request_pkg, event.get_addr(), codec).await {
+                warn!("Failed to handle client request package: {:?}", e);
+            };
+        },
+        Up2pMessage::PkgExchange(exchange_pkg) => {
+            if let Err(e) = handle_exchange_pkg(context, exchange_pkg, event.get_addr(), codec).await {
+                warn!("Failed to handle exchange package: {:?}", e);
+            };
+        },
+        // only the server sends these
+        message @ (Up2pMessage::HelloAck(_)
+            | Up2pMessage::RequestAck(_)
+            | Up2pMessage::TcpPunch(_)
+            | Up2pMessage::LanDiscovery(_)
+            | Up2pMessage::StatusAck(_)
+            | Up2pMessage::DeviceListAck(_)
+            | Up2pMessage::Presence(_)
+            | Up2pMessage::Denied(_)
+            | Up2pMessage::Signed(_)) => {
+            warn!("Unexpected package from {}: {:?}", event.get_addr(), message);
+        }
+    }
+}
+
+async fn handle_client_hello_pkg(context: &ServerContext, clien_hello_pkg: ClientHelloPkg, endpoint_addr: SocketAddr, codec: Codec) -> anyhow::Result<()> {
+    authenticate(context, clien_hello_pkg.get_baseinfo())?;
+    admit_device(context, clien_hello_pkg.get_baseinfo())?;
+    match clien_hello_pkg.get_msg() {
+        ClientHelloPkg::MSG_HELLO => {
+            info!("Client hello: {}", endpoint_addr);
+            // a legacy frame is from the release before negotiation, whatever it announces
+            let client_protocol = if codec == Codec::Legacy { ProtocolVersion::LEGACY } else { clien_hello_pkg.get_protocol() };
+            if client_protocol.version < MIN_PROTOCOL_VERSION {
+                let global_id = crate::utils::get_global_id(&clien_hello_pkg.get_baseinfo().client_class, &clien_hello_pkg.get_baseinfo().client_instance);
+                send_denied(context, BaseUp2pProtocol::TYPE_HELLO_ACK, global_id, "protocol version not supported", endpoint_addr, codec, clien_hello_pkg.get_nonce()).await?;
+                return Err(anyhow::anyhow!("Protocol version {} from {} not supported", client_protocol.version, endpoint_addr));
+            }
+            admit_registration(context, clien_hello_pkg.get_baseinfo(), endpoint_addr, codec, clien_hello_pkg.get_nonce()).await?;
+            // Add the device to the device list
+            let global_id = clien_hello_pkg.get_global_id();
+            let endpoints = context.registry.lookup_all(&global_id);
+            context.registry.register(clien_hello_pkg.get_baseinfo(), endpoint_addr);
+            context.registry.set_protocol(&global_id, context.protocol().negotiate(client_protocol));
+            context.metrics.set_registered_devices(context.registry.len());
+            let endpoints_changed = context.registry.lookup_all(&global_id) != endpoints;
+            if let Some(event) = context.presence.registered(&global_id, endpoints_changed) {
+                publish_presence(context, event, &global_id).await;
+            }
+            context.hooks.on_register(clien_hello_pkg.get_baseinfo(), endpoint_addr);
+            let session = context.sessions.start(clien_hello_pkg.get_baseinfo());
+            let mut hello_ack = HelloAckPkg::new(context.protocol()).with_nonce(clien_hello_pkg.get_nonce());
+            if client_protocol.supports(CAP_SESSION) {
+                hello_ack = hello_ack.with_session(&session);
+            }
+            let pp = Up2pMessage::HelloAck(hello_ack);
+            let encoded = pp.encode_with(codec)?;
+            debug!("Encoded response: {:?}", encoded);
+            context.send_to(&encoded, endpoint_addr).await?;
+        },
+        _ => warn!("Unkown client hello message: {:?}", clien_hello_pkg)
+    }
+    Ok(())
+}
+
+async fn handle_client_request_pkg(context: &ServerContext, client_request_pkg: ClientRequestPkg, endpoint_addr: SocketAddr, codec: Codec) -> anyhow::Result<()> {
+    authenticate_sender(context, client_request_pkg.get_baseinfo(), endpoint_addr)?;
+    admit_device(context, client_request_pkg.get_baseinfo())?;
+    let namespace = &client_request_pkg.get_baseinfo().namespace;
+    let nonce = client_request_pkg.get_nonce();
+    let registered = context.registry.lookup_all(&client_request_pkg.get_global_id()).contains(&endpoint_addr);
+    if registered {
+        context.registry.touch(&client_request_pkg.get_global_id());
+    }
+    match client_request_pkg.get_request() {
+        Up2pRequest::Endpoint(requested_global_id) => {
+            info!("Client request endpoint: {}", endpoint_addr);
+            let asked_global_id = requested_global_id;
+            let requested_global_id = scoped(namespace, requested_global_id)?;
+            if let Some(record) = context.registry.get(&requested_global_id) {
+                authorize_reach(context, client_request_pkg.get_baseinfo(), (&record.client_class, &record.client_instance), BaseUp2pProtocol::TYPE_REQUEST_ACK, endpoint_addr, codec, nonce).await?;
+            }
+            let found_all = context.registry.lookup_all(&requested_global_id);
+            let requester_endpoints = context.registry.lookup_all(&client_request_pkg.get_global_id());
+            let found = preferred_endpoint(&requester_endpoints, endpoint_addr, &found_all);
+            context.hooks.on_endpoint_request(client_request_pkg.get_baseinfo(), &requested_global_id, found);
+            if let Some(ov) = found {
+                let pp = Up2pMessage::RequestAck(
+                    ClientRequestAckPkg::new(ov.to_string())
+                        .with_endpoint_addresses(found_all.iter().map(|endpoint| endpoint.to_string()).collect())
+                        .answering(asked_global_id.clone(), nonce)
+                );
+                let encoded = pp.encode_with(codec)?;
+                context.send_to(&encoded, endpoint_addr).await?;
+            } else {
+                warn!("Requested device not found: {}", requested_global_id);
+            };
+        },
+        Up2pRequest::Status(query) => {
+            debug!("Client status request: {} for {:?}", endpoint_addr, query.get_global_ids());
+            let statuses = query.get_global_ids().iter().map(|global_id| device_status(context, client_request_pkg.get_baseinfo(), global_id)).collect();
+            send_status_ack(context, statuses, endpoint_addr, codec, nonce).await?;
+        },
+        Up2pRequest::AdvertiseStatus(advertisement) => {
+            // same rule as relaying, only the registered endpoint speaks for the device
+            if !registered {
+                return Err(anyhow::anyhow!("Status advertisement from unregistered endpoint: {}", endpoint_addr));
+            }
+            let global_id = client_request_pkg.get_global_id();
+            context.registry.advertise(&global_id, advertisement.get_nat_type(), advertisement.get_capabilities());
+            let status = context.registry.get(&global_id)
+                .map(|record| record_status(context, &record))
+                .ok_or_else(|| anyhow::anyhow!("Advertising device not registered: {}", global_id))?;
+            send_status_ack(context, vec![status], endpoint_addr, codec, nonce).await?;
+        },
+        Up2pRequest::ListDevices(query) => {
+            let requester_class = &client_request_pkg.get_baseinfo().client_class;
+            // denials are answered, the requester would retry a silent drop
+            let ack = if context.listing().allows(requester_class, query.get_client_class()) {
+                device_list_page(context, client_request_pkg.get_baseinfo(), query, codec)?
+            } else {
+                warn!("Device listing of {} denied for {}", query.get_client_class(), client_request_pkg.get_global_id());
+                DeviceListAckPkg::denied()
+            };
+            let encoded = Up2pMessage::DeviceListAck(ack.with_nonce(nonce)).encode_with(codec)?;
+            context.send_to(&encoded, endpoint_addr).await?;
+        },
+        Up2pRequest::SubscribePresence(subscription) => {
+            // pushes go to the registered endpoint only, like relayed packages
+            if !registered {
+                return Err(anyhow::anyhow!("Presence subscription from unregistered endpoint: {}", endpoint_addr));
+            }
+            let requester_class = &client_request_pkg.get_baseinfo().client_class;
+            // watching a class reveals its members, so it takes the same rule as listing it
+            let listing = context.listing();
+            let ack = if subscription.get_client_classes().iter().all(|client_class| listing.allows(requester_class, client_class)) {
+                let scoped_all = |names: &[String]| names.iter().map(|name| scoped(namespace, name)).collect::<anyhow::Result<Vec<_>>>();
+                context.presence.subscribe(
+                    client_request_pkg.get_baseinfo(),
+                    endpoint_addr,
+                    codec,
+                    scoped_all(subscription.get_global_ids())?,
+                    scoped_all(subscription.get_client_classes())?,
+                );
+                let statuses = subscription.get_global_ids().iter().map(|global_id| device_status(context, client_request_pkg.get_baseinfo(), global_id)).collect();
+                DeviceListAckPkg::new(statuses, None)
+            } else {
+                warn!("Presence subscription to {:?} denied for {}", subscription.get_client_classes(), client_request_pkg.get_global_id());
+                DeviceListAckPkg::denied()
+            };
+            let encoded = Up2pMessage::DeviceListAck(ack.with_nonce(nonce)).encode_with(codec)?;
+            context.send_to(&encoded, endpoint_addr).await?;
+        },
+        Up2pRequest::TcpPunch(requested_global_id) => {
+            let requested_global_id = scoped(namespace, requested_global_id)?;
+            if let Some(record) = context.registry.get(&requested_global_id) {
+                authorize_reach(context, client_request_pkg.get_baseinfo(), (&record.client_class, &record.client_instance), BaseUp2pProtocol::TYPE_TCP_PUNCH, endpoint_addr, codec, nonce).await?;
+                // an older peer would not know what to do with the TCP_PUNCH package
+                if !record.protocol.supports(CAP_TCP_PUNCH) {
+                    let to_global_id = crate::utils::get_global_id(&record.client_class, &record.client_instance);
+                    send_denied(context, BaseUp2pProtocol::TYPE_TCP_PUNCH, to_global_id, "peer does not support tcp punch", endpoint_addr, codec, nonce).await?;
+                    return Err(anyhow::anyhow!("Tcp punch target {} does not support it", requested_global_id));
+                }
+            }
+            handle_tcp_punch_request(context, &client_request_pkg.get_global_id(), &requested_global_id, endpoint_addr, codec, nonce).await?;
+        },
+    }
+    Ok(())
+}
+
+// Both peers have to be registered over a tcp connection, whose observed endpoints
+// are what they connect to. Each side gets the other's endpoint at the same time,
+// the requester's copy answers its request.
+async fn handle_tcp_punch_request(context: &ServerContext, src_global_id: &str, dst_global_id: &str, endpoint_addr: SocketAddr, codec: Codec, nonce: u64) -> anyhow::Result<()> {
+    if !context.registry.lookup_all(src_global_id).contains(&endpoint_addr) || !context.transport_for(endpoint_addr).is_connected(endpoint_addr) {
+        return Err(anyhow::anyhow!("Tcp punch request from {} not registered over tcp", endpoint_addr));
+    }
+    let dst_endpoint = context.registry.lookup_all(dst_global_id).into_iter()
+        .find(|dst_endpoint| context.transport_for(*dst_endpoint).is_connected(*dst_endpoint))
+        .ok_or_else(|| anyhow::anyhow!("Tcp punch target not registered over tcp: {}", dst_global_id))?;
+    info!("Tcp punch between {} ({}) and {} ({})", src_global_id, endpoint_addr, dst_global_id, dst_endpoint);
+    let dst_codec = negotiated_codec(context, dst_global_id);
+    for (to, to_codec, peer_global_id, peer_endpoint, to_nonce) in [(dst_endpoint, dst_codec, src_global_id, endpoint_addr, 0), (endpoint_addr, codec, dst_global_id, dst_endpoint, nonce)] {
+        let encoded = Up2pMessage::TcpPunch(
+            TcpPunchPkg::new(unscoped(peer_global_id).to_string(), peer_endpoint.to_string()).with_nonce(to_nonce)
+        ).encode_with(to_codec)?;
+        context.send_to(&encoded, to).await?;
+    }
+    Ok(())
+}
+
+async fn handle_exchange_pkg(context: &ServerContext, exchange_pkg: PeerExchangePkg, endpoint_addr: SocketAddr, codec: Codec) -> anyhow::Result<()> {
+    let src_global_id = exchange_pkg.get_global_id();
+    // verify identy
+    authenticate_sender(context, exchange_pkg.get_baseinfo(), endpoint_addr)?;
+    admit_device(context, exchange_pkg.get_baseinfo())?;
+    let src_endpoint = exchange_pkg.get_baseinfo().clone();
+    let dst_endpoint = match exchange_pkg.get_target() {
+        Some(target) => target,
+        None => {
+            warn!("No target found in exchange package");
+            return Ok(());
+        }
+    };
+    info!("Exchange package: src: {:?}, dst: {:?}", src_endpoint, dst_endpoint);
+    // only relay for a sender registered from this address, otherwise a spoofed
+    // source would turn the server into an amplifier
+    if !context.registry.lookup_all(&src_global_id).contains(&endpoint_addr) {
+        return Err(anyhow::anyhow!("Exchange package from unregistered endpoint: {}", endpoint_addr));
+    }
+    context.registry.touch(&src_global_id);
+    authorize_reach(context, &src_endpoint, (&dst_endpoint.client_class, &dst_endpoint.client_instance), BaseUp2pProtocol::TYPE_DENIED, endpoint_addr, codec, 0).await?;
+    // the target is always looked up in the sender's namespace, whatever the package says
+    let dst_global_id = scoped(&src_endpoint.namespace, &crate::utils::get_global_id(&dst_endpoint.client_class, &dst_endpoint.client_instance))?;
+    let exchange_endpoint = context.registry.lookup(&dst_global_id)
+        .ok_or_else(|| anyhow::anyhow!("Exchange target not found: {}", dst_global_id))?;
+    let encoded = Up2pMessage::PkgExchange(exchange_pkg.without_identity()).encode_with(negotiated_codec(context, &dst_global_id))?;
+    let sent = context.send_to(&encoded, exchange_endpoint).await?;
+    context.metrics.add_relayed_bytes(sent);
+    context.hooks.on_relay(&src_endpoint, &dst_endpoint, sent);
+    Ok(())
+}
@@ -1,4 +0,0 @@
-pub mod udp_event_handle;
-pub mod event_router;
-
-pub use udp_event_handle::udp_event_handle;
\ No newline at end of file
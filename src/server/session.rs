@@ -0,0 +1,71 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use crate::core::{get_global_id::GetGlobalId, uprotocol_pkg::BasePkg};
+
+// marks an identity as a session token rather than a credential
+pub(crate) const SESSION_PREFIX: &str = "ses.";
+
+#[derive(Debug)]
+struct Session {
+    // the base info of the last hello, credential included, checked again on reload
+    hello: BasePkg,
+    token: String,
+}
+
+// The session token each registered device got in its HELLO_ACK, keyed by global id.
+// A device keeps its token across hellos until it is evicted, so the hellos of a dual
+// stack or fallback client don't invalidate packages already sent with it.
+#[derive(Debug, Default)]
+pub(crate) struct Sessions {
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+impl Sessions {
+    // the device's token, a new one unless it has a session already
+    pub(crate) fn start(&self, hello: &BasePkg) -> String {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions.entry(hello.get_global_id()).or_insert_with(|| Session {
+            hello: hello.clone(),
+            token: format!("{}{:032x}", SESSION_PREFIX, rand::random::<u128>()),
+        });
+        session.hello = hello.clone();
+        session.token.clone()
+    }
+    pub(crate) fn check(&self, global_id: &str, token: &str) -> bool {
+        self.sessions.lock().unwrap().get(global_id).is_some_and(|session| constant_time_eq(&session.token, token))
+    }
+    pub(crate) fn end(&self, global_id: &str) {
+        self.sessions.lock().unwrap().remove(global_id);
+    }
+    // what each device said hello with, by global id
+    pub(crate) fn hellos(&self) -> Vec<(String, BasePkg)> {
+        self.sessions.lock().unwrap().iter().map(|(global_id, session)| (global_id.clone(), session.hello.clone())).collect()
+    }
+}
+
+// so timing does not tell how much of a guessed token was right
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test_support::test_base_info;
+
+    use super::{Sessions, SESSION_PREFIX};
+
+    #[tokio::test]
+    async fn test_session_survives_hellos_until_ended() {
+        let sessions = Sessions::default();
+        let token = sessions.start(&test_base_info("cli", "peer1"));
+        assert!(token.starts_with(SESSION_PREFIX));
+        assert_eq!(sessions.start(&test_base_info("cli", "peer1")), token);
+        assert_ne!(sessions.start(&test_base_info("cli", "peer2")), token);
+        assert!(sessions.check("cli-peer1", &token));
+        assert!(!sessions.check("cli-peer2", &token));
+        assert!(!sessions.check("cli-peer1", &token[..token.len() - 1]));
+        sessions.end("cli-peer1");
+        assert!(!sessions.check("cli-peer1", &token));
+        assert_ne!(sessions.start(&test_base_info("cli", "peer1")), token);
+    }
+}
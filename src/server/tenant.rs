@@ -0,0 +1,86 @@
+use std::{collections::HashMap, sync::Mutex, time::Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::core::uprotocol_pkg::BasePkg;
+
+use super::rate_limit::RateLimiter;
+
+// A tenant with its own credential and limits. Its devices register with
+// `namespace` in their BasePkg and can only look up and reach each other.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TenantConfig {
+    pub namespace: String,
+    // what the tenant's devices present as identity
+    pub identity: String,
+    // registered devices, unlimited if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_devices: Option<usize>,
+    // packets per second for all devices of the tenant together, 0 disables the limit
+    #[serde(default)]
+    pub rate: f64,
+    #[serde(default)]
+    pub burst: f64,
+}
+
+// the tenants of one server, each with its own packet budget
+#[derive(Debug, Default)]
+pub(crate) struct Tenants {
+    tenants: HashMap<String, TenantConfig>,
+    limiters: Mutex<HashMap<String, RateLimiter<()>>>,
+}
+
+impl Tenants {
+    pub(crate) fn new(tenants: Vec<TenantConfig>) -> Self {
+        let limiters = tenants.iter()
+            .map(|tenant| (tenant.namespace.clone(), RateLimiter::new(tenant.rate, tenant.burst)))
+            .collect();
+        Self {
+            tenants: tenants.into_iter().map(|tenant| (tenant.namespace.clone(), tenant)).collect(),
+            limiters: Mutex::new(limiters),
+        }
+    }
+    pub(crate) fn get(&self, namespace: &str) -> Option<&TenantConfig> {
+        self.tenants.get(namespace)
+    }
+    pub(crate) fn verify(&self, base_info: &BasePkg) -> anyhow::Result<()> {
+        match self.tenants.get(&base_info.namespace) {
+            Some(tenant) if tenant.identity == base_info.identity => Ok(()),
+            Some(_) => Err(anyhow::anyhow!("Identity not match for namespace {}", base_info.namespace)),
+            None => Err(anyhow::anyhow!("Unknown namespace {}", base_info.namespace)),
+        }
+    }
+    // take one packet from the tenant's budget
+    pub(crate) fn check_rate(&self, namespace: &str) -> bool {
+        match self.limiters.lock().unwrap().get_mut(namespace) {
+            Some(limiter) => limiter.check((), Instant::now()),
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{core::uprotocol_pkg::BasePkg, test_support::test_base_info};
+
+    use super::{TenantConfig, Tenants};
+
+    #[tokio::test]
+    async fn test_tenant_verify_and_rate() {
+        let tenants = Tenants::new(vec![TenantConfig {
+            namespace: "acme".to_string(),
+            identity: "acme-secret".to_string(),
+            max_devices: None,
+            rate: 1.0,
+            burst: 2.0,
+        }]);
+        let device = BasePkg { namespace: "acme".to_string(), identity: "acme-secret".to_string(), ..test_base_info("cli", "peer1") };
+        assert!(tenants.verify(&device).is_ok());
+        assert!(tenants.verify(&BasePkg { identity: "other".to_string(), ..device.clone() }).is_err());
+        assert!(tenants.verify(&BasePkg { namespace: "globex".to_string(), ..device.clone() }).is_err());
+        assert!(tenants.check_rate("acme"));
+        assert!(tenants.check_rate("acme"));
+        assert!(!tenants.check_rate("acme"));
+        assert!(tenants.check_rate("globex"));
+    }
+}
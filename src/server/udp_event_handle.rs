@@ -0,0 +1,63 @@
+use std::{net::SocketAddr, sync::Arc, time::Instant};
+
+use tokio::{sync::mpsc, task::JoinSet};
+use tracing::{debug, warn};
+
+use super::{metrics::DropReason, rate_limit::pre_auth_check, ServerContext, EVENT_CHANNEL_LEN};
+
+// one receive task per transport, all feeding the same channel
+pub fn udp_event_handle(context: Arc<ServerContext>, tasks: &mut JoinSet<()>) -> mpsc::Receiver<Up2pEvent> {
+    let (tx, rx) = mpsc::channel(EVENT_CHANNEL_LEN);
+    context.metrics.add_event_channel(tx.downgrade());
+    for transport in &context.transports {
+        let (tx, context, transport) = (tx.clone(), context.clone(), transport.clone());
+        tasks.spawn(async move {
+            let mut udp_buf = vec![0u8; 1500];
+            let mut udp_err_cnt = 0_u64;
+            loop {
+                if let Ok((size, addr)) = transport.recv_from(&mut udp_buf).await {
+                    // drop abusive traffic before it reaches the channel
+                    if context.rate_limit().banned_ips.contains(&addr.ip()) {
+                        context.metrics.inc_dropped_packets(DropReason::Banned);
+                        continue;
+                    }
+                    if !context.ip_limiter.lock().unwrap().check(addr.ip(), Instant::now()) {
+                        context.metrics.inc_dropped_packets(DropReason::RateLimited);
+                        debug!("Rate limited datagram from {}", addr);
+                        continue;
+                    }
+                    if !pre_auth_check(&udp_buf[..size]) {
+                        context.metrics.inc_dropped_packets(DropReason::Malformed);
+                        continue;
+                    }
+                    let data = udp_buf[..size].to_vec();
+                    let event = Up2pEvent::new(data, addr);
+                    if let Err(err) = tx.send(event).await {
+                        warn!("Failed to send udp event to channel, {}", err);
+                    }
+                } else {
+                    udp_err_cnt += 1;
+                    warn!("Failed to receive data from udp socket, total error count: {}", udp_err_cnt);
+                };
+            }
+        });
+    }
+    rx
+}
+
+pub struct Up2pEvent {
+    data: Vec<u8>,
+    addr: SocketAddr,
+}
+
+impl Up2pEvent {
+    pub(crate) fn new(data: Vec<u8>, addr: SocketAddr) -> Self {
+        Up2pEvent { data, addr }
+    }
+    pub fn get_data(&self) -> Vec<u8> {
+        self.data.clone()
+    }
+    pub fn get_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
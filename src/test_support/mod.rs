@@ -0,0 +1,79 @@
+// In-process test network: a server and clients on loopback, optionally behind
+// simulated nats. Built for `cargo test` and the test-support feature, see tests/nat_traversal.rs.
+pub mod nat;
+
+use std::{net::SocketAddr, sync::Arc};
+
+use tokio::{net::UdpSocket, task::JoinHandle};
+
+use crate::{client_lib::app::Up2pCli, core::uprotocol_pkg::BasePkg, server::{auth::SharedIdentityAuth, rate_limit::RateLimitConfig, Up2pServer}};
+use nat::SimulatedNat;
+
+pub const TEST_IDENTITY: &str = "test_identity";
+
+// a server on 127.0.0.1 with an os assigned port, stopped on drop
+pub struct TestServer {
+    server: Arc<Up2pServer>,
+    handle: JoinHandle<anyhow::Result<()>>,
+}
+
+impl TestServer {
+    pub async fn start() -> anyhow::Result<Self> {
+        Self::start_with(Self::builder()).await
+    }
+    // the builder start uses, to extend before start_with
+    pub fn builder() -> crate::server::Up2pServerBuilder {
+        Up2pServer::builder()
+            .bind_address("127.0.0.1:0".parse().unwrap())
+            .auth(Arc::new(SharedIdentityAuth::new(TEST_IDENTITY)))
+            // every test client shares 127.0.0.1
+            .rate_limit(RateLimitConfig { per_ip_rate: 0.0, ..RateLimitConfig::default() })
+            .workers(2)
+    }
+    pub async fn start_with(builder: crate::server::Up2pServerBuilder) -> anyhow::Result<Self> {
+        let server = Arc::new(builder.build().await?);
+        let running = server.clone();
+        let handle = tokio::spawn(async move { running.run().await });
+        Ok(Self { server, handle })
+    }
+    pub fn addr(&self) -> SocketAddr {
+        self.server.local_addr().expect("server socket has a local address")
+    }
+    pub fn server(&self) -> &Up2pServer {
+        &self.server
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+pub fn test_base_info(client_class: &str, client_instance: &str) -> BasePkg {
+    BasePkg {
+        client_class: client_class.to_string(),
+        client_instance: client_instance.to_string(),
+        identity: TEST_IDENTITY.to_string(),
+        namespace: String::new(),
+    }
+}
+
+// a started client talking to `server_addr` directly
+pub async fn start_client(base_info: BasePkg, server_addr: SocketAddr) -> anyhow::Result<Up2pCli> {
+    let udp_socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+    let (client, _cancel) = Up2pCli::new(base_info, udp_socket, (server_addr.ip(), server_addr.port()));
+    client.start().await?;
+    Ok(client)
+}
+
+// A started client behind `nat`, returned with its inside address.
+// Reach other peers through `nat.alias(peer_public_addr)`.
+pub async fn start_client_behind_nat(base_info: BasePkg, server_addr: SocketAddr, nat: &SimulatedNat) -> anyhow::Result<(Up2pCli, SocketAddr)> {
+    let server_alias = nat.alias(server_addr)?;
+    let udp_socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+    let inside_addr = udp_socket.local_addr()?;
+    let (client, _cancel) = Up2pCli::new(base_info, udp_socket, (server_alias.ip(), server_alias.port()));
+    client.start().await?;
+    Ok((client, inside_addr))
+}
@@ -0,0 +1,224 @@
+use std::{collections::{HashMap, HashSet}, net::{IpAddr, SocketAddr}, sync::{atomic::{AtomicUsize, Ordering}, Arc, Mutex}, time::Duration};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use tokio::{net::UdpSocket, task::JoinHandle};
+use tracing::{debug, warn};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NatType {
+    // any host may send to a mapping once it exists
+    FullCone,
+    // only hosts (ip) the client has sent to may answer
+    Restricted,
+    // only endpoints (ip and port) the client has sent to may answer
+    PortRestricted,
+    // a new mapping per destination, only that destination may answer
+    Symmetric,
+}
+
+// applied to every packet crossing the nat in either direction
+#[derive(Debug, Clone)]
+pub struct LinkConditions {
+    // probability in 0.0..=1.0 that a packet is dropped
+    pub loss: f64,
+    pub latency: Duration,
+    // seed of the loss rng, the same seed drops the same packets
+    pub seed: u64,
+}
+
+impl Default for LinkConditions {
+    fn default() -> Self {
+        Self { loss: 0.0, latency: Duration::ZERO, seed: 0 }
+    }
+}
+
+// A userspace nat between loopback clients and the rest of the test network.
+//
+// The client never sends to an external address directly. It sends to an alias
+// socket, see `alias`, and the nat forwards the packet from a public mapping socket
+// bound on `public_ip`. Packets arriving on a mapping pass the nat filter and reach
+// the client from the alias of their source, so the client sees a consistent peer.
+// Give each nat its own loopback ip (127.0.0.2, 127.0.0.3, ...) so the restricted
+// filters can tell hosts apart.
+pub struct SimulatedNat {
+    inner: Arc<NatInner>,
+}
+
+struct NatInner {
+    nat_type: NatType,
+    public_ip: IpAddr,
+    conditions: LinkConditions,
+    rng: Mutex<StdRng>,
+    drop_next: AtomicUsize,
+    // external address -> alias socket on the inside
+    aliases: Mutex<HashMap<SocketAddr, Arc<UdpSocket>>>,
+    mappings: Mutex<HashMap<MappingKey, Arc<Mapping>>>,
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+}
+
+// (client, destination for symmetric nats)
+type MappingKey = (SocketAddr, Option<SocketAddr>);
+
+struct Mapping {
+    socket: Arc<UdpSocket>,
+    client: SocketAddr,
+    contacted: Mutex<HashSet<SocketAddr>>,
+}
+
+impl SimulatedNat {
+    pub fn new(nat_type: NatType, public_ip: IpAddr, conditions: LinkConditions) -> Self {
+        Self {
+            inner: Arc::new(NatInner {
+                nat_type,
+                public_ip,
+                rng: Mutex::new(StdRng::seed_from_u64(conditions.seed)),
+                conditions,
+                drop_next: AtomicUsize::new(0),
+                aliases: Mutex::new(HashMap::new()),
+                mappings: Mutex::new(HashMap::new()),
+                tasks: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+    pub fn nat_type(&self) -> NatType {
+        self.inner.nat_type
+    }
+    // inside address a client sends to in order to reach `external`
+    pub fn alias(&self, external: SocketAddr) -> anyhow::Result<SocketAddr> {
+        Ok(NatInner::alias_socket(&self.inner, external)?.local_addr()?)
+    }
+    // public addresses currently mapped for `client`
+    pub fn public_addrs(&self, client: SocketAddr) -> Vec<SocketAddr> {
+        self.inner.mappings.lock().unwrap().values()
+            .filter(|mapping| mapping.client == client)
+            .filter_map(|mapping| mapping.socket.local_addr().ok())
+            .collect()
+    }
+    // deterministically drop the next `count` packets, whatever their direction
+    pub fn drop_next(&self, count: usize) {
+        self.inner.drop_next.store(count, Ordering::SeqCst);
+    }
+}
+
+impl Drop for SimulatedNat {
+    fn drop(&mut self) {
+        self.inner.tasks.lock().unwrap().iter().for_each(|task| task.abort());
+    }
+}
+
+impl NatInner {
+    fn alias_socket(inner: &Arc<Self>, external: SocketAddr) -> anyhow::Result<Arc<UdpSocket>> {
+        let mut aliases = inner.aliases.lock().unwrap();
+        if let Some(alias) = aliases.get(&external) {
+            return Ok(alias.clone());
+        }
+        let alias = bind_socket(IpAddr::from([127, 0, 0, 1]))?;
+        aliases.insert(external, alias.clone());
+        let (task_inner, task_alias) = (inner.clone(), alias.clone());
+        inner.spawn(async move {
+            let mut buf = vec![0u8; 1500];
+            loop {
+                let Ok((len, client)) = task_alias.recv_from(&mut buf).await else { continue };
+                if let Err(e) = NatInner::outbound(&task_inner, client, external, &buf[..len]) {
+                    warn!("nat outbound failed: {}", e);
+                }
+            }
+        });
+        Ok(alias)
+    }
+
+    fn outbound(inner: &Arc<Self>, client: SocketAddr, external: SocketAddr, data: &[u8]) -> anyhow::Result<()> {
+        let key = match inner.nat_type {
+            NatType::Symmetric => (client, Some(external)),
+            _ => (client, None),
+        };
+        let mapping = {
+            let mut mappings = inner.mappings.lock().unwrap();
+            match mappings.get(&key) {
+                Some(mapping) => mapping.clone(),
+                None => {
+                    let mapping = Arc::new(Mapping {
+                        socket: bind_socket(inner.public_ip)?,
+                        client,
+                        contacted: Mutex::new(HashSet::new()),
+                    });
+                    mappings.insert(key, mapping.clone());
+                    let (task_inner, task_mapping) = (inner.clone(), mapping.clone());
+                    inner.spawn(async move {
+                        let mut buf = vec![0u8; 1500];
+                        loop {
+                            let Ok((len, source)) = task_mapping.socket.recv_from(&mut buf).await else { continue };
+                            if let Err(e) = NatInner::inbound(&task_inner, &task_mapping, source, &buf[..len]) {
+                                warn!("nat inbound failed: {}", e);
+                            }
+                        }
+                    });
+                    mapping
+                }
+            }
+        };
+        mapping.contacted.lock().unwrap().insert(external);
+        inner.transmit(mapping.socket.clone(), data, external);
+        Ok(())
+    }
+
+    fn inbound(inner: &Arc<Self>, mapping: &Mapping, source: SocketAddr, data: &[u8]) -> anyhow::Result<()> {
+        let allowed = {
+            let contacted = mapping.contacted.lock().unwrap();
+            match inner.nat_type {
+                NatType::FullCone => true,
+                NatType::Restricted => contacted.iter().any(|addr| addr.ip() == source.ip()),
+                NatType::PortRestricted | NatType::Symmetric => contacted.contains(&source),
+            }
+        };
+        if !allowed {
+            debug!("nat filtered packet from {} to {}", source, mapping.socket.local_addr()?);
+            return Ok(());
+        }
+        let alias = NatInner::alias_socket(inner, source)?;
+        inner.transmit(alias, data, mapping.client);
+        Ok(())
+    }
+
+    fn transmit(&self, socket: Arc<UdpSocket>, data: &[u8], to: SocketAddr) {
+        if self.drop_next.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1)).is_ok() {
+            return;
+        }
+        if self.conditions.loss > 0.0 && self.rng.lock().unwrap().random_bool(self.conditions.loss.min(1.0)) {
+            return;
+        }
+        let latency = self.conditions.latency;
+        if latency.is_zero() {
+            // keep packet order when there is no delay to simulate
+            match socket.try_send_to(data, to) {
+                Ok(_) => return,
+                Err(e) if e.kind() != std::io::ErrorKind::WouldBlock => {
+                    warn!("nat send to {} failed: {}", to, e);
+                    return;
+                }
+                Err(_) => {}
+            }
+        }
+        let data = data.to_vec();
+        self.spawn(async move {
+            if !latency.is_zero() {
+                tokio::time::sleep(latency).await;
+            }
+            if let Err(e) = socket.send_to(&data, to).await {
+                warn!("nat send to {} failed: {}", to, e);
+            }
+        });
+    }
+
+    fn spawn(&self, task: impl Future<Output = ()> + Send + 'static) {
+        let mut tasks = self.tasks.lock().unwrap();
+        tasks.retain(|task| !task.is_finished());
+        tasks.push(tokio::spawn(task));
+    }
+}
+
+fn bind_socket(ip: IpAddr) -> anyhow::Result<Arc<UdpSocket>> {
+    let socket = std::net::UdpSocket::bind((ip, 0))?;
+    socket.set_nonblocking(true)?;
+    Ok(Arc::new(UdpSocket::from_std(socket)?))
+}
@@ -0,0 +1,125 @@
+use std::{collections::HashMap, io, net::{IpAddr, SocketAddr}, sync::{Arc, Mutex}};
+
+use tokio::sync::{mpsc, Mutex as AsyncMutex};
+
+use super::{DatagramTransport, TransportFuture};
+
+// queued datagrams per transport before new ones are dropped, like a full socket buffer
+const QUEUE_SIZE: usize = 1024;
+
+type Datagram = (Vec<u8>, SocketAddr);
+
+// An in-process datagram network. Transports bound on it reach each other by
+// address, datagrams to unknown addresses are dropped like udp would.
+#[derive(Clone, Default)]
+pub struct MemoryNetwork {
+    inner: Arc<Mutex<NetworkInner>>,
+}
+
+#[derive(Default)]
+struct NetworkInner {
+    endpoints: HashMap<SocketAddr, mpsc::Sender<Datagram>>,
+    next_port: u16,
+}
+
+impl MemoryNetwork {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    // port 0 picks a free port
+    pub fn bind(&self, address: SocketAddr) -> io::Result<Arc<MemoryTransport>> {
+        let mut inner = self.inner.lock().unwrap();
+        let address = if address.port() == 0 {
+            loop {
+                inner.next_port = inner.next_port.checked_add(1).unwrap_or(1024).max(1024);
+                let candidate = SocketAddr::new(address.ip(), inner.next_port);
+                if !inner.endpoints.contains_key(&candidate) {
+                    break candidate;
+                }
+            }
+        } else {
+            address
+        };
+        if inner.endpoints.get(&address).is_some_and(|tx| !tx.is_closed()) {
+            return Err(io::Error::new(io::ErrorKind::AddrInUse, format!("{} is already bound", address)));
+        }
+        let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+        inner.endpoints.insert(address, tx);
+        Ok(Arc::new(MemoryTransport {
+            network: self.clone(),
+            local_addr: address,
+            rx: AsyncMutex::new(rx),
+        }))
+    }
+    pub fn bind_any(&self, ip: IpAddr) -> io::Result<Arc<MemoryTransport>> {
+        self.bind(SocketAddr::new(ip, 0))
+    }
+    fn route(&self, from: SocketAddr, data: &[u8], target: SocketAddr) {
+        let tx = self.inner.lock().unwrap().endpoints.get(&target).cloned();
+        if let Some(tx) = tx {
+            let _ = tx.try_send((data.to_vec(), from));
+        }
+    }
+}
+
+pub struct MemoryTransport {
+    network: MemoryNetwork,
+    local_addr: SocketAddr,
+    rx: AsyncMutex<mpsc::Receiver<Datagram>>,
+}
+
+impl DatagramTransport for MemoryTransport {
+    fn send_to<'a>(&'a self, data: &'a [u8], target: SocketAddr) -> TransportFuture<'a, usize> {
+        Box::pin(async move {
+            self.network.route(self.local_addr, data, target);
+            Ok(data.len())
+        })
+    }
+    fn recv_from<'a>(&'a self, buf: &'a mut [u8]) -> TransportFuture<'a, (usize, SocketAddr)> {
+        Box::pin(async move {
+            let (data, from) = self.rx.lock().await.recv().await
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "memory network closed"))?;
+            // truncate like a too small udp buffer would
+            let len = data.len().min(buf.len());
+            buf[..len].copy_from_slice(&data[..len]);
+            Ok((len, from))
+        })
+    }
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.local_addr)
+    }
+}
+
+impl Drop for MemoryTransport {
+    fn drop(&mut self) {
+        self.network.inner.lock().unwrap().endpoints.remove(&self.local_addr);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::IpAddr;
+
+    use crate::transport::DatagramTransport;
+
+    use super::MemoryNetwork;
+
+    #[tokio::test]
+    async fn test_memory_transport() {
+        let network = MemoryNetwork::new();
+        let a = network.bind_any(IpAddr::from([10, 0, 0, 1])).unwrap();
+        let b = network.bind("10.0.0.2:9008".parse().unwrap()).unwrap();
+        assert!(network.bind(b.local_addr().unwrap()).is_err());
+        a.send_to(b"hello", b.local_addr().unwrap()).await.unwrap();
+        // unknown targets are dropped silently
+        a.send_to(b"lost", "10.0.0.3:1".parse().unwrap()).await.unwrap();
+        let mut buf = [0u8; 16];
+        let (len, from) = b.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], b"hello");
+        assert_eq!(from, a.local_addr().unwrap());
+        // a dropped transport frees its address
+        let addr = b.local_addr().unwrap();
+        drop(b);
+        assert!(network.bind(addr).is_ok());
+    }
+}
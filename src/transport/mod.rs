@@ -0,0 +1,72 @@
+// Datagram substrate the client and server speak the protocol over.
+// The futures are boxed so the trait stays object safe, both sides hold an
+// `Arc<dyn DatagramTransport>`.
+pub mod memory;
+pub mod stream;
+mod websocket;
+
+use std::{future::Future, io, net::{Ipv4Addr, Ipv6Addr, SocketAddr}, pin::Pin};
+
+use tokio::net::UdpSocket;
+
+pub type TransportFuture<'a, T> = Pin<Box<dyn Future<Output = io::Result<T>> + Send + 'a>>;
+
+pub trait DatagramTransport: Send + Sync + 'static {
+    fn send_to<'a>(&'a self, data: &'a [u8], target: SocketAddr) -> TransportFuture<'a, usize>;
+    fn recv_from<'a>(&'a self, buf: &'a mut [u8]) -> TransportFuture<'a, (usize, SocketAddr)>;
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+    // whether `target` is a connection accepted by this transport. Stream
+    // transports can only answer their own connections, datagram ones reach anyone.
+    fn is_connected(&self, _target: SocketAddr) -> bool {
+        false
+    }
+    // whether this transport only talks to the one server it dialed, so
+    // everything received comes from that server
+    fn is_server_connection(&self) -> bool {
+        false
+    }
+}
+
+// A dual stack socket reports ipv4 peers as v4 mapped ipv6 addresses, callers
+// only ever see and pass the plain ipv4 form.
+impl DatagramTransport for UdpSocket {
+    fn send_to<'a>(&'a self, data: &'a [u8], target: SocketAddr) -> TransportFuture<'a, usize> {
+        Box::pin(async move {
+            let target = match target {
+                SocketAddr::V4(v4) if UdpSocket::local_addr(self)?.is_ipv6() => SocketAddr::new(v4.ip().to_ipv6_mapped().into(), v4.port()),
+                target => target,
+            };
+            UdpSocket::send_to(self, data, target).await
+        })
+    }
+    fn recv_from<'a>(&'a self, buf: &'a mut [u8]) -> TransportFuture<'a, (usize, SocketAddr)> {
+        Box::pin(async move {
+            let (len, from) = UdpSocket::recv_from(self, buf).await?;
+            Ok((len, SocketAddr::new(from.ip().to_canonical(), from.port())))
+        })
+    }
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        UdpSocket::local_addr(self)
+    }
+}
+
+// A udp socket on `port` reaching both ipv4 and ipv6 peers, or ipv4 only when
+// the host has no ipv6.
+pub fn bind_dual_stack_udp(port: u16) -> io::Result<UdpSocket> {
+    let dual_stack = || -> io::Result<socket2::Socket> {
+        let socket = socket2::Socket::new(socket2::Domain::IPV6, socket2::Type::DGRAM, Some(socket2::Protocol::UDP))?;
+        socket.set_only_v6(false)?;
+        socket.bind(&SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), port).into())?;
+        Ok(socket)
+    };
+    let socket = match dual_stack() {
+        Ok(socket) => socket,
+        Err(_) => {
+            let socket = socket2::Socket::new(socket2::Domain::IPV4, socket2::Type::DGRAM, Some(socket2::Protocol::UDP))?;
+            socket.bind(&SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port).into())?;
+            socket
+        }
+    };
+    socket.set_nonblocking(true)?;
+    UdpSocket::from_std(socket.into())
+}
@@ -0,0 +1,361 @@
+// Stream transports for networks that drop udp. Over tcp every package is one
+// frame, a big endian u16 length followed by the encoded BaseUp2pProtocol.
+// The listener side hands frames from all its connections to the server as if
+// they were datagrams from the connection's peer address; the client side has
+// a single connection to the server and sends everything through it.
+use std::{collections::HashMap, io, net::SocketAddr, sync::{Arc, Mutex, Weak}, time::Duration};
+
+use tokio::{io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt}, net::{TcpListener, TcpSocket, TcpStream}, sync::{mpsc, Mutex as AsyncMutex}, task::JoinHandle};
+use tracing::{debug, warn};
+
+use super::{DatagramTransport, TransportFuture};
+
+// same bound the server puts on udp packages
+pub const MAX_FRAME_LEN: usize = 1500;
+// queued frames per connection before new ones are dropped, like a full socket buffer
+const QUEUE_SIZE: usize = 1024;
+// a simultaneous open keeps sending syns for this long, a refused or unanswered
+// syn just means the other side has not started yet
+pub(crate) const PUNCH_TIMEOUT: Duration = Duration::from_secs(5);
+const PUNCH_ATTEMPT_TIMEOUT: Duration = Duration::from_millis(500);
+const PUNCH_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+type Datagram = (Vec<u8>, SocketAddr);
+
+pub async fn write_frame<W: AsyncWrite + Unpin>(writer: &mut W, frame: &[u8]) -> io::Result<()> {
+    if frame.len() > MAX_FRAME_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("frame of {} bytes is too large", frame.len())));
+    }
+    writer.write_all(&(frame.len() as u16).to_be_bytes()).await?;
+    writer.write_all(frame).await?;
+    writer.flush().await
+}
+
+pub async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let len = reader.read_u16().await? as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("frame of {} bytes is too large", len)));
+    }
+    let mut frame = vec![0u8; len];
+    reader.read_exact(&mut frame).await?;
+    Ok(frame)
+}
+
+// open connections of a listener, by peer address
+#[derive(Default)]
+pub(crate) struct ConnectionHub {
+    connections: Mutex<HashMap<SocketAddr, mpsc::Sender<Vec<u8>>>>,
+}
+
+impl ConnectionHub {
+    // the receiver yields the frames to write to `peer`
+    pub(crate) fn add(&self, peer: SocketAddr) -> mpsc::Receiver<Vec<u8>> {
+        let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+        self.connections.lock().unwrap().insert(peer, tx);
+        rx
+    }
+    pub(crate) fn remove(&self, peer: SocketAddr) {
+        self.connections.lock().unwrap().remove(&peer);
+    }
+    fn contains(&self, peer: SocketAddr) -> bool {
+        self.connections.lock().unwrap().contains_key(&peer)
+    }
+    fn send_to(&self, data: &[u8], target: SocketAddr) -> io::Result<usize> {
+        let tx = self.connections.lock().unwrap().get(&target).cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, format!("no connection from {}", target)))?;
+        if let Err(mpsc::error::TrySendError::Closed(_)) = tx.try_send(data.to_vec()) {
+            return Err(io::Error::new(io::ErrorKind::NotConnected, format!("connection from {} is closed", target)));
+        }
+        Ok(data.len())
+    }
+}
+
+// Server side of a stream transport, see bind_tcp and bind_websocket.
+// Dropping it stops accepting, open connections close once they notice.
+pub struct StreamListenerTransport {
+    local_addr: SocketAddr,
+    hub: Arc<ConnectionHub>,
+    inbound: AsyncMutex<mpsc::Receiver<Datagram>>,
+    accept_task: JoinHandle<()>,
+}
+
+impl StreamListenerTransport {
+    pub async fn bind_tcp(address: SocketAddr) -> io::Result<Arc<Self>> {
+        Self::bind(address, |stream, peer, hub, inbound| async move {
+            let (mut reader, mut writer) = stream.into_split();
+            let mut outbound = hub.upgrade().ok_or(io::ErrorKind::NotConnected)?.add(peer);
+            let writing = async {
+                while let Some(frame) = outbound.recv().await {
+                    write_frame(&mut writer, &frame).await?;
+                }
+                Ok(())
+            };
+            let reading = async {
+                loop {
+                    let frame = read_frame(&mut reader).await?;
+                    if inbound.send((frame, peer)).await.is_err() {
+                        return Ok(());
+                    }
+                }
+            };
+            tokio::select! {
+                result = writing => result,
+                result = reading => result,
+            }
+        }).await
+    }
+
+    // `serve` runs one accepted connection until it closes
+    pub(crate) async fn bind<F, Fut>(address: SocketAddr, serve: F) -> io::Result<Arc<Self>>
+    where
+        F: Fn(TcpStream, SocketAddr, Weak<ConnectionHub>, mpsc::Sender<Datagram>) -> Fut + Send + 'static,
+        Fut: Future<Output = io::Result<()>> + Send + 'static,
+    {
+        let listener = TcpListener::bind(address).await?;
+        let local_addr = listener.local_addr()?;
+        let hub = Arc::new(ConnectionHub::default());
+        let (inbound_tx, inbound_rx) = mpsc::channel(QUEUE_SIZE);
+        // the connections only hold a weak reference, so dropping the transport ends them
+        let weak_hub = Arc::downgrade(&hub);
+        let accept_task = tokio::spawn(async move {
+            loop {
+                let (stream, peer) = match listener.accept().await {
+                    // ipv4 peers of a dual stack listener come in v4 mapped
+                    Ok((stream, peer)) => (stream, SocketAddr::new(peer.ip().to_canonical(), peer.port())),
+                    Err(e) => {
+                        warn!("Failed to accept connection on {}, {}", local_addr, e);
+                        continue;
+                    }
+                };
+                debug!("Accepted connection from {} on {}", peer, local_addr);
+                let connection = serve(stream, peer, weak_hub.clone(), inbound_tx.clone());
+                let weak_hub = weak_hub.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = connection.await {
+                        debug!("Connection from {} closed, {}", peer, e);
+                    }
+                    if let Some(hub) = weak_hub.upgrade() {
+                        hub.remove(peer);
+                    }
+                });
+            }
+        });
+        Ok(Arc::new(Self {
+            local_addr,
+            hub,
+            inbound: AsyncMutex::new(inbound_rx),
+            accept_task,
+        }))
+    }
+}
+
+impl Drop for StreamListenerTransport {
+    fn drop(&mut self) {
+        self.accept_task.abort();
+    }
+}
+
+impl DatagramTransport for StreamListenerTransport {
+    fn send_to<'a>(&'a self, data: &'a [u8], target: SocketAddr) -> TransportFuture<'a, usize> {
+        Box::pin(async move { self.hub.send_to(data, target) })
+    }
+    fn recv_from<'a>(&'a self, buf: &'a mut [u8]) -> TransportFuture<'a, (usize, SocketAddr)> {
+        Box::pin(async move {
+            let (data, from) = self.inbound.lock().await.recv().await
+                .ok_or_else(|| io::Error::new(io::ErrorKind::ConnectionAborted, "listener closed"))?;
+            let len = data.len().min(buf.len());
+            buf[..len].copy_from_slice(&data[..len]);
+            Ok((len, from))
+        })
+    }
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.local_addr)
+    }
+    fn is_connected(&self, target: SocketAddr) -> bool {
+        self.hub.contains(target)
+    }
+}
+
+// Client side of a stream transport, see connect_tcp and connect_websocket.
+// Every package goes to the server whatever the target, the server relays
+// PeerExchangePkg by its target. Received packages come from the server address.
+pub struct StreamClientTransport {
+    server_addr: SocketAddr,
+    local_addr: SocketAddr,
+    outbound: mpsc::Sender<Vec<u8>>,
+    inbound: AsyncMutex<mpsc::Receiver<Vec<u8>>>,
+    task: JoinHandle<()>,
+}
+
+impl StreamClientTransport {
+    // the local port is shareable, so tcp_simultaneous_open can punch from the
+    // endpoint the server has seen
+    pub async fn connect_tcp(server_addr: SocketAddr) -> io::Result<Arc<Self>> {
+        let unspecified = match server_addr {
+            SocketAddr::V4(_) => SocketAddr::from(([0, 0, 0, 0], 0)),
+            SocketAddr::V6(_) => SocketAddr::from(([0u16; 8], 0)),
+        };
+        let stream = reusable_tcp_socket(unspecified)?.connect(server_addr).await?;
+        let local_addr = stream.local_addr()?;
+        let (mut reader, mut writer) = stream.into_split();
+        Ok(Self::spawn(server_addr, local_addr, |mut outbound, inbound| async move {
+            let writing = async {
+                while let Some(frame) = outbound.recv().await {
+                    write_frame(&mut writer, &frame).await?;
+                }
+                Ok(())
+            };
+            let reading = async {
+                loop {
+                    let frame = read_frame(&mut reader).await?;
+                    if inbound.send(frame).await.is_err() {
+                        return Ok(());
+                    }
+                }
+            };
+            tokio::select! {
+                result = writing => result,
+                result = reading => result,
+            }
+        }))
+    }
+
+    // `serve` runs the connection, writing what it receives and forwarding what it reads
+    pub(crate) fn spawn<F, Fut>(server_addr: SocketAddr, local_addr: SocketAddr, serve: F) -> Arc<Self>
+    where
+        F: FnOnce(mpsc::Receiver<Vec<u8>>, mpsc::Sender<Vec<u8>>) -> Fut,
+        Fut: Future<Output = io::Result<()>> + Send + 'static,
+    {
+        let (outbound_tx, outbound_rx) = mpsc::channel(QUEUE_SIZE);
+        let (inbound_tx, inbound_rx) = mpsc::channel(QUEUE_SIZE);
+        let connection = serve(outbound_rx, inbound_tx);
+        let task = tokio::spawn(async move {
+            match connection.await {
+                Ok(()) => debug!("Connection to {} closed", server_addr),
+                Err(e) => warn!("Connection to {} closed, {}", server_addr, e),
+            }
+        });
+        Arc::new(Self {
+            server_addr,
+            local_addr,
+            outbound: outbound_tx,
+            inbound: AsyncMutex::new(inbound_rx),
+            task,
+        })
+    }
+}
+
+impl Drop for StreamClientTransport {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl DatagramTransport for StreamClientTransport {
+    fn send_to<'a>(&'a self, data: &'a [u8], _target: SocketAddr) -> TransportFuture<'a, usize> {
+        Box::pin(async move {
+            self.outbound.send(data.to_vec()).await
+                .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, format!("connection to {} is closed", self.server_addr)))?;
+            Ok(data.len())
+        })
+    }
+    fn recv_from<'a>(&'a self, buf: &'a mut [u8]) -> TransportFuture<'a, (usize, SocketAddr)> {
+        Box::pin(async move {
+            let data = self.inbound.lock().await.recv().await
+                .ok_or_else(|| io::Error::new(io::ErrorKind::ConnectionAborted, format!("connection to {} is closed", self.server_addr)))?;
+            let len = data.len().min(buf.len());
+            buf[..len].copy_from_slice(&data[..len]);
+            Ok((len, self.server_addr))
+        })
+    }
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.local_addr)
+    }
+    fn is_server_connection(&self) -> bool {
+        true
+    }
+}
+
+fn reusable_tcp_socket(local_addr: SocketAddr) -> io::Result<TcpSocket> {
+    let socket = match local_addr {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    socket.set_reuseaddr(true)?;
+    #[cfg(unix)]
+    socket.set_reuseport(true)?;
+    socket.bind(local_addr)?;
+    Ok(socket)
+}
+
+// Tcp hole punching: connect from `local_addr`, the address of an open connection
+// to the server, to the peer's observed endpoint while the peer does the same.
+// The crossing syns make both connects succeed with one connection. A syn that
+// arrives between two of our attempts is taken by a listener on the same port,
+// there is only one address pair so either way both sides share the connection.
+pub async fn tcp_simultaneous_open(local_addr: SocketAddr, peer_addr: SocketAddr) -> io::Result<TcpStream> {
+    let listener = reusable_tcp_socket(local_addr)?.listen(1)?;
+    let accepting = async {
+        loop {
+            let (stream, from) = listener.accept().await?;
+            if from == peer_addr {
+                return Ok::<_, io::Error>(stream);
+            }
+            debug!("Dropped tcp punch connection from unexpected {}", from);
+        }
+    };
+    let connecting = async {
+        loop {
+            let socket = reusable_tcp_socket(local_addr)?;
+            match tokio::time::timeout(PUNCH_ATTEMPT_TIMEOUT, socket.connect(peer_addr)).await {
+                Ok(Ok(stream)) => return Ok(stream),
+                Ok(Err(e)) => debug!("Tcp punch from {} to {} failed, {}", local_addr, peer_addr, e),
+                Err(_) => debug!("Tcp punch from {} to {} timed out", local_addr, peer_addr),
+            }
+            tokio::time::sleep(PUNCH_RETRY_INTERVAL).await;
+        }
+    };
+    let punching = async {
+        tokio::select! {
+            result = accepting => result,
+            result = connecting => result,
+        }
+    };
+    tokio::time::timeout(PUNCH_TIMEOUT, punching).await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, format!("tcp punch to {} timed out", peer_addr)))?
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::transport::DatagramTransport;
+    use super::{StreamClientTransport, StreamListenerTransport};
+
+    #[tokio::test]
+    async fn test_tcp_stream_transport() {
+        let listener = StreamListenerTransport::bind_tcp("127.0.0.1:0".parse().unwrap()).await.unwrap();
+        let client = StreamClientTransport::connect_tcp(listener.local_addr().unwrap()).await.unwrap();
+        // the target is ignored, everything goes to the server
+        client.send_to(b"first", "192.0.2.1:9".parse().unwrap()).await.unwrap();
+        client.send_to(b"second", "192.0.2.1:9".parse().unwrap()).await.unwrap();
+        let mut buf = [0u8; 1500];
+        let (len, peer) = listener.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], b"first");
+        assert_eq!(peer, client.local_addr().unwrap());
+        assert!(listener.is_connected(peer));
+        let (len, _) = listener.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], b"second");
+
+        listener.send_to(b"reply", peer).await.unwrap();
+        let (len, from) = tokio::time::timeout(Duration::from_secs(1), client.recv_from(&mut buf)).await.unwrap().unwrap();
+        assert_eq!(&buf[..len], b"reply");
+        assert_eq!(from, listener.local_addr().unwrap());
+
+        // a closed connection is forgotten
+        drop(client);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!listener.is_connected(peer));
+        assert!(listener.send_to(b"gone", peer).await.is_err());
+    }
+}
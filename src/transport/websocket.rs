@@ -0,0 +1,110 @@
+// WebSocket flavour of the stream transports, for networks that only let http
+// through. Each binary message carries one encoded BaseUp2pProtocol.
+use std::{io, net::SocketAddr, sync::Arc};
+
+use futures_util::{SinkExt, StreamExt};
+use tokio_tungstenite::tungstenite::{self, Message};
+
+use super::stream::{StreamClientTransport, StreamListenerTransport, MAX_FRAME_LEN};
+
+fn to_io_error(e: tungstenite::Error) -> io::Error {
+    match e {
+        tungstenite::Error::Io(e) => e,
+        e => io::Error::other(e),
+    }
+}
+
+impl StreamListenerTransport {
+    pub async fn bind_websocket(address: SocketAddr) -> io::Result<Arc<Self>> {
+        Self::bind(address, |stream, peer, hub, inbound| async move {
+            let (mut sink, mut source) = tokio_tungstenite::accept_async(stream).await.map_err(to_io_error)?.split();
+            let mut outbound = hub.upgrade().ok_or(io::ErrorKind::NotConnected)?.add(peer);
+            let writing = async {
+                while let Some(frame) = outbound.recv().await {
+                    sink.send(Message::binary(frame)).await.map_err(to_io_error)?;
+                }
+                Ok(())
+            };
+            let reading = async {
+                while let Some(message) = source.next().await {
+                    match message.map_err(to_io_error)? {
+                        Message::Binary(frame) if frame.len() <= MAX_FRAME_LEN => {
+                            if inbound.send((frame.to_vec(), peer)).await.is_err() {
+                                break;
+                            }
+                        }
+                        Message::Close(_) => break,
+                        // pings are answered by tungstenite, anything else is not ours
+                        _ => {}
+                    }
+                }
+                Ok(())
+            };
+            tokio::select! {
+                result = writing => result,
+                result = reading => result,
+            }
+        }).await
+    }
+}
+
+impl StreamClientTransport {
+    // `url` is e.g. "ws://rendezvous.example:9010", received packages report the server as their source
+    pub async fn connect_websocket(url: &str) -> io::Result<Arc<Self>> {
+        let (stream, _response) = tokio_tungstenite::connect_async(url).await.map_err(to_io_error)?;
+        let (server_addr, local_addr) = match stream.get_ref() {
+            tokio_tungstenite::MaybeTlsStream::Plain(tcp_stream) => (tcp_stream.peer_addr()?, tcp_stream.local_addr()?),
+            _ => return Err(io::Error::new(io::ErrorKind::Unsupported, "only plain ws urls are supported")),
+        };
+        let (mut sink, mut source) = stream.split();
+        Ok(Self::spawn(server_addr, local_addr, |mut outbound, inbound| async move {
+            let writing = async {
+                while let Some(frame) = outbound.recv().await {
+                    sink.send(Message::binary(frame)).await.map_err(to_io_error)?;
+                }
+                sink.close().await.map_err(to_io_error)
+            };
+            let reading = async {
+                while let Some(message) = source.next().await {
+                    match message.map_err(to_io_error)? {
+                        Message::Binary(frame) => {
+                            if inbound.send(frame.to_vec()).await.is_err() {
+                                break;
+                            }
+                        }
+                        Message::Close(_) => break,
+                        _ => {}
+                    }
+                }
+                Ok(())
+            };
+            tokio::select! {
+                result = writing => result,
+                result = reading => result,
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::transport::{stream::{StreamClientTransport, StreamListenerTransport}, DatagramTransport};
+
+    #[tokio::test]
+    async fn test_websocket_stream_transport() {
+        let listener = StreamListenerTransport::bind_websocket("127.0.0.1:0".parse().unwrap()).await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let client = StreamClientTransport::connect_websocket(&url).await.unwrap();
+        client.send_to(b"hello", listener.local_addr().unwrap()).await.unwrap();
+        let mut buf = [0u8; 1500];
+        let (len, peer) = tokio::time::timeout(Duration::from_secs(1), listener.recv_from(&mut buf)).await.unwrap().unwrap();
+        assert_eq!(&buf[..len], b"hello");
+        assert_eq!(peer, client.local_addr().unwrap());
+        listener.send_to(b"ack", peer).await.unwrap();
+        let (len, from) = tokio::time::timeout(Duration::from_secs(1), client.recv_from(&mut buf)).await.unwrap().unwrap();
+        assert_eq!(&buf[..len], b"ack");
+        assert_eq!(from, listener.local_addr().unwrap());
+    }
+}
@@ -4,6 +4,19 @@ pub fn get_global_id(client_class: &str, client_instance: &str) -> String {
     format!("{}-{}", client_class, client_instance)
 }
 
+// the server keys a tenant's devices as "namespace/class-instance", the default namespace is ""
+pub fn get_namespaced_global_id(namespace: &str, client_class: &str, client_instance: &str) -> String {
+    qualify_global_id(namespace, &get_global_id(client_class, client_instance))
+}
+
+pub fn qualify_global_id(namespace: &str, global_id: &str) -> String {
+    if namespace.is_empty() {
+        global_id.to_string()
+    } else {
+        format!("{}/{}", namespace, global_id)
+    }
+}
+
 pub fn parse_ip_port(input: &str) -> Option<(IpAddr, u16)> {
     input.parse::<SocketAddr>().ok().map(|addr| (addr.ip(), addr.port()))
 }
\ No newline at end of file
@@ -0,0 +1,107 @@
+use std::time::{Duration, Instant};
+
+use futures_util::StreamExt;
+use up2p::{
+    core::request_info::RequestInfo,
+    server::{acl::{AclAction, AclConfig, AclRule}, listing::{ListingConfig, ListingRule}},
+    test_support::{start_client, test_base_info, TestServer},
+};
+
+fn rule(action: AclAction, from_class: &str, from_instance: Option<&str>, to_class: &str) -> AclRule {
+    AclRule {
+        action,
+        from_class: from_class.to_string(),
+        from_instance: from_instance.map(str::to_string),
+        to_class: to_class.to_string(),
+        to_instance: None,
+    }
+}
+
+#[tokio::test]
+async fn test_acl_denies_requests_and_relays() {
+    let acl = AclConfig {
+        default_action: AclAction::Deny,
+        rules: vec![
+            rule(AclAction::Allow, "cli", None, "sensor"),
+            rule(AclAction::Deny, "cli", Some("guest"), "*"),
+        ],
+    };
+    let server = TestServer::start_with(TestServer::builder().acl(acl)).await.unwrap();
+    let peer = start_client(test_base_info("cli", "peer1"), server.addr()).await.unwrap();
+    let guest = start_client(test_base_info("cli", "guest"), server.addr()).await.unwrap();
+    let sensor = start_client(test_base_info("sensor", "kitchen"), server.addr()).await.unwrap();
+    for client in [&peer, &guest, &sensor] {
+        client.client_hello().await.unwrap();
+    }
+    let kitchen = RequestInfo { client_class: "sensor".to_string(), client_instance: "kitchen".to_string() };
+    let peer1 = RequestInfo { client_class: "cli".to_string(), client_instance: "peer1".to_string() };
+
+    assert!(peer.client_request(kitchen.clone()).await.unwrap().is_some());
+    // denials come back right away instead of timing out
+    let asked_at = Instant::now();
+    let err = guest.client_request(kitchen).await.unwrap_err();
+    assert!(err.to_string().contains("denied"));
+    assert!(sensor.client_request(peer1).await.is_err());
+    assert!(asked_at.elapsed() < Duration::from_millis(500));
+
+    // a relay the acl denies never reaches the target
+    let (received, _) = tokio::join!(
+        tokio::time::timeout(Duration::from_millis(300), peer.pkg_recv_from()),
+        sensor.pkg_send_to(server.addr(), b"denied".to_vec(), Some(test_base_info("cli", "peer1")))
+    );
+    assert!(received.is_err());
+    assert!(server.server().metrics().render().contains("up2p_dropped_packets_total{reason=\"denied\"} 3"));
+
+    // lifting the acl applies to the next package
+    server.server().set_acl(AclConfig::default());
+    let (received, _) = tokio::join!(
+        tokio::time::timeout(Duration::from_secs(1), peer.pkg_recv_from()),
+        async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            sensor.pkg_send_to(server.addr(), b"allowed".to_vec(), Some(test_base_info("cli", "peer1"))).await.unwrap();
+        }
+    );
+    assert_eq!(received.unwrap().unwrap().1, b"allowed");
+}
+
+#[tokio::test]
+async fn test_acl_hides_status_and_presence() {
+    let acl = AclConfig {
+        default_action: AclAction::Allow,
+        rules: vec![rule(AclAction::Deny, "cli", Some("guest"), "*")],
+    };
+    let listing = ListingConfig {
+        rules: vec![ListingRule { requester_class: "cli".to_string(), client_class: "sensor".to_string() }],
+    };
+    let server = TestServer::start_with(TestServer::builder().acl(acl).listing(listing)).await.unwrap();
+    let peer = start_client(test_base_info("cli", "peer1"), server.addr()).await.unwrap();
+    let guest = start_client(test_base_info("cli", "guest"), server.addr()).await.unwrap();
+    let sensor = start_client(test_base_info("sensor", "kitchen"), server.addr()).await.unwrap();
+    for client in [&peer, &guest, &sensor] {
+        client.client_hello().await.unwrap();
+    }
+    let kitchen = RequestInfo { client_class: "sensor".to_string(), client_instance: "kitchen".to_string() };
+
+    // the guest can't tell the kitchen sensor from a device that never registered
+    assert!(peer.query_status(kitchen.clone()).await.unwrap().is_online());
+    let status = guest.query_status(kitchen.clone()).await.unwrap();
+    assert!(!status.is_online());
+    assert!(status.get_last_seen().is_none());
+    let statuses = guest.subscribe_presence(vec![kitchen.clone()], vec![]).await.unwrap();
+    assert!(statuses[0].get_last_seen().is_none());
+    // nor find it by listing its class
+    let listed = peer.list_all_devices("sensor", false).await.unwrap();
+    assert_eq!(listed.iter().map(|status| status.get_global_id()).collect::<Vec<_>>(), ["sensor-kitchen"]);
+    assert!(guest.list_all_devices("sensor", false).await.unwrap().is_empty());
+
+    // and hears nothing when it registers again, with its endpoints or otherwise
+    let mut peer_events = peer.on_presence().await;
+    let mut guest_events = guest.on_presence().await;
+    peer.subscribe_presence(vec![kitchen], vec![]).await.unwrap();
+    sensor.client_hello().await.unwrap();
+    let moved = start_client(test_base_info("sensor", "kitchen"), server.addr()).await.unwrap();
+    moved.client_hello().await.unwrap();
+    let event = tokio::time::timeout(Duration::from_secs(1), peer_events.next()).await.unwrap().unwrap();
+    assert!(!event.get_endpoint_addresses().is_empty());
+    assert!(tokio::time::timeout(Duration::from_millis(300), guest_events.next()).await.is_err());
+}
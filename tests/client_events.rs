@@ -0,0 +1,65 @@
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use up2p::{
+    client_lib::metrics::PathType,
+    core::request_info::RequestInfo,
+    test_support::{start_client, test_base_info, TestServer},
+};
+
+const RECV_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[tokio::test]
+async fn test_message_handlers() {
+    let server = TestServer::start().await.unwrap();
+    let peer1 = start_client(test_base_info("cli", "peer1"), server.addr()).await.unwrap();
+    peer1.client_hello().await.unwrap();
+    let peer2 = start_client(test_base_info("cli", "peer2"), server.addr()).await.unwrap();
+    peer2.client_hello().await.unwrap();
+
+    // nothing listens on peer1 yet, the package waits for the first handler
+    peer2.pkg_send_to(server.addr(), b"early".to_vec(), Some(test_base_info("cli", "peer1"))).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let mut first = peer1.on_message().await;
+    let mut second = peer1.on_message().await;
+    let early = tokio::time::timeout(RECV_TIMEOUT, first.next()).await.unwrap().unwrap();
+    assert_eq!(early.get_payload(), b"early");
+    assert_eq!(early.get_src().client_instance, "peer2");
+
+    // every handler and pkg_recv_from see the same package
+    let (received, _) = tokio::join!(
+        tokio::time::timeout(RECV_TIMEOUT, peer1.pkg_recv_from()),
+        async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            peer2.pkg_send_to(server.addr(), b"to all".to_vec(), Some(test_base_info("cli", "peer1"))).await.unwrap();
+        }
+    );
+    assert_eq!(received.unwrap().unwrap().1, b"to all");
+    for handler in [&mut first, &mut second] {
+        let message = tokio::time::timeout(RECV_TIMEOUT, handler.next()).await.unwrap().unwrap();
+        assert_eq!(message.get_payload(), b"to all");
+    }
+}
+
+#[tokio::test]
+async fn test_path_change_handler() {
+    let server = TestServer::start().await.unwrap();
+    let peer1 = start_client(test_base_info("cli", "peer1"), server.addr()).await.unwrap();
+    peer1.client_hello().await.unwrap();
+    let peer2 = start_client(test_base_info("cli", "peer2"), server.addr()).await.unwrap();
+    peer2.client_hello().await.unwrap();
+    let mut paths = peer2.on_path_change().await;
+
+    peer2.pkg_send_to(server.addr(), b"relayed".to_vec(), Some(test_base_info("cli", "peer1"))).await.unwrap();
+    peer2.pkg_send_to(server.addr(), b"relayed again".to_vec(), Some(test_base_info("cli", "peer1"))).await.unwrap();
+    let peer1_addr = peer2.client_request(RequestInfo { client_class: "cli".to_string(), client_instance: "peer1".to_string() }).await.unwrap().unwrap();
+    peer2.pkg_send_to(peer1_addr.parse().unwrap(), b"direct".to_vec(), Some(test_base_info("cli", "peer1"))).await.unwrap();
+
+    // only changes are reported
+    let relayed = tokio::time::timeout(RECV_TIMEOUT, paths.next()).await.unwrap().unwrap();
+    assert_eq!(relayed.get_global_id(), "cli-peer1");
+    assert_eq!(relayed.get_path_type(), PathType::Relayed);
+    let direct = tokio::time::timeout(RECV_TIMEOUT, paths.next()).await.unwrap().unwrap();
+    assert_eq!(direct.get_path_type(), PathType::Direct);
+    assert!(tokio::time::timeout(Duration::from_millis(100), paths.next()).await.is_err());
+}
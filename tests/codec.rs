@@ -0,0 +1,87 @@
+use std::{net::SocketAddr, sync::{atomic::Ordering, Arc}, time::Duration};
+
+use tokio::net::UdpSocket;
+use up2p::{
+    client_lib::app::Up2pCli,
+    core::{
+        bincodec::BinCodec,
+        codec::Codec,
+        protocol_version::{ProtocolVersion, CAP_CODEC_CBOR},
+        request_info::RequestInfo,
+        server_key::{PinnedServerKey, ServerKey},
+        uprotocol_pkg::{ClientHelloPkg, DeviceStatusPkg},
+        Up2pMessage,
+    },
+    test_support::{start_client, test_base_info, TestServer},
+};
+
+const RECV_TIMEOUT: Duration = Duration::from_secs(2);
+
+fn request(client_instance: &str) -> RequestInfo {
+    RequestInfo {
+        client_class: "cli".to_string(),
+        client_instance: client_instance.to_string(),
+    }
+}
+
+async fn relay(from: &Up2pCli, server_addr: SocketAddr, to: &Up2pCli, to_instance: &str, payload: &[u8]) -> Vec<u8> {
+    let (received, _) = tokio::join!(
+        tokio::time::timeout(RECV_TIMEOUT, to.pkg_recv_from()),
+        async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            from.pkg_send_to(server_addr, payload.to_vec(), Some(test_base_info("cli", to_instance))).await.unwrap();
+        }
+    );
+    received.unwrap().unwrap().1
+}
+
+#[tokio::test]
+async fn test_cbor_and_bincode_sessions() {
+    let server_key = ServerKey::generate();
+    let server = TestServer::start_with(TestServer::builder().codec(Codec::Cbor).server_key(server_key.clone())).await.unwrap();
+    let udp_socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+    let (mut peer1, _cancel) = Up2pCli::new(test_base_info("cli", "peer1"), udp_socket, (server.addr().ip(), server.addr().port()));
+    peer1.pin_server_key(PinnedServerKey::from_hex(&server_key.verifying_key_hex()).unwrap());
+    peer1.start().await.unwrap();
+    peer1.client_hello().await.unwrap();
+    assert_eq!(peer1.codec(), Codec::Cbor);
+    // a client without CBOR keeps to bincode on the same server
+    let udp_socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+    let (mut peer2, _cancel) = Up2pCli::new(test_base_info("cli", "peer2"), udp_socket, (server.addr().ip(), server.addr().port()));
+    let mut protocol = ProtocolVersion::CURRENT;
+    protocol.capabilities &= !CAP_CODEC_CBOR;
+    peer2.set_protocol(protocol);
+    peer2.start().await.unwrap();
+    peer2.client_hello().await.unwrap();
+    assert_eq!(peer2.codec(), Codec::Bincode);
+
+    peer2.advertise_status(DeviceStatusPkg::NAT_FULL_CONE, 0b11).await.unwrap();
+    let status = peer1.query_status(request("peer2")).await.unwrap();
+    assert_eq!(status.get_nat_type(), DeviceStatusPkg::NAT_FULL_CONE);
+    // CBOR answers are larger, the batch still has to fit its datagrams. Short ids
+    // make the answer grow fastest against the request
+    let reqs = (0..100).map(|i| RequestInfo { client_class: "c".to_string(), client_instance: i.to_string() }).collect();
+    let statuses = peer1.query_status_batch(reqs).await.unwrap();
+    assert_eq!(statuses.len(), 100);
+    assert_eq!(statuses[99].get_global_id(), "c-99");
+
+    // relayed packages are re-encoded for whoever receives them
+    assert_eq!(relay(&peer1, server.addr(), &peer2, "peer2", b"to bincode").await, b"to bincode");
+    assert_eq!(relay(&peer2, server.addr(), &peer1, "peer1", b"to cbor").await, b"to cbor");
+}
+
+#[tokio::test]
+async fn test_bincode_deployment_drops_cbor() {
+    let server = TestServer::start().await.unwrap();
+    let peer1 = start_client(test_base_info("cli", "peer1"), server.addr()).await.unwrap();
+    peer1.client_hello().await.unwrap();
+    assert_eq!(peer1.codec(), Codec::Bincode);
+
+    let hello = Up2pMessage::Hello(ClientHelloPkg::new("cli", "cbor", "test", ClientHelloPkg::MSG_HELLO)).encode_with(Codec::Cbor).unwrap();
+    let cbor = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    cbor.send_to(&hello, server.addr()).await.unwrap();
+    let mut buf = [0u8; 1500];
+    assert!(tokio::time::timeout(Duration::from_millis(300), cbor.recv_from(&mut buf)).await.is_err());
+    assert!(server.server().registry().get("cli-cbor").is_none());
+    assert_eq!(server.server().metrics().decode_failures.load(Ordering::Relaxed), 1);
+}
@@ -0,0 +1,68 @@
+use std::time::Duration;
+
+use up2p::{
+    server::listing::{ListingConfig, ListingRule},
+    test_support::{start_client, test_base_info, TestServer},
+};
+
+fn controller_may_list_sensors() -> ListingConfig {
+    ListingConfig {
+        rules: vec![ListingRule { requester_class: "controller".to_string(), client_class: "sensor".to_string() }],
+    }
+}
+
+#[tokio::test]
+async fn test_list_devices_by_class() {
+    let server = TestServer::start_with(TestServer::builder().listing(controller_may_list_sensors())).await.unwrap();
+    // more sensors than fit in one page, next to devices of another class
+    for i in 0..100 {
+        let endpoint = format!("127.0.0.1:{}", 20000 + i).parse().unwrap();
+        server.server().registry().register(&test_base_info("sensor", &format!("living-room-{:03}", i)), endpoint);
+        server.server().registry().register(&test_base_info("camera", &format!("{:03}", i)), endpoint);
+    }
+    let controller = start_client(test_base_info("controller", "main"), server.addr()).await.unwrap();
+    controller.client_hello().await.unwrap();
+
+    let (page, cursor) = controller.list_devices("sensor", false, None, 10).await.unwrap();
+    assert_eq!(page.len(), 10);
+    assert_eq!(page[0].get_global_id(), "sensor-living-room-000");
+    assert_eq!(cursor.as_deref(), Some("sensor-living-room-009"));
+    let (page, _) = controller.list_devices("sensor", false, cursor, 10).await.unwrap();
+    assert_eq!(page[0].get_global_id(), "sensor-living-room-010");
+
+    let sensors = controller.list_all_devices("sensor", false).await.unwrap();
+    let expected: Vec<String> = (0..100).map(|i| format!("sensor-living-room-{:03}", i)).collect();
+    assert_eq!(sensors.iter().map(|sensor| sensor.get_global_id()).collect::<Vec<_>>(), expected);
+    assert!(sensors.iter().all(|sensor| sensor.is_online()));
+
+    // no rule lets the controller list cameras, or a sensor list anything
+    assert!(controller.list_devices("camera", false, None, 0).await.is_err());
+    let sensor = start_client(test_base_info("sensor", "kitchen"), server.addr()).await.unwrap();
+    sensor.client_hello().await.unwrap();
+    assert!(sensor.list_devices("sensor", false, None, 0).await.is_err());
+    // allowed but empty
+    server.server().set_listing(ListingConfig {
+        rules: vec![ListingRule { requester_class: "controller".to_string(), client_class: "*".to_string() }],
+    });
+    assert!(controller.list_all_devices("thermostat", false).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_list_online_devices() {
+    let builder = TestServer::builder()
+        .listing(controller_may_list_sensors())
+        .online_timeout(Duration::from_millis(300));
+    let server = TestServer::start_with(builder).await.unwrap();
+    for i in 0..5 {
+        server.server().registry().register(&test_base_info("sensor", &format!("stale-{}", i)), "127.0.0.1:20000".parse().unwrap());
+    }
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    let sensor = start_client(test_base_info("sensor", "kitchen"), server.addr()).await.unwrap();
+    sensor.client_hello().await.unwrap();
+    let controller = start_client(test_base_info("controller", "main"), server.addr()).await.unwrap();
+    controller.client_hello().await.unwrap();
+
+    let online = controller.list_all_devices("sensor", true).await.unwrap();
+    assert_eq!(online.iter().map(|sensor| sensor.get_global_id()).collect::<Vec<_>>(), vec!["sensor-kitchen"]);
+    assert_eq!(controller.list_all_devices("sensor", false).await.unwrap().len(), 6);
+}
@@ -0,0 +1,71 @@
+use std::time::Duration;
+
+use up2p::{
+    core::{request_info::RequestInfo, uprotocol_pkg::DeviceStatusPkg},
+    test_support::{start_client, test_base_info, TestServer},
+};
+
+fn request(client_instance: &str) -> RequestInfo {
+    RequestInfo {
+        client_class: "cli".to_string(),
+        client_instance: client_instance.to_string(),
+    }
+}
+
+#[tokio::test]
+async fn test_query_advertised_status() {
+    let server = TestServer::start().await.unwrap();
+    let peer1 = start_client(test_base_info("cli", "peer1"), server.addr()).await.unwrap();
+    let peer2 = start_client(test_base_info("cli", "peer2"), server.addr()).await.unwrap();
+    peer1.client_hello().await.unwrap();
+    peer2.client_hello().await.unwrap();
+
+    let own = peer1.advertise_status(DeviceStatusPkg::NAT_PORT_RESTRICTED, 0b101).await.unwrap();
+    assert_eq!(own.get_global_id(), "cli-peer1");
+    assert!(own.is_online());
+
+    let status = peer2.query_status(request("peer1")).await.unwrap();
+    assert!(status.is_online());
+    assert_eq!(status.get_nat_type(), DeviceStatusPkg::NAT_PORT_RESTRICTED);
+    assert_eq!(status.get_capabilities(), 0b101);
+    assert!(status.get_last_seen().unwrap() < Duration::from_secs(2));
+
+    // never registered
+    let status = peer2.query_status(request("nobody")).await.unwrap();
+    assert_eq!(status, DeviceStatusPkg::unknown("cli-nobody".to_string()));
+}
+
+#[tokio::test]
+async fn test_query_status_batch() {
+    let server = TestServer::start().await.unwrap();
+    let peer1 = start_client(test_base_info("cli", "peer1"), server.addr()).await.unwrap();
+    peer1.client_hello().await.unwrap();
+
+    // more ids than fit in one request package, answered in order
+    let instances: Vec<String> = (0..100).map(|i| format!("device-{}", i)).collect();
+    let mut reqs: Vec<RequestInfo> = instances.iter().map(|instance| request(instance)).collect();
+    reqs.insert(17, request("peer1"));
+    let statuses = peer1.query_status_batch(reqs).await.unwrap();
+    assert_eq!(statuses.len(), 101);
+    assert_eq!(statuses[17].get_global_id(), "cli-peer1");
+    assert!(statuses[17].is_online());
+    assert_eq!(statuses[100].get_global_id(), "cli-device-99");
+    assert_eq!(statuses.iter().filter(|status| status.is_online()).count(), 1);
+}
+
+#[tokio::test]
+async fn test_device_goes_offline() {
+    let server = TestServer::start_with(TestServer::builder().online_timeout(Duration::from_millis(300))).await.unwrap();
+    let peer1 = start_client(test_base_info("cli", "peer1"), server.addr()).await.unwrap();
+    let peer2 = start_client(test_base_info("cli", "peer2"), server.addr()).await.unwrap();
+    peer1.client_hello().await.unwrap();
+    peer2.client_hello().await.unwrap();
+    assert!(peer2.query_status(request("peer1")).await.unwrap().is_online());
+
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let status = peer2.query_status(request("peer1")).await.unwrap();
+    assert!(!status.is_online());
+    assert!(status.get_last_seen().is_some());
+    // asking keeps peer2 itself online
+    assert!(peer1.query_status(request("peer2")).await.unwrap().is_online());
+}
@@ -0,0 +1,73 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use tokio::net::UdpSocket;
+use up2p::{
+    client_lib::app::Up2pCli,
+    core::request_info::RequestInfo,
+    test_support::{test_base_info, TestServer},
+    transport::bind_dual_stack_udp,
+};
+
+fn request(client_instance: &str) -> RequestInfo {
+    RequestInfo {
+        client_class: "cli".to_string(),
+        client_instance: client_instance.to_string(),
+    }
+}
+
+// a dual stack client registering through the server's ipv4 and ipv6 loopback addresses
+async fn start_dual_stack_client(client_instance: &str, server_port: u16) -> Up2pCli {
+    let udp_socket = Arc::new(bind_dual_stack_udp(0).unwrap());
+    let (mut client, _cancel) = Up2pCli::new(test_base_info("cli", client_instance), udp_socket, ("127.0.0.1".parse().unwrap(), server_port));
+    client.add_server_address(SocketAddr::from(([0u16, 0, 0, 0, 0, 0, 0, 1], server_port)));
+    client.start().await.unwrap();
+    client.client_hello().await.unwrap();
+    client
+}
+
+#[tokio::test]
+async fn test_dual_stack_registration_prefers_ipv6() {
+    let server = TestServer::start_with(TestServer::builder().bind_address("[::]:0".parse().unwrap())).await.unwrap();
+    let server_port = server.addr().port();
+    let peer1 = start_dual_stack_client("peer1", server_port).await;
+    let peer2 = start_dual_stack_client("peer2", server_port).await;
+    // an ipv4 only client next to them
+    let udp_socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+    let (peer3, _cancel) = Up2pCli::new(test_base_info("cli", "peer3"), udp_socket, ("127.0.0.1".parse().unwrap(), server_port));
+    peer3.start().await.unwrap();
+    peer3.client_hello().await.unwrap();
+
+    // one mapping per family, ipv4 peers show up unmapped
+    let registered = server.server().registry().lookup_all("cli-peer1");
+    assert_eq!(registered.len(), 2);
+    assert!(registered[0].is_ipv4() && registered[0].ip().is_loopback());
+    assert!(registered[1].is_ipv6() && registered[1].ip().is_loopback());
+    assert_eq!(server.server().registry().lookup_all("cli-peer3").len(), 1);
+
+    // the response carries every address, both sides have ipv6 so it comes first
+    let endpoints = peer2.client_request_all(request("peer1")).await.unwrap();
+    assert_eq!(endpoints, registered.iter().map(|endpoint| endpoint.to_string()).collect::<Vec<_>>());
+    let preferred: SocketAddr = peer2.client_request(request("peer1")).await.unwrap().unwrap().parse().unwrap();
+    assert_eq!(preferred, registered[1]);
+    let preferred: SocketAddr = peer3.client_request(request("peer1")).await.unwrap().unwrap().parse().unwrap();
+    assert_eq!(preferred, registered[0]);
+
+    // direct over ipv6
+    let (received, _) = tokio::join!(
+        tokio::time::timeout(Duration::from_secs(1), peer1.pkg_recv_from()),
+        async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            peer2.pkg_send_to(preferred, b"over ipv6".to_vec(), Some(test_base_info("cli", "peer1"))).await.unwrap();
+        }
+    );
+    assert_eq!(received.unwrap().unwrap().1, b"over ipv6");
+    // and relayed from the ipv4 only peer to the dual stack one
+    let (received, _) = tokio::join!(
+        tokio::time::timeout(Duration::from_secs(1), peer1.pkg_recv_from()),
+        async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            peer3.pkg_send_to(SocketAddr::from(([127, 0, 0, 1], server_port)), b"relayed".to_vec(), Some(test_base_info("cli", "peer1"))).await.unwrap();
+        }
+    );
+    assert_eq!(received.unwrap().unwrap().1, b"relayed");
+}
@@ -0,0 +1,37 @@
+use std::{sync::Arc, time::Duration};
+
+use up2p::{
+    core::{request_info::RequestInfo, uprotocol_pkg::BasePkg},
+    server::{auth::SharedIdentityAuth, enrollment::{EnrollmentAuth, EnrollmentConfig, EnrollmentIssuer}},
+    test_support::{start_client, test_base_info, TestServer, TEST_IDENTITY},
+};
+
+#[tokio::test]
+async fn test_enrolled_devices() {
+    let issuer = EnrollmentIssuer::generate();
+    let config = EnrollmentConfig { verifying_key: issuer.verifying_key_hex(), ..EnrollmentConfig::default() };
+    let enrollment = Arc::new(EnrollmentAuth::new(config, Arc::new(SharedIdentityAuth::new(TEST_IDENTITY))).unwrap());
+    let server = TestServer::start_with(TestServer::builder().auth(enrollment.clone())).await.unwrap();
+    let enrolled = |instance: &str| BasePkg {
+        identity: issuer.issue("cli", instance, Duration::from_secs(60)).unwrap(),
+        ..test_base_info("cli", instance)
+    };
+
+    let peer1 = start_client(enrolled("peer1"), server.addr()).await.unwrap();
+    let peer2 = start_client(enrolled("peer2"), server.addr()).await.unwrap();
+    peer1.client_hello().await.unwrap();
+    peer2.client_hello().await.unwrap();
+    let to_peer2 = RequestInfo { client_class: "cli".to_string(), client_instance: "peer2".to_string() };
+    assert!(peer1.client_request(to_peer2.clone()).await.is_ok());
+    // neither the shared identity nor someone else's token gets in
+    let shared = start_client(test_base_info("cli", "peer3"), server.addr()).await.unwrap();
+    assert!(shared.client_hello().await.is_err());
+    let borrowed = start_client(BasePkg { identity: enrolled("peer1").identity, ..test_base_info("cli", "peer3") }, server.addr()).await.unwrap();
+    assert!(borrowed.client_hello().await.is_err());
+
+    // a revoked device is cut off from its next package on, and nobody finds it anymore
+    server.server().revoke("cli-peer1").await.unwrap();
+    assert!(peer1.client_request(to_peer2).await.is_err());
+    assert!(peer2.client_hello().await.is_ok());
+    assert_eq!(server.server().registry().lookup("cli-peer1"), None);
+}
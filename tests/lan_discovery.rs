@@ -0,0 +1,63 @@
+use std::{net::{Ipv4Addr, SocketAddrV4}, sync::{Arc, Mutex}, time::Duration};
+
+use tokio::net::UdpSocket;
+use up2p::{
+    client_lib::{app::Up2pCli, discovery::DiscoveryConfig, metrics::{CliMetricsHook, PathType}},
+    core::{request_info::RequestInfo, uprotocol_pkg::BasePkg},
+    test_support::test_base_info,
+};
+
+#[derive(Default)]
+struct PathRecorder(Mutex<Vec<PathType>>);
+
+impl CliMetricsHook for PathRecorder {
+    fn on_path(&self, path_type: PathType, _target: Option<&BasePkg>) {
+        self.0.lock().unwrap().push(path_type);
+    }
+}
+
+// a client with lan discovery on loopback and no server at all
+async fn start_lan_client(base_info: BasePkg, config: &DiscoveryConfig) -> (Up2pCli, Arc<PathRecorder>) {
+    let udp_socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+    let (mut client, _cancel) = Up2pCli::new(base_info, udp_socket, (Ipv4Addr::LOCALHOST.into(), 9));
+    let paths = Arc::new(PathRecorder::default());
+    client.set_metrics_hook(paths.clone());
+    client.enable_lan_discovery(config.clone()).await.unwrap();
+    client.start().await.unwrap();
+    (client, paths)
+}
+
+#[tokio::test]
+async fn test_resolve_peer_on_the_lan() {
+    let config = DiscoveryConfig {
+        group: SocketAddrV4::new(Ipv4Addr::new(239, 255, 77, 81), 45710),
+        interface: Ipv4Addr::LOCALHOST,
+        announce_interval: Duration::from_millis(100),
+        key: "lan-key".to_string(),
+    };
+    let (peer1, _) = start_lan_client(test_base_info("cli", "peer1"), &config).await;
+    let (peer2, peer2_paths) = start_lan_client(test_base_info("cli", "peer2"), &config).await;
+    let stranger_config = DiscoveryConfig { key: "other-key".to_string(), ..config.clone() };
+    let (_stranger, _) = start_lan_client(test_base_info("cli", "stranger"), &stranger_config).await;
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    // answered locally, the server address is not even listening
+    let endpoint = peer2.client_request(RequestInfo {
+        client_class: "cli".to_string(),
+        client_instance: "peer1".to_string(),
+    }).await.unwrap().unwrap();
+    assert!(endpoint.starts_with("127.0.0.1:"));
+    assert!(peer1.lan_discovery().unwrap().resolve("cli-peer2").is_some());
+    // a different key is not trusted
+    assert_eq!(peer2.lan_discovery().unwrap().resolve("cli-stranger"), None);
+
+    let (received, _) = tokio::join!(
+        tokio::time::timeout(Duration::from_secs(1), peer1.pkg_recv_from()),
+        async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            peer2.pkg_send_to(endpoint.parse().unwrap(), b"on the lan".to_vec(), Some(test_base_info("cli", "peer1"))).await.unwrap();
+        }
+    );
+    assert_eq!(received.unwrap().unwrap().1, b"on the lan");
+    assert_eq!(*peer2_paths.0.lock().unwrap(), vec![PathType::Lan]);
+}
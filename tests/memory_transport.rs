@@ -0,0 +1,49 @@
+use std::{net::IpAddr, sync::Arc, time::Duration};
+
+use up2p::{
+    client_lib::app::Up2pCli,
+    core::request_info::RequestInfo,
+    server::{auth::SharedIdentityAuth, Up2pServer},
+    test_support::{test_base_info, TEST_IDENTITY},
+    transport::{memory::MemoryNetwork, DatagramTransport},
+};
+
+#[tokio::test]
+async fn test_client_and_server_over_memory_transport() {
+    let network = MemoryNetwork::new();
+    let server_transport = network.bind("10.0.0.1:9008".parse().unwrap()).unwrap();
+    let server_addr = server_transport.local_addr().unwrap();
+    let server = Arc::new(Up2pServer::builder()
+        .transport(server_transport)
+        .auth(Arc::new(SharedIdentityAuth::new(TEST_IDENTITY)))
+        .build().await.unwrap());
+    let running = server.clone();
+    let server_task = tokio::spawn(async move { running.run().await });
+
+    let mut clients = Vec::new();
+    for (instance, ip) in [("peer1", [10, 0, 0, 2]), ("peer2", [10, 0, 0, 3])] {
+        let transport = network.bind_any(IpAddr::from(ip)).unwrap();
+        let (client, _cancel) = Up2pCli::new(test_base_info("cli", instance), transport, (server_addr.ip(), server_addr.port()));
+        client.start().await.unwrap();
+        client.client_hello().await.unwrap();
+        clients.push(client);
+    }
+    let peer1_addr = clients[1].client_request(RequestInfo {
+        client_class: "cli".to_string(),
+        client_instance: "peer1".to_string(),
+    }).await.unwrap().unwrap();
+    assert!(peer1_addr.starts_with("10.0.0.2:"));
+
+    // direct, there is no nat on the memory network
+    let (received, _) = tokio::join!(
+        tokio::time::timeout(Duration::from_secs(1), clients[0].pkg_recv_from()),
+        async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            clients[1].pkg_send_to(peer1_addr.parse().unwrap(), b"over memory".to_vec(), Some(test_base_info("cli", "peer1"))).await.unwrap();
+        }
+    );
+    let (src, payload) = received.unwrap().unwrap();
+    assert_eq!(src, test_base_info("cli", "peer2"));
+    assert_eq!(payload, b"over memory");
+    server_task.abort();
+}
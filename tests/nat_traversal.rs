@@ -0,0 +1,130 @@
+use std::{net::{IpAddr, SocketAddr}, sync::{atomic::{AtomicU32, Ordering}, Arc}, time::Duration};
+
+use up2p::{
+    client_lib::{app::Up2pCli, metrics::CliMetricsHook},
+    core::request_info::RequestInfo,
+    test_support::{nat::{LinkConditions, NatType, SimulatedNat}, start_client, start_client_behind_nat, test_base_info, TestServer},
+};
+
+const RECV_TIMEOUT: Duration = Duration::from_millis(500);
+
+async fn request_endpoint(client: &Up2pCli, client_instance: &str) -> SocketAddr {
+    client.client_request(RequestInfo {
+        client_class: "cli".to_string(),
+        client_instance: client_instance.to_string(),
+    }).await.unwrap().unwrap().parse().unwrap()
+}
+
+// send `payload` from `from` to `to_addr` and return what `to` received, if anything
+async fn exchange(from: &Up2pCli, to_addr: SocketAddr, to: &Up2pCli, to_instance: &str, payload: &[u8]) -> Option<Vec<u8>> {
+    let (received, _) = tokio::join!(
+        tokio::time::timeout(RECV_TIMEOUT, to.pkg_recv_from()),
+        async {
+            // let the receiver subscribe first
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            from.pkg_send_to(to_addr, payload.to_vec(), Some(test_base_info("cli", to_instance))).await.unwrap();
+        }
+    );
+    received.ok().map(|received| received.unwrap().1)
+}
+
+fn nat(nat_type: NatType, last_octet: u8) -> SimulatedNat {
+    SimulatedNat::new(nat_type, IpAddr::from([127, 0, 0, last_octet]), LinkConditions::default())
+}
+
+#[tokio::test]
+async fn test_register_and_request_endpoint() {
+    let server = TestServer::start().await.unwrap();
+    let peer1 = start_client(test_base_info("cli", "peer1"), server.addr()).await.unwrap();
+    let peer2 = start_client(test_base_info("cli", "peer2"), server.addr()).await.unwrap();
+    peer1.client_hello().await.unwrap();
+    peer2.client_hello().await.unwrap();
+    assert_eq!(server.server().registry().len(), 2);
+    let endpoint = request_endpoint(&peer2, "peer1").await;
+    assert_eq!(Some(endpoint), server.server().registry().lookup("cli-peer1"));
+}
+
+#[tokio::test]
+async fn test_full_cone_accepts_unsolicited_peer() {
+    let server = TestServer::start().await.unwrap();
+    let (nat1, nat2) = (nat(NatType::FullCone, 2), nat(NatType::FullCone, 3));
+    let (peer1, _) = start_client_behind_nat(test_base_info("cli", "peer1"), server.addr(), &nat1).await.unwrap();
+    let (peer2, _) = start_client_behind_nat(test_base_info("cli", "peer2"), server.addr(), &nat2).await.unwrap();
+    peer1.client_hello().await.unwrap();
+    peer2.client_hello().await.unwrap();
+    let peer1_public = request_endpoint(&peer2, "peer1").await;
+    assert_eq!(peer1_public.ip(), IpAddr::from([127, 0, 0, 2]));
+    let received = exchange(&peer2, nat2.alias(peer1_public).unwrap(), &peer1, "peer1", b"hello").await;
+    assert_eq!(received.as_deref(), Some(&b"hello"[..]));
+}
+
+#[tokio::test]
+async fn test_hole_punching_port_restricted() {
+    let server = TestServer::start().await.unwrap();
+    let (nat1, nat2) = (nat(NatType::PortRestricted, 4), nat(NatType::PortRestricted, 5));
+    let (peer1, _) = start_client_behind_nat(test_base_info("cli", "peer1"), server.addr(), &nat1).await.unwrap();
+    let (peer2, _) = start_client_behind_nat(test_base_info("cli", "peer2"), server.addr(), &nat2).await.unwrap();
+    peer1.client_hello().await.unwrap();
+    peer2.client_hello().await.unwrap();
+    let peer1_public = request_endpoint(&peer2, "peer1").await;
+    let peer2_public = request_endpoint(&peer1, "peer2").await;
+    let (to_peer1, to_peer2) = (nat2.alias(peer1_public).unwrap(), nat1.alias(peer2_public).unwrap());
+    // nobody punched yet, the first packet is filtered by nat1
+    assert_eq!(exchange(&peer2, to_peer1, &peer1, "peer1", b"early").await, None);
+    // peer2 has now sent to peer1, so peer1's packet opens the hole in both directions
+    assert_eq!(exchange(&peer1, to_peer2, &peer2, "peer2", b"punch").await.as_deref(), Some(&b"punch"[..]));
+    assert_eq!(exchange(&peer2, to_peer1, &peer1, "peer1", b"direct").await.as_deref(), Some(&b"direct"[..]));
+}
+
+#[tokio::test]
+async fn test_symmetric_nat_falls_back_to_relay() {
+    let server = TestServer::start().await.unwrap();
+    let (nat1, nat2) = (nat(NatType::Symmetric, 6), nat(NatType::Symmetric, 7));
+    let (peer1, _) = start_client_behind_nat(test_base_info("cli", "peer1"), server.addr(), &nat1).await.unwrap();
+    let (peer2, _) = start_client_behind_nat(test_base_info("cli", "peer2"), server.addr(), &nat2).await.unwrap();
+    peer1.client_hello().await.unwrap();
+    peer2.client_hello().await.unwrap();
+    let peer1_public = request_endpoint(&peer2, "peer1").await;
+    let peer2_public = request_endpoint(&peer1, "peer2").await;
+    let (to_peer1, to_peer2) = (nat2.alias(peer1_public).unwrap(), nat1.alias(peer2_public).unwrap());
+    // each side uses a fresh mapping per destination, so punching never lines up
+    assert_eq!(exchange(&peer1, to_peer2, &peer2, "peer2", b"punch").await, None);
+    assert_eq!(exchange(&peer2, to_peer1, &peer1, "peer1", b"punch").await, None);
+    // the server relays through the mappings it already knows
+    let server_alias = nat2.alias(server.addr()).unwrap();
+    assert_eq!(exchange(&peer2, server_alias, &peer1, "peer1", b"relayed").await.as_deref(), Some(&b"relayed"[..]));
+}
+
+#[derive(Default)]
+struct RetransmissionCounter(AtomicU32);
+
+impl CliMetricsHook for RetransmissionCounter {
+    fn on_retransmission(&self, _event_type: u8, _attempt: u32) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[tokio::test]
+async fn test_hello_is_retransmitted_on_loss() {
+    let server = TestServer::start().await.unwrap();
+    let nat1 = nat(NatType::PortRestricted, 8);
+    let server_alias = nat1.alias(server.addr()).unwrap();
+    let udp_socket = Arc::new(tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap());
+    let (mut peer1, _cancel) = Up2pCli::new(test_base_info("cli", "peer1"), udp_socket, (server_alias.ip(), server_alias.port()));
+    let counter = Arc::new(RetransmissionCounter::default());
+    peer1.set_metrics_hook(counter.clone());
+    peer1.start().await.unwrap();
+    nat1.drop_next(1);
+    peer1.client_hello().await.unwrap();
+    assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+    assert!(server.server().registry().lookup("cli-peer1").is_some());
+}
+
+#[tokio::test]
+async fn test_hello_fails_on_a_dead_link() {
+    let server = TestServer::start().await.unwrap();
+    let nat1 = SimulatedNat::new(NatType::FullCone, IpAddr::from([127, 0, 0, 9]), LinkConditions { loss: 1.0, ..LinkConditions::default() });
+    let (peer1, _) = start_client_behind_nat(test_base_info("cli", "peer1"), server.addr(), &nat1).await.unwrap();
+    assert!(peer1.client_hello().await.is_err());
+    assert!(server.server().registry().is_empty());
+}
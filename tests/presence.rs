@@ -0,0 +1,58 @@
+use std::{sync::Arc, time::Duration};
+
+use futures_util::StreamExt;
+use tokio::net::UdpSocket;
+use up2p::{
+    client_lib::app::Up2pCli,
+    core::{request_info::RequestInfo, uprotocol_pkg::PresencePkg},
+    server::listing::{ListingConfig, ListingRule},
+    test_support::{start_client, test_base_info, TestServer},
+};
+
+#[tokio::test]
+async fn test_presence_events() {
+    let listing = ListingConfig {
+        rules: vec![ListingRule { requester_class: "controller".to_string(), client_class: "sensor".to_string() }],
+    };
+    let builder = TestServer::builder()
+        .listing(listing)
+        .online_timeout(Duration::from_millis(400));
+    let server = TestServer::start_with(builder).await.unwrap();
+    let udp_socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+    let (mut controller, _cancel) = Up2pCli::new(test_base_info("controller", "main"), udp_socket, (server.addr().ip(), server.addr().port()));
+    controller.set_keepalive_interval(Duration::from_millis(100));
+    controller.start().await.unwrap();
+    controller.client_hello().await.unwrap();
+    let mut events = controller.on_presence().await;
+    let peer1 = RequestInfo { client_class: "cli".to_string(), client_instance: "peer1".to_string() };
+    let statuses = controller.subscribe_presence(vec![peer1], vec!["sensor".to_string()]).await.unwrap();
+    assert_eq!(statuses.len(), 1);
+    assert!(!statuses[0].is_online());
+    // no rule for cameras
+    assert!(controller.subscribe_presence(vec![], vec!["camera".to_string()]).await.is_err());
+    controller.subscribe_presence(vec![], vec!["sensor".to_string()]).await.unwrap();
+
+    let sensor = start_client(test_base_info("sensor", "kitchen"), server.addr()).await.unwrap();
+    sensor.client_hello().await.unwrap();
+    let event = tokio::time::timeout(Duration::from_secs(1), events.next()).await.unwrap().unwrap();
+    assert_eq!(event.get_event(), PresencePkg::EVENT_ONLINE);
+    assert_eq!(event.get_global_id(), "sensor-kitchen");
+    assert_eq!(event.get_client_class(), "sensor");
+    assert!(event.get_status().is_online());
+
+    // the same device registering from a new socket
+    let udp_socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+    let moved_addr = udp_socket.local_addr().unwrap();
+    let (moved, _cancel) = Up2pCli::new(test_base_info("sensor", "kitchen"), udp_socket, (server.addr().ip(), server.addr().port()));
+    moved.start().await.unwrap();
+    moved.client_hello().await.unwrap();
+    let event = tokio::time::timeout(Duration::from_secs(1), events.next()).await.unwrap().unwrap();
+    assert_eq!(event.get_event(), PresencePkg::EVENT_ENDPOINT_CHANGED);
+    assert_eq!(event.get_endpoint_addresses(), vec![moved_addr.to_string()]);
+
+    // the sensor goes quiet, the controller's stream keeps it registered
+    let offline = tokio::time::timeout(Duration::from_secs(2), events.next()).await.unwrap().unwrap();
+    assert_eq!(offline.get_event(), PresencePkg::EVENT_OFFLINE);
+    assert_eq!(offline.get_global_id(), "sensor-kitchen");
+    assert!(!offline.get_status().is_online());
+}
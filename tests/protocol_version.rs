@@ -0,0 +1,105 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use tokio::net::UdpSocket;
+use up2p::{
+    client_lib::app::Up2pCli,
+    core::{
+        codec::Codec,
+        protocol_version::{ProtocolVersion, CAP_PRESENCE, CAP_SIGNED, PROTOCOL_VERSION},
+        request_info::RequestInfo,
+        server_key::ServerKey,
+        wire,
+        BaseUp2pProtocol,
+    },
+    test_support::{start_client, test_base_info, TestServer},
+};
+
+// packages of cli-legacy as the release before the frame encodes them, identity "test_identity"
+const LEGACY_HELLO: [u8; 29] = [26, 1, 26, 3, 99, 108, 105, 6, 108, 101, 103, 97, 99, 121, 13, 116, 101, 115, 116, 95, 105, 100, 101, 110, 116, 105, 116, 121, 1];
+// the endpoint of cli-peer1
+const LEGACY_REQUEST: [u8; 40] = [37, 5, 37, 3, 99, 108, 105, 6, 108, 101, 103, 97, 99, 121, 13, 116, 101, 115, 116, 95, 105, 100, 101, 110, 116, 105, 116, 121, 1, 0, 9, 99, 108, 105, 45, 112, 101, 101, 114, 49];
+// "hi" to cli-peer1
+const LEGACY_EXCHANGE: [u8; 56] = [53, 7, 53, 3, 99, 108, 105, 6, 108, 101, 103, 97, 99, 121, 13, 116, 101, 115, 116, 95, 105, 100, 101, 110, 116, 105, 116, 121, 2, 104, 105, 1, 3, 99, 108, 105, 5, 112, 101, 101, 114, 49, 13, 116, 101, 115, 116, 95, 105, 100, 101, 110, 116, 105, 116, 121];
+
+// the type and payload of the next package, which must be in the legacy frame
+async fn legacy_recv(legacy: &UdpSocket) -> (u8, Vec<u8>) {
+    let mut buf = [0u8; 1500];
+    let (len, _) = tokio::time::timeout(Duration::from_secs(1), legacy.recv_from(&mut buf)).await.unwrap().unwrap();
+    let (codec, package_type, payload) = wire::decode_frame(&buf[..len]).unwrap();
+    assert_eq!(codec, Codec::Legacy);
+    (package_type, payload.to_vec())
+}
+
+#[tokio::test]
+async fn test_hello_negotiates_protocol() {
+    let server = TestServer::start().await.unwrap();
+    let peer1 = start_client(test_base_info("cli", "peer1"), server.addr()).await.unwrap();
+    assert_eq!(peer1.negotiated_protocol(), None);
+    peer1.client_hello().await.unwrap();
+    let negotiated = peer1.negotiated_protocol().unwrap();
+    assert_eq!(negotiated.version, PROTOCOL_VERSION);
+    assert!(negotiated.supports(CAP_PRESENCE));
+    // this server has no key to sign with
+    assert!(!negotiated.supports(CAP_SIGNED));
+    assert_eq!(server.server().registry().get("cli-peer1").unwrap().protocol, negotiated);
+
+    let signing = TestServer::start_with(TestServer::builder().server_key(ServerKey::generate())).await.unwrap();
+    let peer2 = start_client(test_base_info("cli", "peer2"), signing.addr()).await.unwrap();
+    peer2.client_hello().await.unwrap();
+    assert!(peer2.negotiated_protocol().unwrap().supports(CAP_SIGNED));
+}
+
+#[tokio::test]
+async fn test_older_clients_interoperate() {
+    let server = TestServer::start().await.unwrap();
+    // a hello from before negotiation, without version fields
+    let legacy = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    legacy.send_to(&LEGACY_HELLO, server.addr()).await.unwrap();
+    assert_eq!(legacy_recv(&legacy).await.0, BaseUp2pProtocol::TYPE_HELLO_ACK);
+    assert_eq!(server.server().registry().get("cli-legacy").unwrap().protocol, ProtocolVersion::LEGACY);
+
+    // features the legacy device lacks are refused, not attempted
+    let peer1 = start_client(test_base_info("cli", "peer1"), server.addr()).await.unwrap();
+    peer1.client_hello().await.unwrap();
+    let err = peer1.tcp_punch(RequestInfo { client_class: "cli".to_string(), client_instance: "legacy".to_string() }).await.unwrap_err();
+    assert!(err.to_string().contains("does not support tcp punch"), "{}", err);
+
+    // a client of an older release keeps to what it announced
+    let udp_socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+    let (mut older, _cancel) = Up2pCli::new(test_base_info("cli", "older"), udp_socket, (server.addr().ip(), server.addr().port()));
+    older.set_protocol(ProtocolVersion::LEGACY);
+    older.start().await.unwrap();
+    older.client_hello().await.unwrap();
+    assert_eq!(older.negotiated_protocol(), Some(ProtocolVersion::LEGACY));
+    assert!(older.subscribe_presence(vec![], vec!["sensor".to_string()]).await.is_err());
+}
+
+#[tokio::test]
+async fn test_legacy_framing() {
+    let server = TestServer::start_with(TestServer::builder().server_key(ServerKey::generate())).await.unwrap();
+    let peer1 = start_client(test_base_info("cli", "peer1"), server.addr()).await.unwrap();
+    peer1.client_hello().await.unwrap();
+    let legacy = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+    // answered in its frame and unsigned, that release has no SIGNED
+    legacy.send_to(&LEGACY_HELLO, server.addr()).await.unwrap();
+    assert_eq!(legacy_recv(&legacy).await.0, BaseUp2pProtocol::TYPE_HELLO_ACK);
+    assert_eq!(server.server().registry().get("cli-legacy").unwrap().protocol, ProtocolVersion::LEGACY);
+
+    // the endpoint leads the answer, the release reads no further
+    legacy.send_to(&LEGACY_REQUEST, server.addr()).await.unwrap();
+    let (package_type, payload) = legacy_recv(&legacy).await;
+    assert_eq!(package_type, BaseUp2pProtocol::TYPE_REQUEST_ACK);
+    let (endpoint, _): (String, usize) = bincode::decode_from_slice(&payload, up2p::get_binencode_config()).unwrap();
+    assert_eq!(endpoint.parse::<SocketAddr>().unwrap(), server.server().registry().lookup("cli-peer1").unwrap());
+
+    // relayed both ways
+    legacy.send_to(&LEGACY_EXCHANGE, server.addr()).await.unwrap();
+    let (src, payload) = tokio::time::timeout(Duration::from_secs(1), peer1.pkg_recv_from()).await.unwrap().unwrap();
+    assert_eq!((src.client_instance.as_str(), payload.as_slice()), ("legacy", &b"hi"[..]));
+    peer1.pkg_send_to(server.addr(), b"hello".to_vec(), Some(test_base_info("cli", "legacy"))).await.unwrap();
+    let (package_type, payload) = legacy_recv(&legacy).await;
+    assert_eq!(package_type, BaseUp2pProtocol::TYPE_PKG_EXCHANGE);
+    let ((_, src_instance, _, exchanged), _): ((String, String, String, Vec<u8>), usize) = bincode::decode_from_slice(&payload, up2p::get_binencode_config()).unwrap();
+    assert_eq!((src_instance.as_str(), exchanged.as_slice()), ("peer1", &b"hello"[..]));
+}
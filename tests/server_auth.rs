@@ -0,0 +1,184 @@
+use std::{sync::{atomic::{AtomicU8, Ordering}, Arc}, time::Duration};
+
+use tokio::net::UdpSocket;
+use up2p::{
+    client_lib::app::Up2pCli,
+    core::{bincodec::BinCodec, protocol_version::ProtocolVersion, request_info::RequestInfo, server_key::{PinnedServerKey, ServerKey}, uprotocol_pkg::{BasePkg, ClientHelloPkg, HelloAckPkg, PeerExchangePkg}, Up2pMessage},
+    server::{auth::SharedIdentityAuth, rate_limit::RateLimitConfig},
+    test_support::{start_client, test_base_info, TestServer, TEST_IDENTITY},
+};
+
+const SPOOFED: u8 = 0;
+const UNSIGNED: u8 = 1;
+const SIGNED: u8 = 2;
+const REPLAYED: u8 = 3;
+
+#[tokio::test]
+async fn test_signed_server_responses() {
+    let server_key = ServerKey::generate();
+    let server = TestServer::start_with(TestServer::builder().server_key(server_key.clone())).await.unwrap();
+    let mut clients = Vec::new();
+    for instance in ["peer1", "peer2"] {
+        let udp_socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let (mut client, _cancel) = Up2pCli::new(test_base_info("cli", instance), udp_socket, (server.addr().ip(), server.addr().port()));
+        client.pin_server_key(PinnedServerKey::from_hex(&server_key.verifying_key_hex()).unwrap());
+        client.start().await.unwrap();
+        client.client_hello().await.unwrap();
+        clients.push(client);
+    }
+    let peer2 = RequestInfo { client_class: "cli".to_string(), client_instance: "peer2".to_string() };
+    assert!(clients[0].client_request(peer2).await.is_ok());
+    // clients without a pinned key still talk to a signing server
+    let unpinned = start_client(test_base_info("cli", "peer3"), server.addr()).await.unwrap();
+    unpinned.client_hello().await.unwrap();
+
+    let udp_socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+    let (mut pinned_elsewhere, _cancel) = Up2pCli::new(test_base_info("cli", "peer4"), udp_socket, (server.addr().ip(), server.addr().port()));
+    pinned_elsewhere.pin_server_key(PinnedServerKey::from_hex(&ServerKey::generate().verifying_key_hex()).unwrap());
+    pinned_elsewhere.start().await.unwrap();
+    assert!(pinned_elsewhere.client_hello().await.is_err());
+}
+
+#[tokio::test]
+async fn test_spoofed_and_unsigned_answers_are_dropped() {
+    let server_key = ServerKey::generate();
+    let fake_server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let server_addr = fake_server.local_addr().unwrap();
+    let spoofer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mode = Arc::new(AtomicU8::new(SPOOFED));
+    let answer_mode = mode.clone();
+    let signing_key = server_key.clone();
+    tokio::spawn(async move {
+        let mut buf = [0u8; 1500];
+        let mut recorded = Vec::new();
+        loop {
+            let (len, client_addr) = fake_server.recv_from(&mut buf).await.unwrap();
+            let Ok(Up2pMessage::Hello(hello)) = Up2pMessage::decode_from(&buf[..len]) else {
+                continue;
+            };
+            let hello_ack = Up2pMessage::HelloAck(HelloAckPkg::new(ProtocolVersion::CURRENT).with_nonce(hello.get_nonce())).encode_to_vec().unwrap();
+            match answer_mode.load(Ordering::Relaxed) {
+                SPOOFED => spoofer.send_to(&hello_ack, client_addr).await.unwrap(),
+                UNSIGNED => fake_server.send_to(&hello_ack, client_addr).await.unwrap(),
+                SIGNED => {
+                    recorded = signing_key.sign(&hello_ack).unwrap();
+                    fake_server.send_to(&recorded, client_addr).await.unwrap()
+                }
+                _ => fake_server.send_to(&recorded, client_addr).await.unwrap(),
+            };
+        }
+    });
+
+    // answers from anyone but the server never count
+    let unpinned = start_client(test_base_info("cli", "peer1"), server_addr).await.unwrap();
+    assert!(unpinned.client_hello().await.is_err());
+    mode.store(UNSIGNED, Ordering::Relaxed);
+    unpinned.client_hello().await.unwrap();
+
+    // once pinned they must carry the server's signature too
+    let udp_socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+    let (mut pinned, _cancel) = Up2pCli::new(test_base_info("cli", "peer2"), udp_socket, (server_addr.ip(), server_addr.port()));
+    pinned.pin_server_key(PinnedServerKey::from_hex(&server_key.verifying_key_hex()).unwrap());
+    pinned.start().await.unwrap();
+    assert!(pinned.client_hello().await.is_err());
+    mode.store(SIGNED, Ordering::Relaxed);
+    pinned.client_hello().await.unwrap();
+
+    // a signed answer recorded earlier does not answer a new hello
+    mode.store(REPLAYED, Ordering::Relaxed);
+    assert!(pinned.client_hello().await.is_err());
+}
+
+#[tokio::test]
+async fn test_spoofed_packages_spend_no_device_budget() {
+    let rate_limit = RateLimitConfig { per_ip_rate: 0.0, per_device_rate: 0.001, per_device_burst: 2.0, ..RateLimitConfig::default() };
+    let server = TestServer::start_with(TestServer::builder().rate_limit(rate_limit)).await.unwrap();
+    // hellos naming peer1 without its identity
+    let spoofer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let spoofed = Up2pMessage::Hello(ClientHelloPkg::new("cli", "peer1", "wrong", ClientHelloPkg::MSG_HELLO)).encode_to_vec().unwrap();
+    for _ in 0..10 {
+        spoofer.send_to(&spoofed, server.addr()).await.unwrap();
+    }
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let peer1 = start_client(test_base_info("cli", "peer1"), server.addr()).await.unwrap();
+    peer1.client_hello().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_identity_only_in_hello() {
+    let server = TestServer::start().await.unwrap();
+    let peer2 = start_client(test_base_info("cli", "peer2"), server.addr()).await.unwrap();
+    peer2.client_hello().await.unwrap();
+    // an older client, sending its identity with every package
+    let old_client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let hello = Up2pMessage::Hello(ClientHelloPkg::new("cli", "old", TEST_IDENTITY, ClientHelloPkg::MSG_HELLO)).encode_to_vec().unwrap();
+    old_client.send_to(&hello, server.addr()).await.unwrap();
+    old_client.recv_from(&mut [0u8; 1500]).await.unwrap();
+    let relayed = |payload: &[u8], identity: &str| Up2pMessage::PkgExchange(
+        PeerExchangePkg::new(BasePkg { identity: identity.to_string(), ..test_base_info("cli", "old") }, payload.to_vec(), Some(test_base_info("cli", "peer2")))
+    ).encode_to_vec().unwrap();
+
+    // without its identity or a session nothing speaks for the device
+    let spoofer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    spoofer.send_to(&relayed(b"spoofed", ""), server.addr()).await.unwrap();
+    old_client.send_to(&relayed(b"old", TEST_IDENTITY), server.addr()).await.unwrap();
+    let (src, payload) = tokio::time::timeout(Duration::from_secs(1), peer2.pkg_recv_from()).await.unwrap().unwrap();
+    assert_eq!(payload, b"old");
+    // the server relays no identity
+    assert_eq!(src, test_base_info("cli", "old"));
+    assert!(src.identity.is_empty());
+}
+
+#[tokio::test]
+async fn test_session_after_hello() {
+    let server = TestServer::start().await.unwrap();
+    let peer2 = start_client(test_base_info("cli", "peer2"), server.addr()).await.unwrap();
+    peer2.client_hello().await.unwrap();
+    let device = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let hello = Up2pMessage::Hello(
+        ClientHelloPkg::new("cli", "peer1", TEST_IDENTITY, ClientHelloPkg::MSG_HELLO).with_protocol(ProtocolVersion::CURRENT).with_nonce(1)
+    ).encode_to_vec().unwrap();
+    device.send_to(&hello, server.addr()).await.unwrap();
+    let mut buf = [0u8; 1500];
+    let (len, _) = device.recv_from(&mut buf).await.unwrap();
+    let Up2pMessage::HelloAck(hello_ack) = Up2pMessage::decode_from(&buf[..len]).unwrap() else {
+        panic!("expected a HELLO_ACK");
+    };
+    let session = hello_ack.get_session();
+    assert!(session.starts_with("ses."));
+    let relayed = |payload: &[u8], identity: &str| Up2pMessage::PkgExchange(
+        PeerExchangePkg::new(BasePkg { identity: identity.to_string(), ..test_base_info("cli", "peer1") }, payload.to_vec(), Some(test_base_info("cli", "peer2")))
+    ).encode_to_vec().unwrap();
+
+    // even from the registered endpoint, only the session speaks for the device
+    let wrong_session = format!("ses.{}", "0".repeat(32));
+    device.send_to(&relayed(b"no session", ""), server.addr()).await.unwrap();
+    device.send_to(&relayed(b"wrong session", &wrong_session), server.addr()).await.unwrap();
+    device.send_to(&relayed(b"session", &session), server.addr()).await.unwrap();
+    let (src, payload) = tokio::time::timeout(Duration::from_secs(1), peer2.pkg_recv_from()).await.unwrap().unwrap();
+    assert_eq!(payload, b"session");
+    assert!(src.identity.is_empty());
+    // and not from another endpoint
+    let spoofer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    spoofer.send_to(&relayed(b"spoofed", &session), server.addr()).await.unwrap();
+    assert!(tokio::time::timeout(Duration::from_millis(300), peer2.pkg_recv_from()).await.is_err());
+}
+
+#[tokio::test]
+async fn test_reloaded_identity_evicts_devices() {
+    let auth = Arc::new(SharedIdentityAuth::new(TEST_IDENTITY));
+    let server = TestServer::start_with(TestServer::builder().auth(auth.clone())).await.unwrap();
+    let peer1 = start_client(test_base_info("cli", "peer1"), server.addr()).await.unwrap();
+    let peer2 = start_client(BasePkg { identity: "rotated".to_string(), ..test_base_info("cli", "peer2") }, server.addr()).await.unwrap();
+    peer1.client_hello().await.unwrap();
+    auth.set_identity("rotated");
+    peer2.client_hello().await.unwrap();
+
+    // the session peer1 got with the old identity ends, peer2's hello verifies still
+    server.server().evict_unverified().await;
+    assert_eq!(server.server().registry().lookup("cli-peer1"), None);
+    assert!(server.server().registry().lookup("cli-peer2").is_some());
+    let to_peer2 = RequestInfo { client_class: "cli".to_string(), client_instance: "peer2".to_string() };
+    assert!(peer1.client_request(to_peer2).await.is_err());
+}
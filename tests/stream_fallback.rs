@@ -0,0 +1,92 @@
+use std::{net::{IpAddr, SocketAddr}, sync::Arc, time::Duration};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use up2p::{
+    client_lib::app::Up2pCli,
+    core::request_info::RequestInfo,
+    test_support::{start_client, test_base_info, TestServer},
+    transport::{memory::MemoryNetwork, stream::StreamClientTransport, DatagramTransport},
+};
+
+const RECV_TIMEOUT: Duration = Duration::from_secs(1);
+
+// a client whose udp never reaches the server, only `fallback` does
+async fn start_client_without_udp(client_instance: &str, server_addr: SocketAddr, fallback: Arc<StreamClientTransport>) -> Up2pCli {
+    let network = MemoryNetwork::new();
+    let blackhole = network.bind_any(IpAddr::from([10, 0, 0, 2])).unwrap();
+    let (mut client, _cancel) = Up2pCli::new(test_base_info("cli", client_instance), blackhole, (server_addr.ip(), server_addr.port()));
+    client.set_fallback_transport(fallback);
+    client.start().await.unwrap();
+    client
+}
+
+async fn relay(from: &Up2pCli, server_addr: SocketAddr, to: &Up2pCli, to_instance: &str, payload: &[u8]) -> Vec<u8> {
+    let (received, _) = tokio::join!(
+        tokio::time::timeout(RECV_TIMEOUT, to.pkg_recv_from()),
+        async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            from.pkg_send_to(server_addr, payload.to_vec(), Some(test_base_info("cli", to_instance))).await.unwrap();
+        }
+    );
+    received.unwrap().unwrap().1
+}
+
+#[tokio::test]
+async fn test_register_and_relay_over_tcp_and_websocket() {
+    let server = TestServer::start_with(TestServer::builder()
+        .tcp_address("127.0.0.1:0".parse().unwrap())
+        .websocket_address("127.0.0.1:0".parse().unwrap())
+    ).await.unwrap();
+    let tcp = StreamClientTransport::connect_tcp(server.server().tcp_local_addr().unwrap()).await.unwrap();
+    let websocket = StreamClientTransport::connect_websocket(
+        &format!("ws://{}", server.server().websocket_local_addr().unwrap())
+    ).await.unwrap();
+    let (tcp_local, websocket_local) = (tcp.local_addr().unwrap(), websocket.local_addr().unwrap());
+    let tcp_peer = start_client_without_udp("tcp_peer", server.addr(), tcp).await;
+    let websocket_peer = start_client_without_udp("websocket_peer", server.addr(), websocket).await;
+    let udp_peer = start_client(test_base_info("cli", "udp_peer"), server.addr()).await.unwrap();
+
+    // the hello over udp times out, then goes through the fallback
+    let (tcp_hello, websocket_hello, udp_hello) = tokio::join!(tcp_peer.client_hello(), websocket_peer.client_hello(), udp_peer.client_hello());
+    tcp_hello.unwrap();
+    websocket_hello.unwrap();
+    udp_hello.unwrap();
+    assert!(tcp_peer.is_using_fallback());
+    assert!(websocket_peer.is_using_fallback());
+    assert!(!udp_peer.is_using_fallback());
+    assert_eq!(server.server().registry().lookup("cli-tcp_peer"), Some(tcp_local));
+    assert_eq!(server.server().registry().lookup("cli-websocket_peer"), Some(websocket_local));
+
+    // the server answers each peer on the transport it registered with
+    assert_eq!(relay(&udp_peer, server.addr(), &tcp_peer, "tcp_peer", b"udp to tcp").await, b"udp to tcp");
+    assert_eq!(relay(&tcp_peer, server.addr(), &websocket_peer, "websocket_peer", b"tcp to ws").await, b"tcp to ws");
+    assert_eq!(relay(&websocket_peer, server.addr(), &udp_peer, "udp_peer", b"ws to udp").await, b"ws to udp");
+}
+
+#[tokio::test]
+async fn test_tcp_hole_punching() {
+    let server = TestServer::start_with(TestServer::builder().tcp_address("127.0.0.1:0".parse().unwrap())).await.unwrap();
+    let mut peers = Vec::new();
+    for client_instance in ["peer1", "peer2"] {
+        // tcp only peers, the server connection is their main transport
+        let tcp = StreamClientTransport::connect_tcp(server.server().tcp_local_addr().unwrap()).await.unwrap();
+        let (client, _cancel) = Up2pCli::new(test_base_info("cli", client_instance), tcp, (server.addr().ip(), server.addr().port()));
+        client.start().await.unwrap();
+        client.client_hello().await.unwrap();
+        peers.push(client);
+    }
+    let (punched, accepted) = tokio::join!(
+        peers[0].tcp_punch(RequestInfo { client_class: "cli".to_string(), client_instance: "peer2".to_string() }),
+        peers[1].accept_tcp_punch()
+    );
+    let mut stream1 = punched.unwrap();
+    let (peer_global_id, mut stream2) = accepted.unwrap();
+    assert_eq!(peer_global_id, "cli-peer1");
+    // one connection between the endpoints the server registered
+    assert_eq!(Some(stream1.local_addr().unwrap()), server.server().registry().lookup("cli-peer1"));
+    assert_eq!(stream1.peer_addr().unwrap(), stream2.local_addr().unwrap());
+    stream1.write_all(b"over tcp").await.unwrap();
+    let mut buf = [0u8; 8];
+    stream2.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"over tcp");
+}
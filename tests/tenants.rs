@@ -0,0 +1,85 @@
+use std::time::{Duration, Instant};
+
+use up2p::{
+    core::{request_info::RequestInfo, uprotocol_pkg::BasePkg},
+    server::{listing::{ListingConfig, ListingRule}, tenant::TenantConfig},
+    test_support::{start_client, test_base_info, TestServer},
+};
+
+fn tenant(namespace: &str, max_devices: Option<usize>) -> TenantConfig {
+    TenantConfig {
+        namespace: namespace.to_string(),
+        identity: format!("{}-secret", namespace),
+        max_devices,
+        rate: 0.0,
+        burst: 0.0,
+    }
+}
+
+fn tenant_device(namespace: &str, client_class: &str, client_instance: &str) -> BasePkg {
+    BasePkg {
+        namespace: namespace.to_string(),
+        identity: format!("{}-secret", namespace),
+        ..test_base_info(client_class, client_instance)
+    }
+}
+
+#[tokio::test]
+async fn test_tenants_are_isolated() {
+    let listing = ListingConfig {
+        rules: vec![ListingRule { requester_class: "*".to_string(), client_class: "*".to_string() }],
+    };
+    let builder = TestServer::builder().listing(listing).tenants(vec![tenant("acme", None), tenant("globex", None)]);
+    let server = TestServer::start_with(builder).await.unwrap();
+    // both tenants use the same class and instance names
+    let acme_peer = start_client(tenant_device("acme", "cli", "peer1"), server.addr()).await.unwrap();
+    let acme_sensor = start_client(tenant_device("acme", "sensor", "kitchen"), server.addr()).await.unwrap();
+    let globex_peer = start_client(tenant_device("globex", "cli", "peer1"), server.addr()).await.unwrap();
+    for client in [&acme_peer, &acme_sensor, &globex_peer] {
+        client.client_hello().await.unwrap();
+    }
+    let kitchen = RequestInfo { client_class: "sensor".to_string(), client_instance: "kitchen".to_string() };
+
+    assert!(acme_peer.client_request(kitchen.clone()).await.is_ok());
+    assert!(server.server().registry().lookup("acme/sensor-kitchen").is_some());
+    assert!(globex_peer.client_request(kitchen.clone()).await.is_err());
+    assert!(!globex_peer.query_status(kitchen.clone()).await.unwrap().is_online());
+    let status = acme_peer.query_status(kitchen).await.unwrap();
+    assert!(status.is_online());
+    assert_eq!(status.get_global_id(), "sensor-kitchen");
+    let listed = globex_peer.list_all_devices("cli", false).await.unwrap();
+    assert_eq!(listed.iter().map(|status| status.get_global_id()).collect::<Vec<_>>(), vec!["cli-peer1"]);
+
+    // a relay goes to the sender's own tenant
+    let (received, _) = tokio::join!(
+        tokio::time::timeout(Duration::from_secs(1), acme_peer.pkg_recv_from()),
+        async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            acme_sensor.pkg_send_to(server.addr(), b"acme only".to_vec(), Some(tenant_device("globex", "cli", "peer1"))).await.unwrap();
+        }
+    );
+    let (src, payload) = received.unwrap().unwrap();
+    assert_eq!(src.namespace, "acme");
+    assert_eq!(payload, b"acme only");
+}
+
+#[tokio::test]
+async fn test_tenant_credentials_and_limits() {
+    let server = TestServer::start_with(TestServer::builder().tenants(vec![tenant("acme", Some(1))])).await.unwrap();
+    let wrong = BasePkg { identity: "globex-secret".to_string(), ..tenant_device("acme", "cli", "peer1") };
+    let wrong = start_client(wrong, server.addr()).await.unwrap();
+    assert!(wrong.client_hello().await.is_err());
+    // the default namespace keeps the server identity
+    let plain = start_client(test_base_info("cli", "peer1"), server.addr()).await.unwrap();
+    plain.client_hello().await.unwrap();
+
+    let first = start_client(tenant_device("acme", "cli", "peer1"), server.addr()).await.unwrap();
+    first.client_hello().await.unwrap();
+    // registering again is no new device
+    first.client_hello().await.unwrap();
+    let second = start_client(tenant_device("acme", "cli", "peer2"), server.addr()).await.unwrap();
+    let asked_at = Instant::now();
+    let err = second.client_hello().await.unwrap_err();
+    assert!(err.to_string().contains("device limit"));
+    assert!(asked_at.elapsed() < Duration::from_millis(500));
+}
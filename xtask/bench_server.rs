@@ -0,0 +1,67 @@
+// Throughput benchmark for a running server on localhost.
+// All clients share 127.0.0.1, so start the server with the rate limits disabled:
+//   [rate_limit]
+//   per_ip_rate = 0.0
+//   per_device_rate = 0.0
+// usage: bench-server [server_address] [identity] [clients] [seconds]
+use std::{net::SocketAddr, sync::{atomic::{AtomicU64, Ordering}, Arc}, time::{Duration, Instant}};
+
+use tokio::net::UdpSocket;
+use tracing::{info, warn, Level};
+use up2p::core::{bincodec::BinCodec, uprotocol_pkg::ClientHelloPkg, Up2pMessage};
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt().with_max_level(Level::INFO).init();
+    let args = std::env::args().collect::<Vec<String>>();
+    let server_address: SocketAddr = args.get(1).map(String::as_str).unwrap_or("127.0.0.1:9008").parse()?;
+    let identity = args.get(2).cloned().unwrap_or_else(|| "bbb".to_string());
+    let clients: usize = args.get(3).map(|s| s.parse()).transpose()?.unwrap_or(64);
+    let duration = Duration::from_secs(args.get(4).map(|s| s.parse()).transpose()?.unwrap_or(10));
+    info!("Benchmarking {} with {} clients for {:?}", server_address, clients, duration);
+
+    let acked = Arc::new(AtomicU64::new(0));
+    let lost = Arc::new(AtomicU64::new(0));
+    let latency_us = Arc::new(AtomicU64::new(0));
+    let deadline = Instant::now() + duration;
+    let mut handles = Vec::with_capacity(clients);
+    for i in 0..clients {
+        let (acked, lost, latency_us, identity) = (acked.clone(), lost.clone(), latency_us.clone(), identity.clone());
+        handles.push(tokio::spawn(async move {
+            let udp_socket = UdpSocket::bind("127.0.0.1:0").await?;
+            udp_socket.connect(server_address).await?;
+            let hello = Up2pMessage::Hello(
+                ClientHelloPkg::new("bench", &format!("client{}", i), &identity, ClientHelloPkg::MSG_HELLO)
+            ).encode_to_vec()?;
+            let mut buf = [0u8; 1500];
+            while Instant::now() < deadline {
+                let sent_at = Instant::now();
+                udp_socket.send(&hello).await?;
+                match tokio::time::timeout(Duration::from_secs(1), udp_socket.recv(&mut buf)).await {
+                    Ok(Ok(_)) => {
+                        acked.fetch_add(1, Ordering::Relaxed);
+                        latency_us.fetch_add(sent_at.elapsed().as_micros() as u64, Ordering::Relaxed);
+                    }
+                    _ => {
+                        lost.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            }
+            anyhow::Ok(())
+        }));
+    }
+    for handle in handles {
+        if let Err(e) = handle.await? {
+            warn!("Client failed: {}", e);
+        }
+    }
+    let acked = acked.load(Ordering::Relaxed);
+    info!(
+        "{} hello acks in {:?}: {:.0} req/s, avg latency {} us, {} lost",
+        acked, duration,
+        acked as f64 / duration.as_secs_f64(),
+        latency_us.load(Ordering::Relaxed).checked_div(acked).unwrap_or(0),
+        lost.load(Ordering::Relaxed),
+    );
+    Ok(())
+}